@@ -0,0 +1,118 @@
+//! HTTP API для администраторов. Доступ по заголовку
+//! `Authorization: Bearer <ADMIN_TOKEN>`, без переменной API закрыт.
+
+use crate::chat::Context;
+use crate::graph_ql;
+use crate::storage::Revenue;
+use chrono::{NaiveDate, Utc};
+use juniper::http::GraphQLRequest;
+use warp::http::StatusCode;
+use warp::{reject, Filter, Rejection, Reply};
+
+#[derive(Debug)]
+pub struct Unauthorized;
+impl reject::Reject for Unauthorized {}
+
+#[derive(Debug)]
+pub struct Failed(pub String);
+impl reject::Reject for Failed {}
+
+/// Дата в пути не разбирается или вне календаря
+#[derive(Debug)]
+pub struct BadDate;
+impl reject::Reject for BadDate {}
+
+pub fn routes(
+    ctx: Context,
+    token: Option<String>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let photo = warp::path!("admin" / "photo" / u32 / String)
+        .and(crate::with_ctx(ctx.clone()))
+        .and_then(photo);
+    let payroll = warp::path!("admin" / "payroll" / String / String)
+        .and(crate::with_ctx(ctx.clone()))
+        .and_then(payroll);
+    let graphql = warp::path!("admin" / "graphql")
+        .and(warp::body::json())
+        .and(crate::with_ctx(ctx))
+        .map(graphql);
+    auth(token).and(
+        warp::get()
+            .and(photo.or(payroll))
+            .map(Reply::into_response)
+            .or(warp::post().and(graphql)),
+    )
+}
+
+fn auth(token: Option<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let ok = match (&token, header) {
+                (Some(token), Some(header)) => header == format!("Bearer {}", token),
+                _ => false,
+            };
+            async move {
+                if ok {
+                    Ok(())
+                } else {
+                    Err(reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// `GET /admin/photo/<id точки>/<ГГГГ-ММ-ДД>` - фото Z-отчета. Берется из
+/// `BLOB_DIR`, а если копии нет, скачивается из Telegram.
+async fn photo(corner_id: u32, date: String, ctx: Context) -> Result<impl Reply, Rejection> {
+    let rev = ctx
+        .db
+        .get_revenue(day(&date)?, corner_id)
+        .map_err(|e| reject::custom(Failed(e.to_string())))?;
+    let file_id = rev.and_then(|r| r.photo).ok_or_else(reject::not_found)?;
+    let data = match &ctx.blobs {
+        Some(blobs) => blobs.fetch(&ctx.bot, &file_id).await,
+        None => ctx.bot.download(&file_id).await,
+    }
+    .map_err(|e| reject::custom(Failed(e.to_string())))?;
+    // Telegram пережимает все фото в JPEG
+    Ok(warp::reply::with_header(data, "content-type", "image/jpeg"))
+}
+
+/// `GET /admin/payroll/<с ГГГГ-ММ-ДД>/<по ГГГГ-ММ-ДД>` - ведомость в CSV
+async fn payroll(from: String, to: String, ctx: Context) -> Result<impl Reply, Rejection> {
+    let (from, to) = (day(&from)?, day(&to)?);
+    let mut csv = Vec::new();
+    ctx.db
+        .export_payroll(from, to, &mut csv)
+        .map_err(|e| reject::custom(Failed(e.to_string())))?;
+    Ok(warp::reply::with_header(
+        csv,
+        "content-type",
+        "text/csv; charset=utf-8",
+    ))
+}
+
+/// Номер дня по `ГГГГ-ММ-ДД` из пути запроса
+fn day(s: &str) -> Result<u32, Rejection> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(Revenue::day)
+        .ok_or_else(|| reject::custom(BadDate))
+}
+
+/// `POST /admin/graphql` - запрос GraphQL, см. `graph_ql::Query` и `graph_ql::Mutation`
+fn graphql(request: GraphQLRequest, ctx: Context) -> warp::reply::Response {
+    let ctx = graph_ql::Context {
+        db: ctx.db,
+        now: Utc::now(),
+    };
+    let schema = graph_ql::schema();
+    let response = request.execute(&schema, &ctx);
+    let status = if response.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    warp::reply::with_status(warp::reply::json(&response), status).into_response()
+}
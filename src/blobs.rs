@@ -0,0 +1,77 @@
+//! Локальные копии фото из Telegram в `BLOB_DIR`. Имя файла - `file_id`.
+
+use crate::bot::Bot;
+use std::io;
+use std::path::PathBuf;
+use tokio::fs;
+
+#[derive(Clone)]
+pub struct BlobDir {
+    dir: PathBuf,
+}
+
+impl BlobDir {
+    pub fn new(dir: PathBuf) -> Self {
+        BlobDir { dir }
+    }
+
+    /// `file_id` приходит от Telegram, но в путь попадает только безопасный
+    fn path(&self, file_id: &str) -> Option<PathBuf> {
+        let valid = !file_id.is_empty()
+            && file_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        Some(self.dir.join(file_id)).filter(|_| valid)
+    }
+
+    /// Сохраненная копия, если она есть
+    pub async fn get(&self, file_id: &str) -> io::Result<Option<Vec<u8>>> {
+        let path = match self.path(file_id) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        match fs::read(path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Записывает через временный файл, чтобы не оставить обрезанную копию
+    pub async fn put(&self, file_id: &str, data: &[u8]) -> io::Result<()> {
+        let path = self
+            .path(file_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "bad file_id"))?;
+        fs::create_dir_all(&self.dir).await?;
+        let tmp = path.with_extension("part");
+        fs::write(&tmp, data).await?;
+        fs::rename(tmp, path).await
+    }
+
+    /// Копия из папки, а если ее нет - скачивает через getFile и сохраняет
+    pub async fn fetch(&self, bot: &Bot, file_id: &str) -> anyhow::Result<Vec<u8>> {
+        if let Some(data) = self.get(file_id).await? {
+            return Ok(data);
+        }
+        let data = bot.download(file_id).await?;
+        self.put(file_id, &data).await?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn blobs_on_disk() {
+        let dir = std::env::temp_dir().join(format!("cmbot-blobs-{}", rand::random::<u32>()));
+        let blobs = BlobDir::new(dir.clone());
+        assert!(blobs.path("../database").is_none());
+        assert_eq!(blobs.get("AgAD-x_1").await.unwrap(), None);
+        blobs.put("AgAD-x_1", b"jpeg").await.unwrap();
+        assert_eq!(blobs.get("AgAD-x_1").await.unwrap(), Some(b"jpeg".to_vec()));
+        assert!(blobs.put("a/b", b"jpeg").await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
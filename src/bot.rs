@@ -0,0 +1,90 @@
+//! Исходящие запросы к Bot API: то, что нельзя отправить ответом на вебхук
+
+use serde::Serialize;
+use telegram_types::bot::{methods, types};
+
+#[derive(Clone)]
+pub struct Bot {
+    client: reqwest::Client,
+    url: String,
+    file_url: String,
+}
+
+impl Bot {
+    pub fn new(token: &str) -> Self {
+        Bot {
+            client: reqwest::Client::new(),
+            url: format!("https://api.telegram.org/bot{}/", token),
+            file_url: format!("https://api.telegram.org/file/bot{}/", token),
+        }
+    }
+
+    pub async fn call<T: Serialize>(&self, method: &str, args: &T) -> anyhow::Result<()> {
+        let resp = self
+            .client
+            .post(&format!("{}{}", self.url, method))
+            .json(args)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("{} failed: {}", method, resp.text().await?);
+        }
+        Ok(())
+    }
+
+    pub async fn send_message(&self, chat_id: i64, text: String) -> anyhow::Result<()> {
+        let msg = methods::SendMessage::new(methods::ChatTarget::id(chat_id), text);
+        self.call("sendMessage", &msg).await
+    }
+
+    /// Загружает картинку PNG как фото с подписью
+    pub async fn send_png(
+        &self,
+        chat_id: i64,
+        png: Vec<u8>,
+        caption: String,
+    ) -> anyhow::Result<()> {
+        let photo = reqwest::multipart::Part::bytes(png)
+            .file_name("chart.png")
+            .mime_str("image/png")?;
+        let form = reqwest::multipart::Form::new()
+            .text("chat_id", chat_id.to_string())
+            .text("caption", caption)
+            .part("photo", photo);
+        let resp = self
+            .client
+            .post(&format!("{}sendPhoto", self.url))
+            .multipart(form)
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("sendPhoto failed: {}", resp.text().await?);
+        }
+        Ok(())
+    }
+
+    /// Скачивает файл по `file_id`: getFile дает путь, по нему сам файл
+    pub async fn download(&self, file_id: &str) -> anyhow::Result<Vec<u8>> {
+        let resp: methods::TelegramResult<types::File> = self
+            .client
+            .post(&format!("{}getFile", self.url))
+            .json(&serde_json::json!({ "file_id": file_id }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let path = match resp.result.and_then(|f| f.file_path) {
+            Some(path) => path,
+            None => anyhow::bail!("getFile failed: {}", resp.description.unwrap_or_default()),
+        };
+        let bytes = self
+            .client
+            .get(&format!("{}{}", self.file_url, path))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        Ok(bytes.to_vec())
+    }
+}
@@ -0,0 +1,145 @@
+//! Данные кнопок inline-клавиатур. В `callback_data` помещается не больше
+//! 64 байт, поэтому действие - буква с числами через точку, а в конце
+//! обрезанная HMAC-SHA256 от них и id чата. Подделанную кнопку или кнопку
+//! из чужого чата бот не примет.
+
+use sha2::{Digest, Sha256};
+
+/// Имя ключа подписи в `DataBase::secret`
+pub const KEY: &str = "callback";
+/// Байт подписи, которые попадают в кнопку
+const MAC_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Итоги за день
+    Day(u32),
+    /// Графики точки, 0 - всех видимых, за дни с `from` по сегодня
+    Chart { corner_id: u32, from: u32 },
+    /// Записать отложенную выручку
+    Confirm,
+    /// Отказаться от отложенной выручки
+    Cancel,
+}
+
+impl Action {
+    fn payload(&self) -> String {
+        match self {
+            Action::Day(day) => format!("d.{}", day),
+            Action::Chart { corner_id, from } => format!("c.{}.{}", corner_id, from),
+            Action::Confirm => "y".to_owned(),
+            Action::Cancel => "n".to_owned(),
+        }
+    }
+
+    fn parse(payload: &str) -> Option<Action> {
+        let mut parts = payload.split('.');
+        let tag = parts.next()?;
+        let nums: Option<Vec<u32>> = parts.map(|p| p.parse().ok()).collect();
+        Some(match (tag, nums?.as_slice()) {
+            ("d", [day]) => Action::Day(*day),
+            ("c", [corner_id, from]) => Action::Chart {
+                corner_id: *corner_id,
+                from: *from,
+            },
+            ("y", []) => Action::Confirm,
+            ("n", []) => Action::Cancel,
+            _ => return None,
+        })
+    }
+
+    /// `callback_data` кнопки для чата `chat_id`
+    pub fn encode(&self, key: &[u8], chat_id: i64) -> String {
+        let payload = self.payload();
+        let mac = hmac(key, &[&chat_id.to_be_bytes(), payload.as_bytes()]);
+        format!("{}:{}", payload, hex(&mac[..MAC_LEN]))
+    }
+
+    /// Действие из `callback_data`, если подпись верна для этого чата
+    pub fn decode(key: &[u8], chat_id: i64, data: &str) -> Option<Action> {
+        let (payload, mac) = match data.rfind(':') {
+            Some(i) => (&data[..i], &data[i + 1..]),
+            None => return None,
+        };
+        let expected = hmac(key, &[&chat_id.to_be_bytes(), payload.as_bytes()]);
+        let expected = hex(&expected[..MAC_LEN]);
+        // сравнение без раннего выхода, чтобы по времени ответа нельзя было подбирать
+        let diff = expected
+            .bytes()
+            .zip(mac.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 || mac.len() != expected.len() {
+            return None;
+        }
+        Action::parse(payload)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// HMAC-SHA256 (RFC 2104) от склеенных `parts`
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.iter().map(|b| b ^ 0x36).collect::<Vec<_>>());
+    for part in parts {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(block.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>());
+    outer.update(inner.finalize());
+    let mut res = [0u8; 32];
+    res.copy_from_slice(&outer.finalize());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_rfc4231() {
+        let mac = hmac(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        assert_eq!(
+            hex(&mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn signed_payloads() {
+        let key = [7u8; 32];
+        let actions = [
+            Action::Day(737_500),
+            Action::Chart {
+                corner_id: 12,
+                from: 737_494,
+            },
+            Action::Confirm,
+            Action::Cancel,
+        ];
+        for action in &actions {
+            let data = action.encode(&key, 42);
+            assert!(data.len() <= 64, "{}", data);
+            assert_eq!(Action::decode(&key, 42, &data), Some(*action));
+            // другой чат, другой ключ
+            assert_eq!(Action::decode(&key, 43, &data), None);
+            assert_eq!(Action::decode(&[8u8; 32], 42, &data), None);
+        }
+        let data = Action::Day(737_500).encode(&key, 42);
+        assert_eq!(data.len(), "d.737500:".len() + 2 * MAC_LEN);
+        // подмена дня при старой подписи
+        let forged = data.replacen("737500", "737501", 1);
+        assert_eq!(Action::decode(&key, 42, &forged), None);
+        assert_eq!(Action::decode(&key, 42, &data[..data.len() - 2]), None);
+        assert_eq!(Action::decode(&key, 42, "d.1"), None);
+        assert_eq!(Action::decode(&key, 42, ""), None);
+    }
+}
@@ -0,0 +1,325 @@
+//! Графики для отчетов в PNG. Рисуются прямо в памяти, подписи осей -
+//! встроенным пиксельным шрифтом, поэтому названия точек идут в подпись к фото.
+
+use crc32fast::Hasher;
+
+pub const WIDTH: u32 = 640;
+pub const HEIGHT: u32 = 360;
+
+type Rgb = [u8; 3];
+
+const BACKGROUND: Rgb = [255, 255, 255];
+const GRID: Rgb = [225, 225, 225];
+const AXIS: Rgb = [90, 90, 90];
+const FACT: Rgb = [52, 120, 200];
+const PLAN: Rgb = [200, 200, 200];
+const BEHIND: Rgb = [215, 90, 70];
+const AHEAD: Rgb = [70, 160, 90];
+
+/// Поля: слева под подписи шкалы, снизу под подписи столбцов
+const LEFT: u32 = 56;
+const RIGHT: u32 = 12;
+const TOP: u32 = 14;
+const BOTTOM: u32 = 26;
+/// Во сколько раз увеличен шрифт 3x5
+const SCALE: u32 = 2;
+
+/// Столбец графика. С планом рисуется серый столбец плана и на нем
+/// факт: зеленый, если план выполнен, иначе красный.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bar {
+    pub label: String,
+    pub value: u64,
+    pub plan: Option<u64>,
+}
+
+impl Bar {
+    pub fn new(label: impl ToString, value: u64) -> Self {
+        Bar {
+            label: label.to_string(),
+            value,
+            plan: None,
+        }
+    }
+}
+
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<Rgb>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        Canvas {
+            width,
+            height,
+            pixels: vec![BACKGROUND; (width * height) as usize],
+        }
+    }
+
+    /// Прямоугольник, обрезанный по краям холста
+    fn rect(&mut self, x: u32, y: u32, w: u32, h: u32, color: Rgb) {
+        for row in y..(y + h).min(self.height) {
+            for col in x..(x + w).min(self.width) {
+                self.pixels[(row * self.width + col) as usize] = color;
+            }
+        }
+    }
+
+    /// Строка из символов, которые есть в `glyph`, остальные пропускаются
+    fn text(&mut self, x: u32, y: u32, text: &str, color: Rgb) {
+        for (i, c) in text.chars().enumerate() {
+            let rows = match glyph(c) {
+                Some(rows) => rows,
+                None => continue,
+            };
+            let left = x + i as u32 * 4 * SCALE;
+            for (dy, bits) in rows.iter().enumerate() {
+                for dx in 0..3 {
+                    if bits & (0b100 >> dx) != 0 {
+                        let (px, py) = (left + dx * SCALE, y + dy as u32 * SCALE);
+                        self.rect(px, py, SCALE, SCALE, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// PNG без прозрачности, 8 бит на канал
+    pub fn png(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.height * (self.width * 3 + 1)) as usize);
+        for row in self.pixels.chunks(self.width as usize) {
+            // фильтр строки: без фильтра
+            raw.push(0);
+            for px in row {
+                raw.extend_from_slice(px);
+            }
+        }
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        // глубина 8, RGB, deflate, стандартный фильтр, без чересстрочности
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        chunk(
+            &mut png,
+            b"IDAT",
+            &miniz_oxide::deflate::compress_to_vec_zlib(&raw, 6),
+        );
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+/// Шрифт 3x5: строки сверху вниз, старший из трех битов - левый пиксель
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => return None,
+    })
+}
+
+/// Ширина строки в пикселях
+fn text_width(text: &str) -> u32 {
+    (text.chars().count() as u32 * 4).saturating_sub(1) * SCALE
+}
+
+/// Шаг шкалы вида 1, 2 или 5 на степень десяти, чтобы делений было не больше `ticks`
+fn nice_step(max: u64, ticks: u64) -> u64 {
+    let rough = max.div_ceil(ticks);
+    let mut power = 1;
+    loop {
+        for &m in &[1, 2, 5] {
+            if m * power >= rough {
+                return m * power;
+            }
+        }
+        power *= 10;
+    }
+}
+
+/// Подпись шкалы в тысячах: "12", "2.5"
+fn thousands(value: u64) -> String {
+    match value % 1000 {
+        0 => (value / 1000).to_string(),
+        rest => format!("{}.{}", value / 1000, rest / 100),
+    }
+}
+
+/// Столбчатая диаграмма, шкала в тысячах
+pub fn bars(bars: &[Bar]) -> Vec<u8> {
+    bar_canvas(bars).png()
+}
+
+fn bar_canvas(bars: &[Bar]) -> Canvas {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT);
+    let plot_w = WIDTH - LEFT - RIGHT;
+    let plot_h = HEIGHT - TOP - BOTTOM;
+    let bottom = TOP + plot_h;
+    let max = bars
+        .iter()
+        .map(|b| b.value.max(b.plan.unwrap_or(0)))
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let step = nice_step(max, 5);
+    let top = max.div_ceil(step) * step;
+    let height = |v: u64| (v * plot_h as u64 / top) as u32;
+
+    let mut tick = 0;
+    while tick <= top {
+        let y = bottom - height(tick);
+        canvas.rect(LEFT, y, plot_w, 1, GRID);
+        let label = thousands(tick);
+        let x = LEFT.saturating_sub(text_width(&label) + 6);
+        canvas.text(x, y.saturating_sub(5 * SCALE / 2), &label, AXIS);
+        tick += step;
+    }
+    canvas.rect(LEFT, TOP, 1, plot_h + 1, AXIS);
+    canvas.rect(LEFT, bottom, plot_w, 1, AXIS);
+    if bars.is_empty() {
+        return canvas;
+    }
+
+    let slot = plot_w / bars.len() as u32;
+    let bar_w = (slot * 7 / 10).max(1);
+    // подписывается каждый `every`-й столбец, чтобы подписи не слипались
+    let widest = bars.iter().map(|b| text_width(&b.label)).max().unwrap_or(0);
+    let every = ((widest + 4) / slot.max(1) + 1) as usize;
+    for (i, bar) in bars.iter().enumerate() {
+        let x = LEFT + 1 + i as u32 * slot + (slot - bar_w) / 2;
+        let color = match bar.plan {
+            Some(plan) => {
+                canvas.rect(x, bottom - height(plan), bar_w, height(plan), PLAN);
+                if bar.value >= plan {
+                    AHEAD
+                } else {
+                    BEHIND
+                }
+            }
+            None => FACT,
+        };
+        let (fact_x, fact_w) = match bar.plan {
+            Some(_) => (x + bar_w / 4, (bar_w / 2).max(1)),
+            None => (x, bar_w),
+        };
+        canvas.rect(
+            fact_x,
+            bottom - height(bar.value),
+            fact_w,
+            height(bar.value),
+            color,
+        );
+        if i % every == 0 {
+            let center = x + bar_w / 2;
+            let label_x = center.saturating_sub(text_width(&bar.label) / 2);
+            canvas.text(label_x, bottom + 8, &bar.label, AXIS);
+        }
+    }
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(canvas: &Canvas, x: u32, y: u32) -> Rgb {
+        canvas.pixels[(y * canvas.width + x) as usize]
+    }
+
+    /// Разбирает PNG на куски, проверяя их контрольные суммы
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut res = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let mut len = [0u8; 4];
+            len.copy_from_slice(&rest[..4]);
+            let len = u32::from_be_bytes(len) as usize;
+            let (body, crc) = rest[4..].split_at(4 + len);
+            let mut hasher = Hasher::new();
+            hasher.update(body);
+            assert_eq!(hasher.finalize().to_be_bytes(), crc[..4]);
+            let kind = String::from_utf8(body[..4].to_vec()).unwrap();
+            res.push((kind, body[4..].to_vec()));
+            rest = &crc[4..];
+        }
+        res
+    }
+
+    #[test]
+    fn valid_png() {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.rect(1, 1, 5, 5, FACT);
+        let png = canvas.png();
+        let chunks = chunks(&png);
+        let kinds: Vec<_> = chunks.iter().map(|c| c.0.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        let raw = miniz_oxide::inflate::decompress_to_vec_zlib(&chunks[1].1).unwrap();
+        let mut expected = vec![0];
+        expected.extend_from_slice(&[255; 9]);
+        expected.push(0);
+        expected.extend_from_slice(&[255, 255, 255]);
+        expected.extend_from_slice(&FACT);
+        expected.extend_from_slice(&FACT);
+        assert_eq!(raw, expected);
+    }
+
+    #[test]
+    fn scale() {
+        assert_eq!(nice_step(1, 5), 1);
+        assert_eq!(nice_step(7_300, 5), 2_000);
+        assert_eq!(nice_step(48_000, 5), 10_000);
+        assert_eq!(nice_step(101, 5), 50);
+        assert_eq!(thousands(20_000), "20");
+        assert_eq!(thousands(2_500), "2.5");
+        assert_eq!(text_width("12"), 14);
+    }
+
+    #[test]
+    fn plan_and_fact_colors() {
+        let canvas = bar_canvas(&[
+            Bar::new(1, 10_000),
+            Bar {
+                label: "2".to_owned(),
+                value: 3_000,
+                plan: Some(6_000),
+            },
+        ]);
+        let slot = (WIDTH - LEFT - RIGHT) / 2;
+        let center = |i: u32| LEFT + 1 + i * slot + slot / 2;
+        let y = HEIGHT - BOTTOM - 10;
+        assert_eq!(pixel(&canvas, center(0), y), FACT);
+        assert_eq!(pixel(&canvas, center(1), y), BEHIND);
+        // выше факта виден план, выше плана - фон
+        let above = HEIGHT - BOTTOM - (HEIGHT - TOP - BOTTOM) / 2;
+        assert_eq!(pixel(&canvas, center(1) - slot / 4, above), PLAN);
+        assert_eq!(pixel(&canvas, center(1), TOP + 1), BACKGROUND);
+        assert!(chunks(&bars(&[])).len() == 3);
+    }
+}
@@ -1,14 +1,77 @@
-use crate::storage::{self, DataBase};
-use core::time::Duration;
+use crate::blobs::BlobDir;
+use crate::bot::Bot;
+use crate::callback::{self, Action};
+use crate::dates;
+use crate::locale::{Lang, Msg};
+use crate::money::Money;
+use crate::report;
+use crate::storage::{
+    self, Anomaly, DataBase, Expense, PayScheme, Payment, Revenue, Role, Stats, TargetPeriod,
+};
+use chrono::{TimeZone, Utc};
 use serde::{Deserialize, Serialize};
-use std::future::Future;
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
 use telegram_types::bot::methods;
 use telegram_types::bot::types;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Методы Bot API, которыми бот отвечает на вебхук или вызывает через `Bot`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum ApiMethod {
     SendMessage,
+    SendPhoto,
+    EditMessageText,
+    DeleteMessage,
+    AnswerCallbackQuery,
+    LeaveChat,
+    SetMyCommands,
+}
+
+impl ApiMethod {
+    /// Имя метода для адреса запроса, совпадает с сериализованным
+    pub fn name(self) -> &'static str {
+        match self {
+            ApiMethod::SendMessage => "sendMessage",
+            ApiMethod::SendPhoto => "sendPhoto",
+            ApiMethod::EditMessageText => "editMessageText",
+            ApiMethod::DeleteMessage => "deleteMessage",
+            ApiMethod::AnswerCallbackQuery => "answerCallbackQuery",
+            ApiMethod::LeaveChat => "leaveChat",
+            ApiMethod::SetMyCommands => "setMyCommands",
+        }
+    }
+}
+
+/// Ответ на нажатие кнопки, в telegram_types его нет
+#[derive(Serialize, Debug)]
+pub(crate) struct AnswerCallbackQuery {
+    pub callback_query_id: String,
+    /// Всплывающее уведомление
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<Cow<'static, str>>,
+}
+
+/// Выход из группы, в telegram_types его нет
+#[derive(Serialize, Debug)]
+pub(crate) struct LeaveChat {
+    pub chat_id: methods::ChatTarget<'static>,
+}
+
+/// Команда в меню бота
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct BotCommand {
+    pub command: &'static str,
+    pub description: &'static str,
+}
+
+/// Меню команд бота, в telegram_types его нет
+#[derive(Serialize, Debug)]
+pub(crate) struct SetMyCommands {
+    pub commands: Vec<BotCommand>,
+    /// Меню для пользователей с этим языком, без него - для всех остальных
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language_code: Option<&'static str>,
 }
 
 #[derive(Serialize, Debug)]
@@ -18,17 +81,88 @@ pub(crate) struct UpdateReply<T: Serialize> {
     pub args: T,
 }
 
+/// Ответ на вебхук. Сериализуется как `UpdateReply`: имя метода и его аргументы.
+#[derive(Debug)]
+pub(crate) enum Reply {
+    Message(methods::SendMessage<'static>),
+    Photo(methods::SendPhoto<'static>),
+    Edit(methods::EditMessageText<'static>),
+    Delete(methods::DeleteMessage<'static>),
+    Answer(AnswerCallbackQuery),
+    LeaveChat(LeaveChat),
+    Commands(SetMyCommands),
+}
+
+impl Reply {
+    pub fn method(&self) -> ApiMethod {
+        match self {
+            Reply::Message(_) => ApiMethod::SendMessage,
+            Reply::Photo(_) => ApiMethod::SendPhoto,
+            Reply::Edit(_) => ApiMethod::EditMessageText,
+            Reply::Delete(_) => ApiMethod::DeleteMessage,
+            Reply::Answer(_) => ApiMethod::AnswerCallbackQuery,
+            Reply::LeaveChat(_) => ApiMethod::LeaveChat,
+            Reply::Commands(_) => ApiMethod::SetMyCommands,
+        }
+    }
+
+    fn json(self) -> warp::reply::Json {
+        warp::reply::json(&self)
+    }
+}
+
+impl Serialize for Reply {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let method = self.method();
+        match self {
+            Reply::Message(args) => UpdateReply { method, args }.serialize(s),
+            Reply::Photo(args) => UpdateReply { method, args }.serialize(s),
+            Reply::Edit(args) => UpdateReply { method, args }.serialize(s),
+            Reply::Delete(args) => UpdateReply { method, args }.serialize(s),
+            Reply::Answer(args) => UpdateReply { method, args }.serialize(s),
+            Reply::LeaveChat(args) => UpdateReply { method, args }.serialize(s),
+            Reply::Commands(args) => UpdateReply { method, args }.serialize(s),
+        }
+    }
+}
+
+/// Что отправляется через `Bot` после ответа на вебхук
+#[derive(Debug)]
+pub(crate) enum Outgoing {
+    Text(String),
+    /// PNG и подпись к нему
+    Png(Vec<u8>, String),
+    /// Любой метод, например правка или удаление сообщения бота
+    Method(Reply),
+}
+
+/// Сообщения, которые нельзя отправить ответом на вебхук: другим чатам
+/// или с загрузкой файлов
+type Outbox = Vec<(i64, Outgoing)>;
+
+/// Все, что нужно обработчику вебхука
+#[derive(Clone)]
+pub struct Context {
+    pub db: DataBase,
+    pub bot: Bot,
+    /// Куда копировать фото, если задан `BLOB_DIR`
+    pub blobs: Option<BlobDir>,
+}
+
 #[derive(Debug)]
 pub enum HandleError {
     NotMessage,
 }
 impl warp::reject::Reject for HandleError {}
 
-fn main_handler(db: DataBase, update: types::Update) -> Result<warp::reply::Json, warp::Rejection> {
-    let msg = if let types::UpdateContent::Message(m) = update.content {
-        m
-    } else {
-        return Err(warp::reject::custom(HandleError::NotMessage));
+pub fn main_handler(
+    ctx: Context,
+    update: types::Update,
+) -> Result<warp::reply::Json, warp::Rejection> {
+    let msg = match update.content {
+        types::UpdateContent::Message(m) => m,
+        types::UpdateContent::CallbackQuery(query) => return Ok(callback_query(ctx, query)),
+        _ => return Err(warp::reject::custom(HandleError::NotMessage)),
     };
 
     let name: String = match msg.chat.kind {
@@ -37,40 +171,1954 @@ fn main_handler(db: DataBase, update: types::Update) -> Result<warp::reply::Json
             first_name: s,
             last_name: _,
         } => s,
-        _ => return Ok(leave_chat(msg.chat.id.0)),
+        _ => return Ok(leave_chat(msg.chat.id.0).json()),
     };
     let chat_id = msg.chat.id.0;
-    Ok(warp::reply::json(&match db.get_chat(chat_id) {
-        None if msg.text.is_none() => send_msg(chat_id, GUEST_MSG),
-        None => match db.register(msg.text.unwrap(), name) {
-            false => send_msg(chat_id, FAIL_CODE),
-            true => send_msg(chat_id, HELP),
+    // у фото текст приходит в подписи, самый большой размер - последний
+    let text = msg.text.or(msg.caption);
+    let photo = msg.photo.last().map(|p| p.file_id.0.clone());
+    if let (Some(blobs), Some(file_id)) = (&ctx.blobs, &photo) {
+        let active = matches!(ctx.db.get_chat(chat_id), Ok(Some(c)) if c.is_active);
+        if active {
+            tokio::spawn(save_photo(ctx.bot.clone(), blobs.clone(), file_id.clone()));
+        }
+    }
+    let code = msg.from.as_ref().and_then(|u| u.language_code.as_deref());
+    let lang = Lang::from_code(code);
+    let mut out = Outbox::new();
+    let reply = handle_msg(&ctx.db, chat_id, name, lang, text, photo, &mut out);
+    let reply = reply.unwrap_or_else(|e| {
+        eprintln!("ERROR: chat {}: {}", chat_id, e);
+        match e {
+            storage::Error::Conflict(_) => send_msg(chat_id, lang.text(Msg::DbConflict)),
+            _ => send_msg(chat_id, lang.text(Msg::DbError)),
+        }
+    });
+    if !out.is_empty() {
+        tokio::spawn(send_outbox(ctx.bot.clone(), out));
+    }
+    Ok(reply.json())
+}
+
+/// Нажатие кнопки под сообщением бота
+fn callback_query(ctx: Context, query: types::CallbackQuery) -> warp::reply::Json {
+    let types::CallbackQuery {
+        id,
+        from,
+        message,
+        data,
+        ..
+    } = query;
+    let lang = Lang::from_code(from.language_code.as_deref());
+    let message = match message {
+        Some(message) => message,
+        None => return answer(id, None).json(),
+    };
+    let chat_id = message.chat.id.0;
+    let data = data.unwrap_or_default();
+    let mut out = Outbox::new();
+    let message_id = message.message_id.0;
+    let reply = handle_callback(
+        &ctx.db,
+        chat_id,
+        lang,
+        message_id,
+        id.clone(),
+        &data,
+        &mut out,
+    );
+    let reply = reply.unwrap_or_else(|e| {
+        eprintln!("ERROR: chat {}: {}", chat_id, e);
+        match e {
+            storage::Error::Conflict(_) => answer(id, Some(lang.text(Msg::DbConflict))),
+            _ => answer(id, Some(lang.text(Msg::DbError))),
+        }
+    });
+    if !out.is_empty() {
+        tokio::spawn(send_outbox(ctx.bot.clone(), out));
+    }
+    reply.json()
+}
+
+async fn send_outbox(bot: Bot, out: Outbox) {
+    for (chat_id, msg) in out {
+        let res = match msg {
+            Outgoing::Text(text) => bot.send_message(chat_id, text).await,
+            Outgoing::Png(png, caption) => bot.send_png(chat_id, png, caption).await,
+            Outgoing::Method(reply) => bot.call(reply.method().name(), &reply).await,
+        };
+        if let Err(e) = res {
+            eprintln!("ERROR: message to {}: {}", chat_id, e);
+        }
+    }
+}
+
+async fn save_photo(bot: Bot, blobs: BlobDir, file_id: String) {
+    if let Err(e) = blobs.fetch(&bot, &file_id).await {
+        eprintln!("ERROR: photo {}: {}", file_id, e);
+    }
+}
+
+/// `lang` - язык из настроек Telegram, выбранный в /lang важнее
+fn handle_msg(
+    db: &DataBase,
+    chat_id: i64,
+    name: String,
+    lang: Lang,
+    text: Option<String>,
+    photo: Option<String>,
+    out: &mut Outbox,
+) -> storage::Result<Reply> {
+    Ok(match db.get_chat(chat_id)? {
+        None if text.is_none() => send_msg(chat_id, lang.text(Msg::Guest)),
+        None => match db.register(chat_id, &text.unwrap(), name)? {
+            false => send_msg(chat_id, lang.text(Msg::FailCode)),
+            true => send_msg(chat_id, lang.text(Msg::Help)),
+        },
+
+        Some(chat) if !chat.is_active => {
+            send_msg(chat_id, chat.lang.unwrap_or(lang).text(Msg::Inactive))
+        }
+        Some(chat) => match (text, photo) {
+            (Some(text), photo) => com_handler(db, chat_id, chat, lang, text, photo, out)?,
+            (None, Some(photo)) => {
+                let lang = chat.lang.unwrap_or(lang);
+                send_msg(chat_id, attach_photo(db, &chat, lang, &photo)?)
+            }
+            (None, None) => send_msg(chat_id, chat.lang.unwrap_or(lang).text(Msg::Help)),
+        },
+    })
+}
+
+fn send_msg<T: Into<Cow<'static, str>>>(chat_id: i64, text: T) -> Reply {
+    Reply::Message(methods::SendMessage::new(
+        methods::ChatTarget::id(chat_id),
+        text,
+    ))
+}
+
+/// Сообщение с inline-клавиатурой
+fn send_buttons<T: Into<Cow<'static, str>>>(
+    chat_id: i64,
+    text: T,
+    keyboard: types::InlineKeyboardMarkup,
+) -> Reply {
+    let msg = methods::SendMessage::new(methods::ChatTarget::id(chat_id), text);
+    Reply::Message(msg.reply_markup(methods::ReplyMarkup::InlineKeyboard(keyboard)))
+}
+
+fn answer(query_id: String, text: Option<&'static str>) -> Reply {
+    Reply::Answer(AnswerCallbackQuery {
+        callback_query_id: query_id,
+        text: text.map(Cow::from),
+    })
+}
+
+/// Клавиатура из рядов кнопок с подписанными для чата действиями
+fn keyboard(
+    db: &DataBase,
+    chat_id: i64,
+    rows: Vec<Vec<(String, Action)>>,
+) -> storage::Result<types::InlineKeyboardMarkup> {
+    let key = db.secret(callback::KEY)?;
+    let inline_keyboard = rows
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|(text, action)| types::InlineKeyboardButton {
+                    text,
+                    pressed: types::InlineKeyboardButtonPressed::CallbackData(
+                        action.encode(&key, chat_id),
+                    ),
+                })
+                .collect()
+        })
+        .collect();
+    Ok(types::InlineKeyboardMarkup { inline_keyboard })
+}
+
+/// Переход к предыдущему и, если это не сегодня, к следующему дню
+fn day_keyboard(
+    db: &DataBase,
+    chat_id: i64,
+    lang: Lang,
+    day: u32,
+    today: u32,
+) -> storage::Result<types::InlineKeyboardMarkup> {
+    let date = |day: u32| Revenue::date_of(day).map(|date| lang.day_month(date));
+    let mut row = Vec::new();
+    if let Some(prev) = day
+        .checked_sub(1)
+        .filter(|&prev| Revenue::date(prev).is_some())
+    {
+        row.push((format!("← {}", date(prev)?), Action::Day(prev)));
+    }
+    if day < today {
+        row.push((format!("{} →", date(day + 1)?), Action::Day(day + 1)));
+    }
+    keyboard(db, chat_id, vec![row])
+}
+
+/// Меню по умолчанию на русском и отдельное для английского интерфейса
+fn set_commands(chat_id: i64, chat: &storage::Chat, lang: Lang, out: &mut Outbox) -> &'static str {
+    if let Role::Staff = chat.role {
+        return lang.text(Msg::NotAdmin);
+    }
+    for &menu_lang in &[Lang::Ru, Lang::En] {
+        let commands = menu_lang
+            .commands()
+            .map(|(command, description)| BotCommand {
+                command,
+                description,
+            })
+            .collect();
+        let language_code = Some(menu_lang.code()).filter(|_| menu_lang != Lang::Ru);
+        let reply = Reply::Commands(SetMyCommands {
+            commands,
+            language_code,
+        });
+        out.push((chat_id, Outgoing::Method(reply)));
+    }
+    lang.text(Msg::CommandsSet)
+}
+
+/// `/lang ru|en|auto`, auto возвращает язык из настроек Telegram
+fn set_lang(
+    db: &DataBase,
+    chat_id: i64,
+    user_lang: Lang,
+    lang: Lang,
+    args: Vec<&str>,
+) -> storage::Result<&'static str> {
+    let chosen = match args.as_slice() {
+        ["auto"] => None,
+        [code] => match Lang::parse(code) {
+            Some(chosen) => Some(chosen),
+            None => return Ok(lang.text(Msg::LangUsage)),
         },
+        _ => return Ok(lang.text(Msg::LangUsage)),
+    };
+    db.set_chat_lang(chat_id, chosen)?;
+    Ok(chosen.unwrap_or(user_lang).text(Msg::LangSet))
+}
+
+fn leave_chat(chat_id: i64) -> Reply {
+    Reply::LeaveChat(LeaveChat {
+        chat_id: methods::ChatTarget::id(chat_id),
+    })
+}
 
-        Some(chat) if !chat.is_active => send_msg(chat_id, INACTIVE),
-        Some(_) if msg.text.is_none() => send_msg(chat_id, HELP),
-        Some(chat) => com_handler(msg.text.unwrap(), chat, db.clone()),
-    }))
+fn com_handler(
+    db: &DataBase,
+    chat_id: i64,
+    chat: storage::Chat,
+    user_lang: Lang,
+    com: String,
+    photo: Option<String>,
+    out: &mut Outbox,
+) -> storage::Result<Reply> {
+    let lang = chat.lang.unwrap_or(user_lang);
+    let today = Revenue::day_of(dates::today(db.chat_tz(&chat)?))?;
+    let mut words = com.split_whitespace();
+    Ok(match words.next() {
+        Some("/day") => {
+            let mut args: Vec<&str> = words.collect();
+            let day = pop_date(&mut args, today).unwrap_or(today);
+            if !args.is_empty() || day > today {
+                send_msg(chat_id, lang.text(Msg::DayUsage))
+            } else {
+                send_buttons(
+                    chat_id,
+                    report::day_summary(db, &chat, lang, day)?,
+                    day_keyboard(db, chat_id, lang, day, today)?,
+                )
+            }
+        }
+        Some("/month") => send_msg(
+            chat_id,
+            report::month_summary(db, &chat, lang, Stats::month(today)?)?,
+        ),
+        Some("/target") => send_msg(
+            chat_id,
+            set_target(db, &chat, lang, words.collect(), today)?,
+        ),
+        Some("/methods") => send_msg(chat_id, list_methods(db, lang)?),
+        Some("/method") => send_msg(chat_id, edit_method(db, &chat, lang, words.collect())?),
+        Some("/expense") => {
+            let reply = add_expense(db, &chat, lang, words.collect(), photo, today)?;
+            send_msg(chat_id, reply)
+        }
+        Some("/expenses") => send_msg(chat_id, list_expenses(db, &chat, lang, today)?),
+        Some("/photo") => show_photo(db, chat_id, &chat, lang, words.collect(), today)?,
+        Some("/zreport") => send_msg(chat_id, require_photo(db, &chat, lang, words.collect())?),
+        Some(com @ "/open") | Some(com @ "/close") | Some(com @ "/shift") => send_msg(
+            chat_id,
+            shift(db, chat_id, &chat, lang, com, words.collect())?,
+        ),
+        Some("/confirm") => send_msg(chat_id, confirm_revenue(db, chat_id, lang, out)?),
+        Some("/anomaly") => send_msg(chat_id, set_anomaly(db, &chat, lang, words.collect())?),
+        Some("/tz") => send_msg(chat_id, set_timezone(db, &chat, lang, words.collect())?),
+        Some("/salary") => send_msg(chat_id, salary(db, chat_id, &chat, lang, today)?),
+        Some("/pay") => send_msg(chat_id, set_pay(db, &chat, lang, words.collect())?),
+        Some("/chart") => charts(db, chat_id, &chat, lang, words.collect(), today, out)?,
+        Some("/forecast") => send_msg(chat_id, report::forecast_summary(db, &chat, lang, today)?),
+        Some("/commands") => send_msg(chat_id, set_commands(chat_id, &chat, lang, out)),
+        Some("/lang") => send_msg(
+            chat_id,
+            set_lang(db, chat_id, user_lang, lang, words.collect())?,
+        ),
+        Some("/staff") => match chat.role {
+            Role::Staff => send_msg(chat_id, lang.text(Msg::NotAdmin)),
+            _ => send_msg(
+                chat_id,
+                report::staff_summary(db, &chat, lang, Stats::month(today)?)?,
+            ),
+        },
+        _ => match parse_revenue(db, &com, today)? {
+            Some((day, payments)) => {
+                submit_revenue(db, chat_id, &chat, lang, payments, photo, day)?
+            }
+            None => send_msg(chat_id, lang.text(Msg::Help)),
+        },
+    })
 }
 
-fn send_msg(chat_id: i64, text: &'static str) -> methods::SendMessage<'static> {
-    methods::SendMessage::new(methods::ChatTarget::id(chat_id), text)
+/// Выручка из сообщения. Перед суммой можно указать прошедший день:
+/// "вчера 12 000", "15.03 нал 5000, карта 7000".
+fn parse_revenue(
+    db: &DataBase,
+    text: &str,
+    today: u32,
+) -> storage::Result<Option<(u32, Vec<Payment>)>> {
+    if let Some(payments) = parse_payments(db, text)? {
+        return Ok(Some((today, payments)));
+    }
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let today_date = Revenue::date_of(today)?;
+    for n in 1..words.len().min(4) {
+        let day = match dates::parse(&words[..n].join(" "), today_date).and_then(Revenue::day) {
+            Some(day) => day,
+            None => continue,
+        };
+        if day > today {
+            return Ok(None);
+        }
+        if let Some(payments) = parse_payments(db, &words[n..].join(" "))? {
+            return Ok(Some((day, payments)));
+        }
+    }
+    Ok(None)
 }
 
-const GUEST_MSG: &'static str = "Ведите код приглашения";
-const FAIL_CODE: &'static str = "Код не найден.
-    \nВозможно у вас опечатка, либо срок действия кода истек.
-    \nПроверьте правильность написания и попробуйте еще раз";
-const INACTIVE: &'static str = "Ваш профиль был заблокирован администратором";
-const HELP: &'static str = "Помощь";
+/// Сумма ("12 000", "12 345,50", "12к") или разбивка по способам оплаты
+/// ("нал 5000, карта 7 000,50"). Просто сумма дает пустую разбивку,
+/// т.е. способ "не указан".
+fn parse_payments(db: &DataBase, text: &str) -> storage::Result<Option<Vec<Payment>>> {
+    if let Some(amount) = Money::parse(text) {
+        return Ok(Some(vec![Payment::new(storage::UNSPECIFIED, amount)]));
+    }
+    // запятая между цифрами - копейки, иначе разделитель
+    let chars: Vec<char> = text.chars().collect();
+    let text: String = chars
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            let digit = |j: usize| matches!(chars.get(j), Some(c) if c.is_ascii_digit());
+            match c {
+                ',' if i > 0 && digit(i - 1) && digit(i + 1) => c,
+                ',' | ';' | '+' => ' ',
+                c => c,
+            }
+        })
+        .collect();
+    let mut parts: Vec<(u32, String)> = Vec::new();
+    for word in text.split_whitespace() {
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            match parts.last_mut() {
+                // "7 000" - продолжение той же суммы
+                Some((_, number)) => number.push_str(word),
+                None => return Ok(None),
+            }
+        } else {
+            // перед следующим способом должна быть сумма
+            if let Some((_, number)) = parts.last() {
+                if number.is_empty() {
+                    return Ok(None);
+                }
+            }
+            match db.find_payment_method(word)? {
+                Some(m) if !m.archived => parts.push((m.id, String::new())),
+                _ => return Ok(None),
+            }
+        }
+    }
+    let payments: Option<Vec<Payment>> = parts
+        .iter()
+        .map(|(method, number)| Money::parse(number).map(|amount| Payment::new(*method, amount)))
+        .collect();
+    Ok(payments.filter(|p| !p.is_empty()))
+}
 
-fn leave_chat(chat_id: i64) -> warp::reply::Json {
-    warp::reply::json(&serde_json::json!({
-        "method": "leaveChat",
-        "chat_id": chat_id
-    }))
+/// Выручка точки сотрудника за сегодня, с планом если он задан.
+/// Фото Z-отчета, присланное раньше, сохраняется при исправлении суммы.
+/// Выручка записывается на того, кто на смене, а без смен - на отправителя.
+fn submit_revenue(
+    db: &DataBase,
+    chat_id: i64,
+    chat: &storage::Chat,
+    lang: Lang,
+    mut payments: Vec<Payment>,
+    photo: Option<String>,
+    day: u32,
+) -> storage::Result<Reply> {
+    let corner = match db.get_corner(chat.corner_id)? {
+        Some(corner) => corner,
+        None => return Ok(send_msg(chat_id, lang.text(Msg::NoCorner))),
+    };
+    let amount = payments
+        .iter()
+        .try_fold(Money::ZERO, |sum, p| sum.checked_add(p.amount));
+    let amount = match amount {
+        Some(amount) => amount,
+        None => return Ok(send_msg(chat_id, lang.text(Msg::Help))),
+    };
+    payments.retain(|p| p.method != storage::UNSPECIFIED);
+    let photo = match photo {
+        Some(photo) => Some(photo),
+        None => db.get_revenue(day, corner.id)?.and_then(|r| r.photo),
+    };
+    let on_shift = db.on_shift(corner.id)?;
+    let staff = match on_shift.first() {
+        Some(first) if on_shift.iter().all(|s| s.chat_id != chat_id) => first.chat_id,
+        _ => chat_id,
+    };
+    let rev = Revenue {
+        corner_id: chat.corner_id,
+        date: day,
+        amount,
+        post_datetime: chrono::Utc::now().timestamp() as u32,
+        comment: None,
+        payments,
+        photo,
+        staff: Some(staff),
+    };
+    if let Some(anomaly) = db.check_revenue(&corner, day, amount)? {
+        db.put_pending(chat_id, &rev)?;
+        let text = match anomaly {
+            Anomaly::High { usual } => lang.unusual_revenue(amount, usual, true),
+            Anomaly::Low { usual } => lang.unusual_revenue(amount, usual, false),
+        };
+        let buttons = vec![vec![
+            (lang.text(Msg::AllRight).to_owned(), Action::Confirm),
+            (lang.text(Msg::Cancel).to_owned(), Action::Cancel),
+        ]];
+        return Ok(send_buttons(chat_id, text, keyboard(db, chat_id, buttons)?));
+    }
+    db.take_pending(chat_id)?;
+    Ok(send_msg(
+        chat_id,
+        save_revenue(db, chat_id, lang, &corner, rev)?,
+    ))
 }
 
-fn com_handler(com: String, chat: storage::Chat, db: DataBase) -> methods::SendMessage<'static> {
-    todo!();
+/// Записывает выручку и собирает ответ сотруднику
+fn save_revenue(
+    db: &DataBase,
+    chat_id: i64,
+    lang: Lang,
+    corner: &storage::Corner,
+    rev: Revenue,
+) -> storage::Result<String> {
+    let day = rev.date;
+    let old = db.put_revenue(&rev)?;
+    let mut reply = lang.revenue_accepted(Revenue::date_of(day)?, rev.amount);
+    if !rev.payments.is_empty() {
+        let names = db.payment_method_names()?;
+        let parts = rev.payments.iter().map(|p| (p.method, p.amount));
+        reply += &format!(" ({})", storage::format_breakdown(&names, lang, parts));
+    }
+    if let Some(old) = old {
+        reply += &lang.was(old.amount);
+    }
+    if let Some(staff) = rev.staff.filter(|s| *s != chat_id) {
+        if let Some(on_shift) = db.get_chat(staff)? {
+            reply += &lang.whose_shift(&on_shift.name);
+        }
+    }
+    if rev.missing_photo(corner) {
+        reply += "\n";
+        reply += lang.text(Msg::SendPhoto);
+    }
+    if let Some(pf) = db.plan_fact(corner.id, day)? {
+        reply += "\n";
+        reply += &report::plan_line(&pf, lang);
+    }
+    Ok(reply)
+}
+
+/// `/confirm` записывает отложенную необычную выручку и предупреждает
+/// администраторов точки
+fn confirm_revenue(
+    db: &DataBase,
+    chat_id: i64,
+    lang: Lang,
+    out: &mut Outbox,
+) -> storage::Result<String> {
+    // отложенная выручка забирается только перед записью, чтобы
+    // ошибка раньше не потеряла ее
+    let rev = match db.get_pending(chat_id)? {
+        Some(rev) => rev,
+        None => return Ok(lang.text(Msg::NothingPending).to_owned()),
+    };
+    let corner = match db.get_corner(rev.corner_id)? {
+        Some(corner) => corner,
+        None => return Ok(lang.text(Msg::NoCorner).to_owned()),
+    };
+    let usual = db.usual_revenue(corner.id, rev.date)?;
+    let date = Revenue::date_of(rev.date)?;
+    let staff = db.get_chat(chat_id)?.map(|s| s.name);
+    // у каждого администратора свой язык, а из Telegram известен только
+    // язык отправившего
+    let alert = |lang: Lang| {
+        let mut alert = lang.unusual_confirmed(&corner.name, rev.amount, date);
+        if let Some(usual) = usual {
+            alert += &lang.usually(usual);
+        }
+        if let Some(staff) = &staff {
+            alert += &lang.sent_by(staff);
+        }
+        alert
+    };
+    let mut alerts = Vec::new();
+    for (id, admin) in db.get_chats()? {
+        if let (true, Role::Admin(_)) = (admin.is_active, admin.role) {
+            if db.can_manage(&admin, corner.id)? {
+                alerts.push((id, Outgoing::Text(alert(admin.lang.unwrap_or(lang)))));
+            }
+        }
+    }
+    // пока считали, выручку могли подтвердить или отменить
+    if !db.take_pending_if(chat_id, &rev)? {
+        return Ok(lang.text(Msg::NothingPending).to_owned());
+    }
+    let reply = save_revenue(db, chat_id, lang, &corner, rev)?;
+    out.extend(alerts);
+    Ok(reply)
+}
+
+/// `/anomaly <точка> <кратность|off>`: во сколько раз выручка может
+/// отличаться от обычной без подтверждения
+fn set_anomaly(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    mut args: Vec<&str>,
+) -> storage::Result<String> {
+    if let Role::Staff = chat.role {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    // кратность хранится в десятых, а parse_percent дает сотые
+    let ratio = match args.pop() {
+        Some("off") => Some(0),
+        Some(v) => parse_percent(v).map(|r| r / 10).filter(|r| *r > 10),
+        None => None,
+    };
+    let (ratio, corner) = match (ratio, db.find_corner(&args.join(" "))?) {
+        (Some(ratio), Some(corner)) => (ratio, corner),
+        _ => return Ok(lang.text(Msg::AnomalyUsage).to_owned()),
+    };
+    if !db.can_manage(chat, corner.id)? {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    db.set_anomaly_ratio(corner.id, ratio)?;
+    Ok(lang.anomaly_set(&corner.name, ratio))
+}
+
+/// `/tz <точка> [пояс]`: часовой пояс IANA, по которому у точки
+/// начинается день. Без пояса показывает текущий.
+fn set_timezone(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    mut args: Vec<&str>,
+) -> storage::Result<String> {
+    if let Role::Staff = chat.role {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    let tz = args.last().and_then(|name| dates::zone(name));
+    if tz.is_some() {
+        args.pop();
+    }
+    let corner = match db.find_corner(&args.join(" "))? {
+        Some(corner) => corner,
+        None => return Ok(lang.text(Msg::TzUsage).to_owned()),
+    };
+    if !db.can_manage(chat, corner.id)? {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    let corner = match tz {
+        Some(tz) => db.set_corner_timezone(corner.id, tz.name())?,
+        None => corner,
+    };
+    let now = Utc::now().with_timezone(&corner.tz()).format("%H:%M");
+    Ok(lang.timezone(&corner.name, &corner.timezone, &now.to_string()))
+}
+
+/// `/chart [week|month] [точка]`: графики за последние 7 дней или
+/// с начала месяца. Картинки уходят через `out`, а к ответу для нескольких
+/// точек прикладываются кнопки, чтобы посмотреть одну.
+fn charts(
+    db: &DataBase,
+    chat_id: i64,
+    chat: &storage::Chat,
+    lang: Lang,
+    mut args: Vec<&str>,
+    today: u32,
+    out: &mut Outbox,
+) -> storage::Result<Reply> {
+    let from = match args.first().copied() {
+        Some("month") | Some("месяц") => {
+            args.remove(0);
+            Stats::first_day(Stats::month(today)?)?
+        }
+        Some("week") | Some("неделя") => {
+            args.remove(0);
+            today.saturating_sub(6)
+        }
+        _ => today.saturating_sub(6),
+    };
+    let only = match args.join(" ").as_str() {
+        "" => None,
+        name => match db.find_corner(name)? {
+            Some(corner) if db.visible_corners(chat)?.iter().any(|c| c.id == corner.id) => {
+                Some(corner.id)
+            }
+            _ => return Ok(send_msg(chat_id, lang.text(Msg::ChartUsage))),
+        },
+    };
+    let text = push_charts(db, chat_id, chat, lang, only, from..=today, out)?;
+    let mut corners = db.visible_corners(chat)?;
+    corners.retain(|c| !c.archived);
+    if only.is_some() || corners.len() < 2 {
+        return Ok(send_msg(chat_id, text));
+    }
+    let rows = corners
+        .into_iter()
+        .map(|c| {
+            let action = Action::Chart {
+                corner_id: c.id,
+                from,
+            };
+            vec![(c.name, action)]
+        })
+        .collect();
+    Ok(send_buttons(chat_id, text, keyboard(db, chat_id, rows)?))
+}
+
+fn push_charts(
+    db: &DataBase,
+    chat_id: i64,
+    chat: &storage::Chat,
+    lang: Lang,
+    only: Option<u32>,
+    days: RangeInclusive<u32>,
+    out: &mut Outbox,
+) -> storage::Result<String> {
+    let (from, today) = days.into_inner();
+    for (png, caption) in report::charts(db, chat, lang, only, from, today)? {
+        out.push((chat_id, Outgoing::Png(png, caption)));
+    }
+    Ok(lang.charts_for(Revenue::date_of(from)?, Revenue::date_of(today)?))
+}
+
+/// Нажатие кнопки. Ответ на вебхук - answerCallbackQuery, а новое
+/// содержимое сообщения с кнопкой уходит через `out`.
+fn handle_callback(
+    db: &DataBase,
+    chat_id: i64,
+    lang: Lang,
+    message_id: i64,
+    query_id: String,
+    data: &str,
+    out: &mut Outbox,
+) -> storage::Result<Reply> {
+    let chat = match db.get_chat(chat_id)? {
+        Some(chat) if chat.is_active => chat,
+        _ => return Ok(answer(query_id, Some(lang.text(Msg::Guest)))),
+    };
+    let lang = chat.lang.unwrap_or(lang);
+    let action = match Action::decode(&db.secret(callback::KEY)?, chat_id, data) {
+        Some(action) => action,
+        None => return Ok(answer(query_id, Some(lang.text(Msg::BadButton)))),
+    };
+    let today = Revenue::day_of(dates::today(db.chat_tz(&chat)?))?;
+    let (text, keyboard) = match action {
+        Action::Day(day) if day <= today => (
+            report::day_summary(db, &chat, lang, day)?,
+            Some(day_keyboard(db, chat_id, lang, day, today)?),
+        ),
+        Action::Chart { corner_id, from } if from <= today => {
+            let visible = db.visible_corners(&chat)?.iter().any(|c| c.id == corner_id);
+            if !visible {
+                return Ok(answer(query_id, Some(lang.text(Msg::NotAdmin))));
+            }
+            let only = Some(corner_id);
+            let text = push_charts(db, chat_id, &chat, lang, only, from..=today, out)?;
+            (text, None)
+        }
+        Action::Confirm => (confirm_revenue(db, chat_id, lang, out)?, None),
+        Action::Cancel => match db.take_pending(chat_id)? {
+            // вопрос больше не нужен, об отмене скажет уведомление
+            Some(_) => {
+                let delete = methods::DeleteMessage {
+                    chat_id: methods::ChatTarget::id(chat_id),
+                    message_id: types::MessageId(message_id),
+                };
+                out.push((chat_id, Outgoing::Method(Reply::Delete(delete))));
+                return Ok(answer(query_id, Some(lang.text(Msg::Cancelled))));
+            }
+            None => (lang.text(Msg::NothingPending).to_owned(), None),
+        },
+        Action::Day(_) | Action::Chart { .. } => {
+            return Ok(answer(query_id, Some(lang.text(Msg::BadButton))))
+        }
+    };
+    let target = methods::ChatTarget::id(chat_id);
+    let mut edit = methods::EditMessageText::new(target, types::MessageId(message_id), text);
+    edit.reply_markup = keyboard;
+    out.push((chat_id, Outgoing::Method(Reply::Edit(edit))));
+    Ok(answer(query_id, None))
+}
+
+/// `/target <точка> <день|месяц> <сумма> [дата]`, план действует
+/// с указанной даты или с сегодняшнего дня. Период можно указать
+/// и по-английски: day, month.
+fn set_target(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    mut args: Vec<&str>,
+    today: u32,
+) -> storage::Result<String> {
+    if let Role::Staff = chat.role {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    let from = pop_date(&mut args, today).unwrap_or(today);
+    let amount = args.pop().and_then(Money::parse);
+    let period = match args.pop() {
+        Some("день") | Some("day") => Some(TargetPeriod::Day),
+        Some("месяц") | Some("month") => Some(TargetPeriod::Month),
+        _ => None,
+    };
+    let corner = db.find_corner(&args.join(" "))?;
+    let (corner, period, amount) = match (corner, period, amount) {
+        (Some(c), Some(p), Some(a)) => (c, p, a),
+        _ => return Ok(lang.text(Msg::TargetUsage).to_owned()),
+    };
+    if !db.can_manage(chat, corner.id)? {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    db.set_target(corner.id, from, period, amount)?;
+    Ok(lang.target_set(
+        &corner.name,
+        Revenue::date_of(from)?,
+        amount,
+        period == TargetPeriod::Month,
+    ))
+}
+
+/// `/open <наличные>` и `/close <наличные>` открывают и закрывают смену,
+/// `/shift` показывает текущую
+fn shift(
+    db: &DataBase,
+    chat_id: i64,
+    chat: &storage::Chat,
+    lang: Lang,
+    com: &str,
+    args: Vec<&str>,
+) -> storage::Result<String> {
+    let tz = match db.get_corner(chat.corner_id)? {
+        Some(corner) => corner.tz(),
+        None => return Ok(lang.text(Msg::NoCorner).to_owned()),
+    };
+    let now = chrono::Utc::now().timestamp() as u32;
+    let cash = match args.as_slice() {
+        [cash] => Money::parse(cash),
+        _ => None,
+    };
+    let time = |ts: u32| tz.timestamp(ts as i64, 0).format("%H:%M").to_string();
+    let res = match (com, cash) {
+        ("/open", Some(cash)) => db.open_shift(chat_id, chat.corner_id, cash, now),
+        ("/close", Some(cash)) => db.close_shift(chat_id, cash, now),
+        ("/shift", None) => match db.current_shift(chat_id)? {
+            Some(shift) => Ok(shift),
+            None => return Ok(lang.text(Msg::NoShift).to_owned()),
+        },
+        _ => return Ok(lang.text(Msg::ShiftUsage).to_owned()),
+    };
+    Ok(match res {
+        Ok(shift) => match (shift.closed, shift.close_cash) {
+            (Some(closed), Some(cash)) => lang.shift_closed(
+                &time(shift.opened),
+                &time(closed),
+                (closed.saturating_sub(shift.opened) / 60) as u64,
+                cash,
+                shift.open_cash,
+            ),
+            _ => lang.shift_opened(&time(shift.opened), shift.open_cash),
+        },
+        Err(storage::Error::Duplicate(_)) => lang.text(Msg::ShiftOpen).to_owned(),
+        Err(storage::Error::NotFound) => lang.text(Msg::NoShift).to_owned(),
+        Err(e) => return Err(e),
+    })
+}
+
+/// Начисления сотрудника с начала месяца по сегодня
+fn salary(
+    db: &DataBase,
+    chat_id: i64,
+    chat: &storage::Chat,
+    lang: Lang,
+    today: u32,
+) -> storage::Result<String> {
+    if chat.pay.is_none() {
+        return Ok(lang.text(Msg::NoPayScheme).to_owned());
+    }
+    let from = Stats::first_day(Stats::month(today)?)?;
+    let line = db
+        .payroll(from, today)?
+        .into_iter()
+        .find(|l| l.chat_id == chat_id)
+        .unwrap_or_default();
+    Ok(lang.salary(
+        Revenue::date_of(from)?,
+        Revenue::date_of(today)?,
+        line.shifts,
+        line.fixed,
+        line.revenue,
+        line.bonus,
+    ))
+}
+
+/// `/pay <имя> [смена <ставка>] [процент <п>] [порог <сумма>]` или `/pay <имя> off`.
+/// Вместо смена, процент, порог годятся shift, percent, threshold.
+fn set_pay(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    args: Vec<&str>,
+) -> storage::Result<String> {
+    if let Role::Staff = chat.role {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    let keywords = [
+        "смена",
+        "процент",
+        "порог",
+        "shift",
+        "percent",
+        "threshold",
+        "off",
+    ];
+    let split = args
+        .iter()
+        .position(|w| keywords.contains(w))
+        .unwrap_or(args.len());
+    let (name, params) = args.split_at(split);
+    let name = name.join(" ").to_lowercase();
+    let mut pay = Some(PayScheme::default());
+    let mut params = params.iter();
+    while let Some(word) = params.next() {
+        let value = params.next();
+        match (*word, value) {
+            ("off", None) => pay = None,
+            ("смена", Some(v)) | ("shift", Some(v)) => match (&mut pay, Money::parse(v)) {
+                (Some(pay), Some(v)) => pay.per_shift = v,
+                _ => return Ok(lang.text(Msg::PayUsage).to_owned()),
+            },
+            ("процент", Some(v)) | ("percent", Some(v)) => {
+                match (&mut pay, parse_percent(v)) {
+                    (Some(pay), Some(v)) => pay.percent = v,
+                    _ => return Ok(lang.text(Msg::PayUsage).to_owned()),
+                }
+            }
+            ("порог", Some(v)) | ("threshold", Some(v)) => match (&mut pay, Money::parse(v)) {
+                (Some(pay), Some(v)) => pay.threshold = v,
+                _ => return Ok(lang.text(Msg::PayUsage).to_owned()),
+            },
+            _ => return Ok(lang.text(Msg::PayUsage).to_owned()),
+        }
+    }
+    if name.is_empty() || pay == Some(PayScheme::default()) {
+        return Ok(lang.text(Msg::PayUsage).to_owned());
+    }
+
+    let mut found = Vec::new();
+    for (id, staff) in db.get_chats()? {
+        if staff.role == Role::Staff
+            && staff.name.to_lowercase() == name
+            && db.can_manage(chat, staff.corner_id)?
+        {
+            found.push(id);
+        }
+    }
+    let staff_id = match found.as_slice() {
+        [id] => *id,
+        [] => return Ok(lang.text(Msg::StaffNotFound).to_owned()),
+        _ => return Ok(lang.text(Msg::StaffAmbiguous).to_owned()),
+    };
+    let staff = db.set_pay_scheme(staff_id, pay)?;
+    Ok(match staff.pay {
+        None => lang.pay_off(&staff.name),
+        Some(pay) => lang.pay_scheme(&staff.name, pay.per_shift, pay.percent, pay.threshold),
+    })
+}
+
+/// "2,5", "2.5%" или "3" в сотых долях процента
+fn parse_percent(s: &str) -> Option<u32> {
+    let s = s.trim_end_matches('%').replace(',', ".");
+    let mut parts = s.splitn(2, '.');
+    let whole: u32 = parts.next()?.parse().ok()?;
+    let frac = match parts.next() {
+        None => 0,
+        Some(f) if f.len() == 1 => f.parse::<u32>().ok()? * 10,
+        Some(f) if f.len() == 2 => f.parse().ok()?,
+        _ => return None,
+    };
+    Some(whole * 100 + frac).filter(|p| *p <= 10_000)
+}
+
+/// Дата последними словами команды: "15.03.2020", "вчера", "15 марта"
+fn pop_date(args: &mut Vec<&str>, today: u32) -> Option<u32> {
+    let today = Revenue::date(today)?;
+    for n in (1..=args.len().min(3)).rev() {
+        let start = args.len() - n;
+        let day = dates::parse(&args[start..].join(" "), today).and_then(Revenue::day);
+        if day.is_some() {
+            args.truncate(start);
+            return day;
+        }
+    }
+    None
+}
+
+/// Фото без подписи прикрепляется к сегодняшней выручке
+fn attach_photo(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    photo: &str,
+) -> storage::Result<String> {
+    let today = dates::today(db.corner_tz(chat.corner_id)?);
+    match db.set_revenue_photo(Revenue::day_of(today)?, chat.corner_id, photo) {
+        Ok(rev) => Ok(lang.photo_saved(today, rev.amount)),
+        Err(storage::Error::NotFound) => Ok(lang.text(Msg::NoRevenue).to_owned()),
+        Err(e) => Err(e),
+    }
+}
+
+/// `/photo <точка> [дата]` присылает администратору фото Z-отчета
+fn show_photo(
+    db: &DataBase,
+    chat_id: i64,
+    chat: &storage::Chat,
+    lang: Lang,
+    mut args: Vec<&str>,
+    today: u32,
+) -> storage::Result<Reply> {
+    if let Role::Staff = chat.role {
+        return Ok(send_msg(chat_id, lang.text(Msg::NotAdmin)));
+    }
+    let day = pop_date(&mut args, today).unwrap_or(today);
+    let corner = match db.find_corner(&args.join(" "))? {
+        Some(corner) => corner,
+        None => return Ok(send_msg(chat_id, lang.text(Msg::PhotoUsage))),
+    };
+    if !db.can_manage(chat, corner.id)? {
+        return Ok(send_msg(chat_id, lang.text(Msg::NotAdmin)));
+    }
+    let date = Revenue::date_of(day)?;
+    let rev = db.get_revenue(day, corner.id)?;
+    Ok(match rev.and_then(|r| Some((r.photo?, r.amount))) {
+        Some((photo, amount)) => {
+            let file = types::FileToSend::FileId(types::FileId(photo));
+            let mut msg = methods::SendPhoto::new(methods::ChatTarget::id(chat_id), file);
+            let caption = format!(
+                "{}, {}: {}",
+                corner.name,
+                lang.date(date),
+                lang.money(amount)
+            );
+            msg.caption = Some(caption.into());
+            Reply::Photo(msg)
+        }
+        None => send_msg(chat_id, lang.no_photo(&corner.name, date)),
+    })
+}
+
+/// `/zreport <точка> on|off`
+fn require_photo(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    mut args: Vec<&str>,
+) -> storage::Result<String> {
+    if let Role::Staff = chat.role {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    let required = match args.pop() {
+        Some("on") => true,
+        Some("off") => false,
+        _ => return Ok(lang.text(Msg::ZreportUsage).to_owned()),
+    };
+    let corner = match db.find_corner(&args.join(" "))? {
+        Some(corner) => corner,
+        None => return Ok(lang.text(Msg::ZreportUsage).to_owned()),
+    };
+    if !db.can_manage(chat, corner.id)? {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    db.set_photo_required(corner.id, required)?;
+    Ok(lang.photo_required(&corner.name, required))
+}
+
+fn list_methods(db: &DataBase, lang: Lang) -> storage::Result<String> {
+    let mut res = String::from(lang.text(Msg::MethodsTitle));
+    for m in db.payment_methods()? {
+        res += &format!("\n{} - {}", m.code, m.name);
+        if m.archived {
+            res += lang.text(Msg::MethodOff);
+        }
+    }
+    res += "\n";
+    res += lang.text(Msg::MethodsHint);
+    Ok(res)
+}
+
+/// Список способов оплаты общий для всей сети, поэтому меняет его
+/// только администратор без ограничения группой
+fn edit_method(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    args: Vec<&str>,
+) -> storage::Result<String> {
+    if chat.role != Role::Admin(None) {
+        return Ok(lang.text(Msg::NotAdmin).to_owned());
+    }
+    let res = match args.as_slice() {
+        ["add", code, name @ ..] if !name.is_empty() => {
+            db.add_payment_method(&name.join(" "), code)
+        }
+        ["off", code] => db.set_payment_method_archived(code, true),
+        ["on", code] => db.set_payment_method_archived(code, false),
+        _ => return Ok(lang.text(Msg::MethodUsage).to_owned()),
+    };
+    match res {
+        Ok(_) => list_methods(db, lang),
+        Err(storage::Error::Duplicate(_)) => Ok(lang.text(Msg::MethodExists).to_owned()),
+        Err(storage::Error::Invalid(_)) => Ok(lang.text(Msg::MethodUsage).to_owned()),
+        Err(storage::Error::NotFound) => Ok(lang.text(Msg::MethodNotFound).to_owned()),
+        Err(e) => Err(e),
+    }
+}
+
+/// `/expense <категория> <сумма> [комментарий]` или `/expense del <номер>`
+fn add_expense(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    args: Vec<&str>,
+    photo: Option<String>,
+    day: u32,
+) -> storage::Result<String> {
+    if db.get_corner(chat.corner_id)?.is_none() {
+        return Ok(lang.text(Msg::NoCorner).to_owned());
+    }
+    let (category, amount, comment) = match args.as_slice() {
+        ["del", n] => {
+            let list = db.get_expenses(day, chat.corner_id)?;
+            let exp = n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| list.get(n.wrapping_sub(1)));
+            return match exp {
+                Some(exp) => {
+                    db.remove_expense(day, chat.corner_id, exp.id)?;
+                    list_expenses(db, chat, lang, day)
+                }
+                None => Ok(lang.text(Msg::ExpenseUsage).to_owned()),
+            };
+        }
+        [category, amount, comment @ ..] => match Money::parse(amount) {
+            Some(amount) if amount > Money::ZERO => (category, amount, comment.join(" ")),
+            _ => return Ok(lang.text(Msg::ExpenseUsage).to_owned()),
+        },
+        _ => return Ok(lang.text(Msg::ExpenseUsage).to_owned()),
+    };
+    let exp = db.add_expense(Expense {
+        corner_id: chat.corner_id,
+        date: day,
+        id: 0,
+        category: category.to_string(),
+        amount,
+        comment: Some(comment).filter(|c| !c.is_empty()),
+        photo,
+        post_datetime: chrono::Utc::now().timestamp() as u32,
+    })?;
+    let total: Money = db
+        .get_expenses(day, chat.corner_id)?
+        .iter()
+        .map(|e| e.amount)
+        .sum();
+    let revenue = db
+        .get_revenue(day, chat.corner_id)?
+        .map_or(Money::ZERO, |r| r.amount);
+    Ok(lang.expense_added(&exp.category, exp.amount, total, revenue - total))
+}
+
+fn list_expenses(
+    db: &DataBase,
+    chat: &storage::Chat,
+    lang: Lang,
+    day: u32,
+) -> storage::Result<String> {
+    let list = db.get_expenses(day, chat.corner_id)?;
+    if list.is_empty() {
+        return Ok(lang.no_expenses(&storage::EXPENSE_CATEGORIES.join(", ")));
+    }
+    let mut res = String::from(lang.text(Msg::ExpensesTitle));
+    for (i, exp) in list.iter().enumerate() {
+        res += &format!("\n{}. {} {}", i + 1, exp.category, lang.money(exp.amount));
+        if let Some(comment) = &exp.comment {
+            res += &format!(" - {}", comment);
+        }
+        if exp.photo.is_some() {
+            res += lang.text(Msg::HasReceipt);
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Точка "Галерея" и ее сотрудник Анна в чате 2
+    fn registered_chat(db: &DataBase) -> (storage::Corner, storage::Chat) {
+        let corner = db
+            .add_corner(storage::CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let staff = storage::Chat {
+            corner_id: corner.id,
+            name: "Анна".to_owned(),
+            is_active: true,
+            role: Role::Staff,
+            pay: None,
+            lang: None,
+        };
+        db.put_chat(2, &staff).unwrap();
+        (corner, staff)
+    }
+
+    fn revenue(corner_id: u32, date: u32, rubles: i64) -> Revenue {
+        Revenue {
+            corner_id,
+            date,
+            amount: Money::rubles(rubles),
+            post_datetime: 0,
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff: None,
+        }
+    }
+
+    /// История точки: по 10 000 ₽ за каждый день из `days`
+    fn seed_revenue(db: &DataBase, corner_id: u32, days: std::ops::Range<u32>) {
+        for day in days {
+            db.put_revenue(&revenue(corner_id, day, 10_000)).unwrap();
+        }
+    }
+
+    #[test]
+    fn payments_from_text() {
+        let db = DataBase::temporary();
+        let parse = |text| parse_payments(&db, text).unwrap();
+        let pay = |method, kopecks| Payment::new(method, Money::from_kopecks(kopecks));
+        assert_eq!(
+            parse("12 000"),
+            Some(vec![pay(storage::UNSPECIFIED, 1_200_000)])
+        );
+        assert_eq!(
+            parse("12 345,50"),
+            Some(vec![pay(storage::UNSPECIFIED, 1_234_550)])
+        );
+        assert_eq!(
+            parse("12к"),
+            Some(vec![pay(storage::UNSPECIFIED, 1_200_000)])
+        );
+        assert_eq!(
+            parse("Нал 5000, карта 7 000 + qr 300"),
+            Some(vec![pay(1, 500_000), pay(2, 700_000), pay(3, 30_000)])
+        );
+        assert_eq!(
+            parse("нал 5000,50,карта 7,5к"),
+            Some(vec![pay(1, 500_050), pay(2, 750_000)])
+        );
+        assert_eq!(parse("нал 5000,505"), None);
+        assert_eq!(parse("нал карта 7000"), None);
+        assert_eq!(parse("чек 7000"), None);
+        assert_eq!(parse("нал"), None);
+        assert_eq!(parse("привет"), None);
+    }
+
+    #[test]
+    fn z_report_photo() {
+        let db = DataBase::temporary();
+        let (_, staff) = registered_chat(&db);
+        let chat = |role| storage::Chat {
+            role,
+            ..staff.clone()
+        };
+        let text = |reply| match reply {
+            Reply::Message(msg) => msg.text.into_owned(),
+            reply => panic!("{:?}", reply),
+        };
+        let admin = |com: &str| {
+            com_handler(
+                &db,
+                1,
+                chat(Role::Admin(None)),
+                Lang::Ru,
+                com.to_owned(),
+                None,
+                &mut Vec::new(),
+            )
+        };
+
+        assert!(text(admin("/zreport Галерея on").unwrap()).ends_with("обязательно"));
+        let reply = com_handler(
+            &db,
+            2,
+            chat(Role::Staff),
+            Lang::Ru,
+            "1500".to_owned(),
+            None,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(text(reply).contains("фото Z-отчета"));
+        assert!(text(admin("/photo Галерея").unwrap()).ends_with("нет"));
+
+        // фото без подписи идет к уже внесенной выручке, исправление суммы его не теряет
+        let staff = chat(Role::Staff);
+        assert!(attach_photo(&db, &staff, Lang::Ru, "AgAD")
+            .unwrap()
+            .contains("1500 ₽"));
+        let reply = com_handler(
+            &db,
+            2,
+            staff,
+            Lang::Ru,
+            "1600".to_owned(),
+            None,
+            &mut Vec::new(),
+        )
+        .unwrap();
+        assert!(!text(reply).contains("фото"));
+        match admin("/photo Галерея").unwrap() {
+            Reply::Photo(args) => {
+                let json = serde_json::to_value(&UpdateReply {
+                    method: ApiMethod::SendPhoto,
+                    args,
+                })
+                .unwrap();
+                assert_eq!(json["method"], "sendPhoto");
+                assert_eq!(json["photo"], "AgAD");
+            }
+            reply => panic!("{:?}", reply),
+        }
+    }
+
+    #[test]
+    fn revenue_for_past_day() {
+        let db = DataBase::temporary();
+        let (corner, staff) = registered_chat(&db);
+        let chat = |role| storage::Chat {
+            role,
+            ..staff.clone()
+        };
+        let com = |role, com: &str| match com_handler(
+            &db,
+            2,
+            chat(role),
+            Lang::Ru,
+            com.to_owned(),
+            None,
+            &mut Vec::new(),
+        )
+        .unwrap()
+        {
+            Reply::Message(msg) => msg.text.into_owned(),
+            reply => panic!("{:?}", reply),
+        };
+        let today = Revenue::day(dates::today(storage::TIMEZONE)).unwrap();
+        let date = |day| Revenue::date(day).unwrap().format("%d.%m").to_string();
+
+        let reply = com(Role::Staff, "вчера 1 500,50");
+        assert!(reply.starts_with(&format!(
+            "Выручка за {} принята: 1500,50 ₽",
+            date(today - 1)
+        )));
+        com(
+            Role::Staff,
+            &format!("{} нал 700, карта 300", date(today - 2)),
+        );
+        let rev = db.get_revenue(today - 2, corner.id).unwrap().unwrap();
+        assert_eq!(rev.amount, Money::rubles(1_000));
+        assert_eq!(rev.payments.len(), 2);
+        assert_eq!(db.get_revenue(today, corner.id).unwrap(), None);
+        assert_eq!(com(Role::Staff, "завтра 500"), Lang::Ru.text(Msg::Help));
+        let tomorrow = format!("{} 500", date(today + 1));
+        assert_eq!(com(Role::Staff, &tomorrow), Lang::Ru.text(Msg::Help));
+        let huge = "нал 50000000000000000, карта 50000000000000000";
+        assert_eq!(com(Role::Staff, huge), Lang::Ru.text(Msg::Help));
+
+        let admin = Role::Admin(None);
+        let summary = com(admin, "/day позавчера");
+        assert!(summary.contains("Галерея: 1000 ₽ (Наличные 700 ₽, Карта 300 ₽)"));
+        assert_eq!(com(admin, "/day когда-то"), Lang::Ru.text(Msg::DayUsage));
+        let target = com(
+            admin,
+            &format!("/target Галерея месяц 30000 {}", date(today - 1)),
+        );
+        let yesterday = Revenue::date(today - 1)
+            .unwrap()
+            .format("%d.%m.%Y")
+            .to_string();
+        assert!(target.starts_with(&format!("План для Галерея с {}", yesterday)));
+
+        let admin = chat(admin);
+        let target = |lang, args| set_target(&db, &admin, lang, args, today).unwrap();
+        assert_eq!(
+            target(Lang::En, vec!["Галерея", "day", "1000"]),
+            Lang::En.target_set(
+                "Галерея",
+                Revenue::date(today).unwrap(),
+                Money::rubles(1000),
+                false
+            )
+        );
+        assert_eq!(
+            target(Lang::Ru, vec!["Галерея", "день", "1000"]),
+            Lang::Ru.target_set(
+                "Галерея",
+                Revenue::date(today).unwrap(),
+                Money::rubles(1000),
+                false
+            )
+        );
+        assert_eq!(
+            target(Lang::En, vec!["Галерея", "week", "1000"]),
+            Lang::En.text(Msg::TargetUsage)
+        );
+    }
+
+    #[test]
+    fn revenue_to_staff_on_shift() {
+        let db = DataBase::temporary();
+        let (corner, staff) = registered_chat(&db);
+        let chat = |name: &str| storage::Chat {
+            name: name.to_owned(),
+            ..staff.clone()
+        };
+        db.put_chat(3, &chat("Олег")).unwrap();
+        let com = |chat_id, com: &str| {
+            let name = if chat_id == 2 { "Анна" } else { "Олег" };
+            match com_handler(
+                &db,
+                chat_id,
+                chat(name),
+                Lang::Ru,
+                com.to_owned(),
+                None,
+                &mut Vec::new(),
+            )
+            .unwrap()
+            {
+                Reply::Message(msg) => msg.text.into_owned(),
+                reply => panic!("{:?}", reply),
+            }
+        };
+
+        assert_eq!(com(3, "/shift"), Lang::Ru.text(Msg::NoShift));
+        assert!(com(3, "/open 2000").ends_with("в кассе 2000 ₽"));
+        assert!(com(3, "/open 2000").starts_with("Смена уже открыта"));
+        // Анна без смены вносит выручку за Олега
+        assert!(com(2, "1500").contains("смена Олег"));
+        let today = Revenue::day(dates::today(storage::TIMEZONE)).unwrap();
+        let rev = db.get_revenue(today, corner.id).unwrap().unwrap();
+        assert_eq!(rev.staff, Some(3));
+        assert!(com(3, "/close 3500").contains("на открытии было 2000 ₽"));
+        com(2, "1600");
+        let rev = db.get_revenue(today, corner.id).unwrap().unwrap();
+        assert_eq!(rev.staff, Some(2));
+
+        let admin = storage::Chat {
+            role: Role::Admin(None),
+            ..chat("Админ")
+        };
+        let summary =
+            report::staff_summary(&db, &admin, Lang::Ru, Stats::month(today).unwrap()).unwrap();
+        assert!(
+            summary.ends_with("Анна: 1600 ₽ за 1 день\nОлег: 0 ₽ за 0 дней, смен 1 (0 часов)\n")
+        );
+    }
+
+    #[test]
+    fn pay_and_salary() {
+        assert_eq!(parse_percent("2,5"), Some(250));
+        assert_eq!(parse_percent("3%"), Some(300));
+        assert_eq!(parse_percent("0.75"), Some(75));
+        assert_eq!(parse_percent("2,555"), None);
+        assert_eq!(parse_percent("101"), None);
+
+        let db = DataBase::temporary();
+        let (corner, staff) = registered_chat(&db);
+        let admin = storage::Chat {
+            role: Role::Admin(None),
+            ..staff.clone()
+        };
+        assert_eq!(
+            set_pay(
+                &db,
+                &admin,
+                Lang::Ru,
+                vec!["анна", "смена", "1500", "процент", "2,5"]
+            )
+            .unwrap(),
+            "Анна: 1500 ₽ за смену, 2,50% с выручки дня сверх 0 ₽"
+        );
+        assert_eq!(
+            set_pay(&db, &admin, Lang::Ru, vec!["анна", "процент"]).unwrap(),
+            Lang::Ru.text(Msg::PayUsage)
+        );
+        assert_eq!(
+            set_pay(
+                &db,
+                &admin,
+                Lang::En,
+                vec!["анна", "shift", "1000", "threshold", "5000"]
+            )
+            .unwrap(),
+            Lang::En.pay_scheme("Анна", Money::rubles(1000), 0, Money::rubles(5000))
+        );
+        assert_eq!(
+            set_pay(&db, &admin, Lang::En, vec!["анна", "percent", "x"]).unwrap(),
+            Lang::En.text(Msg::PayUsage)
+        );
+        set_pay(
+            &db,
+            &admin,
+            Lang::Ru,
+            vec!["анна", "смена", "1500", "процент", "2,5"],
+        )
+        .unwrap();
+        assert_eq!(
+            set_pay(&db, &staff, Lang::Ru, vec!["анна", "off"]).unwrap(),
+            Lang::Ru.text(Msg::NotAdmin)
+        );
+
+        let staff = db.get_chat(2).unwrap().unwrap();
+        let today = Revenue::day(chrono::NaiveDate::from_ymd(2020, 3, 15)).unwrap();
+        db.put_revenue(&Revenue {
+            staff: Some(2),
+            ..revenue(corner.id, today, 10_000)
+        })
+        .unwrap();
+        assert_eq!(
+            salary(&db, 2, &staff, Lang::Ru, today).unwrap(),
+            "Зарплата с 01.03 по 15.03: смен 0 - 0 ₽, процент с выручки 10 000 ₽ - 250 ₽. Итого 250 ₽"
+        );
+    }
+
+    #[test]
+    fn unusual_revenue_confirm() {
+        let db = DataBase::temporary();
+        let (corner, staff) = registered_chat(&db);
+        let today = Revenue::day(dates::today(storage::TIMEZONE)).unwrap();
+        seed_revenue(&db, corner.id, today - 20..today - 10);
+        let admin = storage::Chat {
+            corner_id: 0,
+            role: Role::Admin(None),
+            ..staff.clone()
+        };
+        db.put_chat(1, &admin).unwrap();
+        let mut out = Outbox::new();
+        let mut com = |chat: &storage::Chat, com: &str| {
+            let chat_id = if chat.role == Role::Staff { 2 } else { 1 };
+            match com_handler(
+                &db,
+                chat_id,
+                chat.clone(),
+                Lang::Ru,
+                com.to_owned(),
+                None,
+                &mut out,
+            ) {
+                Ok(Reply::Message(msg)) => msg.text.into_owned(),
+                reply => panic!("{:?}", reply),
+            }
+        };
+
+        assert!(com(&staff, "100000").contains("/confirm"));
+        assert!(com(&staff, "9500").starts_with("Выручка"));
+        assert_eq!(com(&staff, "/confirm"), "Подтверждать нечего");
+        assert!(com(&staff, "100000").contains("намного больше"));
+        assert!(com(&staff, "/confirm").starts_with("Выручка"));
+        assert!(com(&admin, "/anomaly Галерея 1").starts_with("Формат"));
+        assert!(com(&admin, "/anomaly Галерея off").ends_with("выключена"));
+        assert!(com(&staff, "10").starts_with("Выручка"));
+
+        // обычная выручка зависит от того, на какие месяцы пришлась история
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].0, 1);
+        let alert = match &out[0].1 {
+            Outgoing::Text(text) => text,
+            msg => panic!("{:?}", msg),
+        };
+        assert!(alert.starts_with("Галерея: подтверждена необычная выручка 100 000 ₽"));
+        assert!(alert.ends_with("Отправил сотрудник Анна"));
+
+        // без точки отложенная выручка не пропадает
+        let lost = revenue(corner.id + 1, today, 100_000);
+        db.put_pending(2, &lost).unwrap();
+        assert_eq!(
+            confirm_revenue(&db, 2, Lang::Ru, &mut Outbox::new()).unwrap(),
+            Lang::Ru.text(Msg::NoCorner)
+        );
+        assert_eq!(db.get_pending(2).unwrap(), Some(lost));
+    }
+
+    #[test]
+    fn corner_timezone() {
+        let db = DataBase::temporary();
+        let (corner, staff) = registered_chat(&db);
+        let admin = storage::Chat {
+            corner_id: 0,
+            role: Role::Admin(None),
+            ..staff.clone()
+        };
+        let com = |chat: &storage::Chat, com: &str| match com_handler(
+            &db,
+            1,
+            chat.clone(),
+            Lang::Ru,
+            com.to_owned(),
+            None,
+            &mut Vec::new(),
+        ) {
+            Ok(Reply::Message(msg)) => msg.text.into_owned(),
+            reply => panic!("{:?}", reply),
+        };
+
+        assert_eq!(com(&staff, "/tz Галерея UTC"), Lang::Ru.text(Msg::NotAdmin));
+        assert_eq!(com(&admin, "/tz Вокзал UTC"), Lang::Ru.text(Msg::TzUsage));
+        assert!(com(&admin, "/tz Галерея").starts_with("Галерея: часовой пояс Europe/Moscow"));
+        // сутки в этих поясах не пересекаются: +14 и -11 часов от UTC
+        let (east, west) = ("Pacific/Kiritimati", "Pacific/Pago_Pago");
+        assert!(com(&admin, &format!("/tz Галерея {}", east)).contains(east));
+        let east_day = Revenue::day(dates::today(dates::zone(east).unwrap())).unwrap();
+        let west_day = Revenue::day(dates::today(dates::zone(west).unwrap())).unwrap();
+        assert!(east_day > west_day);
+        assert!(com(&staff, "1500").starts_with("Выручка"));
+        assert!(db.get_revenue(east_day, corner.id).unwrap().is_some());
+        // вчерашний день точки, а не сервера
+        com(&staff, "вчера 700");
+        let rev = db.get_revenue(east_day - 1, corner.id).unwrap().unwrap();
+        assert_eq!(rev.amount, Money::rubles(700));
+
+        db.set_corner_timezone(corner.id, west).unwrap();
+        assert!(com(&staff, "1200").starts_with("Выручка"));
+        assert!(db.get_revenue(west_day, corner.id).unwrap().is_some());
+    }
+
+    #[test]
+    fn charts_to_outbox() {
+        let db = DataBase::temporary();
+        for name in &["Галерея", "Вокзал"] {
+            db.add_corner(storage::CornerInfo {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        let admin = storage::Chat {
+            corner_id: 0,
+            name: "Админ".to_owned(),
+            is_active: true,
+            role: Role::Admin(None),
+            pay: None,
+            lang: None,
+        };
+        let today = Revenue::day(dates::today(storage::TIMEZONE)).unwrap();
+        let month = Stats::first_day(Stats::month(today).unwrap()).unwrap();
+        db.set_target(1, month, TargetPeriod::Day, Money::rubles(5_000))
+            .unwrap();
+        let mut out = Outbox::new();
+        let mut com = |com: &str| match com_handler(
+            &db,
+            1,
+            admin.clone(),
+            Lang::Ru,
+            com.to_owned(),
+            None,
+            &mut out,
+        ) {
+            Ok(Reply::Message(msg)) => msg.text.into_owned(),
+            reply => panic!("{:?}", reply),
+        };
+        assert!(com("/chart").starts_with("Графики за"));
+        assert!(com("/chart month Вокзал").starts_with("Графики за"));
+        assert!(com("/chart week Нет такой").starts_with("Формат"));
+
+        let captions: Vec<_> = out
+            .iter()
+            .map(|(chat_id, msg)| match msg {
+                Outgoing::Png(png, caption) if *chat_id == 1 && png.starts_with(b"\x89PNG") => {
+                    caption.as_str()
+                }
+                msg => panic!("{:?}", msg),
+            })
+            .collect();
+        // за неделю по всем точкам: по дням, сравнение и план; по одной точке без плана
+        assert_eq!(captions.len(), 4);
+        assert!(captions[0].ends_with("все точки, тыс. ₽"));
+        assert!(captions[1].ends_with("1 - Галерея, 2 - Вокзал"));
+        assert!(captions[2].ends_with("и факт, тыс. ₽: 1 - Галерея"));
+        assert!(captions[3].ends_with("Вокзал, тыс. ₽"));
+    }
+
+    #[test]
+    fn inline_buttons() {
+        let db = DataBase::temporary();
+        let (corner, staff) = registered_chat(&db);
+        let today = Revenue::day(dates::today(storage::TIMEZONE)).unwrap();
+        seed_revenue(&db, corner.id, today - 20..today - 10);
+        let buttons = |reply| match reply {
+            Ok(Reply::Message(methods::SendMessage {
+                reply_markup: Some(methods::ReplyMarkup::InlineKeyboard(k)),
+                ..
+            })) => k
+                .inline_keyboard
+                .concat()
+                .into_iter()
+                .map(|b| match b.pressed {
+                    types::InlineKeyboardButtonPressed::CallbackData(data) => (b.text, data),
+                    pressed => panic!("{:?}", pressed),
+                })
+                .collect::<Vec<_>>(),
+            reply => panic!("{:?}", reply),
+        };
+        let mut out = Outbox::new();
+        let press = |data: &str, out: &mut Outbox| match handle_callback(
+            &db,
+            2,
+            Lang::Ru,
+            10,
+            "q".to_owned(),
+            data,
+            out,
+        )
+        .unwrap()
+        {
+            Reply::Answer(answer) => answer.text.map(Cow::into_owned),
+            reply => panic!("{:?}", reply),
+        };
+        let edited = |out: &mut Outbox| match out.pop() {
+            Some((2, Outgoing::Method(Reply::Edit(edit)))) => {
+                assert_eq!(edit.message_id, Some(types::MessageId(10)));
+                (edit.text.into_owned(), edit.reply_markup)
+            }
+            msg => panic!("{:?}", msg),
+        };
+
+        let day = buttons(com_handler(
+            &db,
+            2,
+            staff.clone(),
+            Lang::Ru,
+            "/day".into(),
+            None,
+            &mut out,
+        ));
+        assert_eq!(day.len(), 1);
+        assert!(day[0].0.starts_with("← "));
+        assert_eq!(press(&day[0].1, &mut out), None);
+        let (text, keyboard) = edited(&mut out);
+        let yesterday = Revenue::date(today - 1).unwrap();
+        assert!(text.starts_with(&format!("Итоги за {}", yesterday.format("%d.%m.%Y"))));
+        assert_eq!(keyboard.unwrap().inline_keyboard[0].len(), 2);
+
+        // чужая или подделанная кнопка
+        let forged = day[0]
+            .1
+            .replacen(&(today - 1).to_string(), &(today + 1).to_string(), 1);
+        assert_eq!(
+            press(&forged, &mut out).as_deref(),
+            Some(Lang::Ru.text(Msg::BadButton))
+        );
+        let other = Action::Day(today).encode(&db.secret(callback::KEY).unwrap(), 3);
+        assert_eq!(
+            press(&other, &mut out).as_deref(),
+            Some(Lang::Ru.text(Msg::BadButton))
+        );
+        assert!(out.is_empty());
+
+        let big = |out: &mut Outbox| {
+            buttons(com_handler(
+                &db,
+                2,
+                staff.clone(),
+                Lang::Ru,
+                "100000".into(),
+                None,
+                out,
+            ))
+        };
+        let confirm = big(&mut out);
+        assert_eq!(confirm.len(), 2);
+        assert_eq!(
+            press(&confirm[1].1, &mut out).as_deref(),
+            Some(Lang::Ru.text(Msg::Cancelled))
+        );
+        match out.pop() {
+            Some((2, Outgoing::Method(Reply::Delete(delete)))) => {
+                assert_eq!(delete.message_id, types::MessageId(10))
+            }
+            msg => panic!("{:?}", msg),
+        }
+        assert_eq!(db.get_revenue(today, corner.id).unwrap(), None);
+        // отмененную выручку уже не подтвердить
+        press(&confirm[0].1, &mut out);
+        assert_eq!(edited(&mut out).0, Lang::Ru.text(Msg::NothingPending));
+        let confirm = big(&mut out);
+        press(&confirm[0].1, &mut out);
+        assert!(edited(&mut out).0.starts_with("Выручка"));
+        assert_eq!(
+            db.get_revenue(today, corner.id).unwrap().unwrap().amount,
+            Money::rubles(100_000)
+        );
+
+        let json = serde_json::to_value(&UpdateReply {
+            method: ApiMethod::AnswerCallbackQuery,
+            args: AnswerCallbackQuery {
+                callback_query_id: "q".to_owned(),
+                text: None,
+            },
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"method": "answerCallbackQuery", "callback_query_id": "q"})
+        );
+    }
+
+    #[test]
+    fn day_keyboard_edges() {
+        let db = DataBase::temporary();
+        let texts = |day, today| -> Vec<String> {
+            let markup = day_keyboard(&db, 42, Lang::Ru, day, today).unwrap();
+            markup.inline_keyboard[0]
+                .iter()
+                .map(|b| b.text.clone())
+                .collect()
+        };
+        assert_eq!(texts(0, 0), Vec::<String>::new());
+        let today = Revenue::day(chrono::NaiveDate::from_ymd(2020, 3, 2)).unwrap();
+        assert_eq!(texts(today, today), vec!["← 01.03"]);
+        assert_eq!(texts(today - 1, today), vec!["← 29.02", "02.03 →"]);
+    }
+
+    #[test]
+    fn reply_json() {
+        use serde_json::json;
+        let chat = || methods::ChatTarget::id(42);
+        let file = || types::FileToSend::FileId(types::FileId("AgAD".to_owned()));
+        let key = [7u8; 32];
+        let mut edit = methods::EditMessageText::new(chat(), types::MessageId(10), "Итоги");
+        edit.reply_markup = Some(types::InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![types::InlineKeyboardButton {
+                text: "← 01.03".to_owned(),
+                pressed: types::InlineKeyboardButtonPressed::CallbackData(
+                    Action::Day(1).encode(&key, 42),
+                ),
+            }]],
+        });
+        let replies = vec![
+            (
+                Reply::Message(methods::SendMessage::new(chat(), "Привет")),
+                json!({
+                    "method": "sendMessage",
+                    "chat_id": 42,
+                    "text": "Привет",
+                    "disable_web_page_preview": false,
+                    "disable_notification": false
+                }),
+            ),
+            (
+                Reply::Photo(methods::SendPhoto::new(chat(), file())),
+                json!({"method": "sendPhoto", "chat_id": 42, "photo": "AgAD"}),
+            ),
+            (
+                Reply::Edit(edit),
+                json!({
+                    "method": "editMessageText",
+                    "chat_id": 42,
+                    "message_id": 10,
+                    "text": "Итоги",
+                    "reply_markup": {"inline_keyboard": [[{
+                        "text": "← 01.03",
+                        "callback_data": Action::Day(1).encode(&key, 42)
+                    }]]}
+                }),
+            ),
+            (
+                Reply::Delete(methods::DeleteMessage {
+                    chat_id: chat(),
+                    message_id: types::MessageId(10),
+                }),
+                json!({"method": "deleteMessage", "chat_id": 42, "message_id": 10}),
+            ),
+            (
+                Reply::Answer(AnswerCallbackQuery {
+                    callback_query_id: "q".to_owned(),
+                    text: Some(Lang::Ru.text(Msg::BadButton).into()),
+                }),
+                json!({
+                    "method": "answerCallbackQuery",
+                    "callback_query_id": "q",
+                    "text": Lang::Ru.text(Msg::BadButton)
+                }),
+            ),
+            (
+                leave_chat(42),
+                json!({"method": "leaveChat", "chat_id": 42}),
+            ),
+            (
+                Reply::Commands(SetMyCommands {
+                    commands: vec![BotCommand {
+                        command: "day",
+                        description: "Today's summary",
+                    }],
+                    language_code: Some("en"),
+                }),
+                json!({
+                    "method": "setMyCommands",
+                    "commands": [{"command": "day", "description": "Today's summary"}],
+                    "language_code": "en"
+                }),
+            ),
+        ];
+        for (reply, expected) in replies {
+            let method = reply.method();
+            assert_eq!(serde_json::to_value(method).unwrap(), method.name());
+            assert_eq!(serde_json::to_value(&reply).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn commands_menu() {
+        let db = DataBase::temporary();
+        let chat = |role| storage::Chat {
+            corner_id: 0,
+            name: "Анна".to_owned(),
+            is_active: true,
+            role,
+            pay: None,
+            lang: None,
+        };
+        let mut out = Outbox::new();
+        let reply = com_handler(
+            &db,
+            2,
+            chat(Role::Staff),
+            Lang::Ru,
+            "/commands".into(),
+            None,
+            &mut out,
+        );
+        match reply.unwrap() {
+            Reply::Message(msg) => assert_eq!(msg.text, Lang::Ru.text(Msg::NotAdmin)),
+            reply => panic!("{:?}", reply),
+        }
+        assert!(out.is_empty());
+        let reply = com_handler(
+            &db,
+            1,
+            chat(Role::Admin(None)),
+            Lang::Ru,
+            "/commands".into(),
+            None,
+            &mut out,
+        );
+        match reply.unwrap() {
+            Reply::Message(msg) => assert_eq!(msg.text, Lang::Ru.text(Msg::CommandsSet)),
+            reply => panic!("{:?}", reply),
+        }
+        let mut menu = |language_code| match out.pop() {
+            Some((1, Outgoing::Method(Reply::Commands(menu)))) => {
+                assert_eq!(menu.language_code, language_code);
+                assert_eq!(menu.commands.len(), Lang::Ru.commands().count());
+                menu.commands
+            }
+            msg => panic!("{:?}", msg),
+        };
+        assert_eq!(menu(Some("en"))[0].description, "Today's summary");
+        assert_eq!(menu(None)[0].description, "Итоги за сегодня");
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn chat_language() {
+        let db = DataBase::temporary();
+        let mut out = Outbox::new();
+        let mut guest = |lang| match handle_msg(&db, 5, "Ann".into(), lang, None, None, &mut out) {
+            Ok(Reply::Message(msg)) => msg.text.into_owned(),
+            reply => panic!("{:?}", reply),
+        };
+        assert_eq!(guest(Lang::Ru), "Ведите код приглашения");
+        assert_eq!(guest(Lang::En), "Enter your invite code");
+
+        registered_chat(&db);
+        let com = |lang, text: &str| {
+            let chat = db.get_chat(2).unwrap().unwrap();
+            match com_handler(&db, 2, chat, lang, text.to_owned(), None, &mut Vec::new()) {
+                Ok(Reply::Message(msg)) => msg.text.into_owned(),
+                reply => panic!("{:?}", reply),
+            }
+        };
+        // язык из Telegram, пока в чате не выбран другой
+        assert_eq!(com(Lang::En, "/staff"), Lang::En.text(Msg::NotAdmin));
+        assert!(com(Lang::En, "12345").starts_with("Revenue for "));
+        assert!(com(Lang::Ru, "12345").ends_with("принята: 12 345 ₽ (было 12 345 ₽)"));
+        assert_eq!(com(Lang::Ru, "/lang de"), Lang::Ru.text(Msg::LangUsage));
+        assert_eq!(com(Lang::Ru, "/lang en"), "Bot language is English");
+        assert_eq!(db.get_chat(2).unwrap().unwrap().lang, Some(Lang::En));
+        assert_eq!(com(Lang::Ru, "/staff"), Lang::En.text(Msg::NotAdmin));
+        assert_eq!(
+            com(Lang::Ru, "/open 1000"),
+            format!(
+                "Shift opened at {}, ₽1,000 in the till",
+                Utc::now().with_timezone(&storage::TIMEZONE).format("%H:%M")
+            )
+        );
+        assert_eq!(com(Lang::Ru, "/lang auto"), "Язык бота - русский");
+        assert_eq!(db.get_chat(2).unwrap().unwrap().lang, None);
+        assert_eq!(com(Lang::Ru, "/open 1000"), Lang::Ru.text(Msg::ShiftOpen));
+    }
 }
@@ -0,0 +1,111 @@
+//! Служебные команды, запускаемые вместо вебхука: `cmbot <команда> [аргументы]`
+
+use crate::storage::{DataBase, ImportFormat, OnConflict, Revenue};
+use chrono::NaiveDate;
+use std::path::Path;
+
+const USAGE: &str = "Commands:
+    upgrade-db                  rewrite old records in the current format
+    rebuild-stats               recount monthly rollups of corners and groups
+    backup <file>               save the database to an archive
+    restore <file> <db dir>     build a new database from an archive
+    import <file.csv|file.json> [--dry-run] [--on-conflict skip|overwrite|fail]
+                                load historical revenue, skipping existing days by default
+    export <file.csv>           save all revenue with payment method columns
+    payroll <from> <to> <file.csv>
+                                save staff pay for the days from..=to (YYYY-MM-DD)";
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["upgrade-db"] => {
+            let n = DataBase::open()?.upgrade_all()?;
+            println!("Upgraded {} records", n);
+        }
+        ["rebuild-stats"] => {
+            let n = DataBase::open()?.rebuild_stats()?;
+            println!("Rebuilt {} monthly rollups", n);
+        }
+        ["backup", file] => {
+            let summary = DataBase::open()?.backup_to_file(Path::new(file))?;
+            println!(
+                "Saved {} trees, {} records to {}",
+                summary.trees, summary.records, file
+            );
+        }
+        ["restore", file, db_dir] => {
+            let (_, summary) = DataBase::restore(Path::new(file), Path::new(db_dir))?;
+            println!(
+                "Restored {} trees, {} records into {}",
+                summary.trees, summary.records, db_dir
+            );
+        }
+        ["import", file, opts @ ..] => import(Path::new(file), opts)?,
+        ["export", file] => {
+            let out = std::io::BufWriter::new(std::fs::File::create(file)?);
+            let rows = DataBase::open()?.export_revenue(out)?;
+            println!("Saved {} rows to {}", rows, file);
+        }
+        ["payroll", from, to, file] => {
+            let day = |s: &str| {
+                NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                    .ok()
+                    .and_then(Revenue::day)
+                    .ok_or_else(|| anyhow::anyhow!("bad date {:?}, expected YYYY-MM-DD", s))
+            };
+            let (from, to) = (day(from)?, day(to)?);
+            let out = std::io::BufWriter::new(std::fs::File::create(file)?);
+            let rows = DataBase::open()?.export_payroll(from, to, out)?;
+            println!("Saved {} employees to {}", rows, file);
+        }
+        _ => anyhow::bail!("unknown command\n{}", USAGE),
+    }
+    Ok(())
+}
+
+fn import(file: &Path, opts: &[&str]) -> anyhow::Result<()> {
+    let format = match file.extension().and_then(|e| e.to_str()) {
+        Some("csv") => ImportFormat::Csv,
+        Some("json") => ImportFormat::Json,
+        _ => anyhow::bail!("expected a .csv or .json file"),
+    };
+    let mut dry_run = false;
+    let mut on_conflict = OnConflict::Skip;
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match (*opt, opts.as_slice().first()) {
+            ("--dry-run", _) => dry_run = true,
+            ("--on-conflict", Some(policy)) => {
+                on_conflict = match *policy {
+                    "skip" => OnConflict::Skip,
+                    "overwrite" => OnConflict::Overwrite,
+                    "fail" => OnConflict::Fail,
+                    p => anyhow::bail!("unknown conflict policy {}", p),
+                };
+                opts.next();
+            }
+            (o, _) => anyhow::bail!("unknown option {}\n{}", o, USAGE),
+        }
+    }
+
+    let input = std::fs::File::open(file)?;
+    let report = DataBase::open()?.import_revenue(input, format, on_conflict, dry_run)?;
+    for (line, err) in &report.errors {
+        println!("row {}: {}", line, err);
+    }
+    println!(
+        "{} rows: {} new, {} overwritten, {} skipped, {} errors",
+        report.rows,
+        report.inserted,
+        report.overwritten,
+        report.skipped,
+        report.errors.len()
+    );
+    if report.aborted {
+        anyhow::bail!("some days already exist, nothing was written");
+    }
+    if dry_run {
+        println!("Dry run, nothing was written");
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! Даты, которые пишут в чате: "вчера", "позавчера", "15.03", "15.03.2020",
+//! "15 марта". Год без указания выбирается так, чтобы дата была ближе
+//! всего к сегодняшней: в январе "28.12" - это прошлый декабрь.
+//! Здесь же перевод моментов времени в даты часового пояса точки.
+
+use chrono::{Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Формы названий месяцев, которые понимает разбор, по порядку месяцев
+const MONTHS: [&[&str]; 12] = [
+    &["январь", "января", "янв"],
+    &["февраль", "февраля", "фев"],
+    &["март", "марта", "мар"],
+    &["апрель", "апреля", "апр"],
+    &["май", "мая"],
+    &["июнь", "июня", "июн"],
+    &["июль", "июля", "июл"],
+    &["август", "августа", "авг"],
+    &["сентябрь", "сентября", "сен", "сент"],
+    &["октябрь", "октября", "окт"],
+    &["ноябрь", "ноября", "ноя"],
+    &["декабрь", "декабря", "дек"],
+];
+
+/// Дата из текста относительно `today` - сегодняшнего дня в часовом поясе
+/// точки. Регистр не важен, слова разделяются пробелами.
+pub fn parse(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let text = text.trim().to_lowercase();
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let date = match words.as_slice() {
+        ["сегодня"] | ["today"] => Some(today),
+        ["вчера"] | ["yesterday"] => Some(today - Duration::days(1)),
+        ["позавчера"] => Some(today - Duration::days(2)),
+        [date] => numeric(date, today),
+        [day, month] => near(today, month_number(month)?, day.parse().ok()?),
+        [day, month, year] => {
+            NaiveDate::from_ymd_opt(year.parse().ok()?, month_number(month)?, day.parse().ok()?)
+        }
+        _ => None,
+    };
+    // номера дней начинаются с первого года
+    date.filter(|date| date.year() > 0)
+}
+
+/// Часовой пояс по имени из базы IANA: "Europe/Moscow", "Asia/Vladivostok"
+pub fn zone(name: &str) -> Option<Tz> {
+    name.trim().parse().ok()
+}
+
+/// Дата в поясе `tz` в момент `ts` (секунды Unix)
+pub fn date_at(tz: Tz, ts: i64) -> NaiveDate {
+    tz.timestamp(ts, 0).date().naive_local()
+}
+
+/// Сегодняшняя дата в поясе `tz`
+pub fn today(tz: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&tz).date().naive_local()
+}
+
+/// "15.03", "15.03.20", "15.03.2020" или "2020-03-15"
+fn numeric(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Some(date);
+    }
+    let parts: Vec<&str> = text.split('.').collect();
+    let number = |s: &str| {
+        if (1..=2).contains(&s.len()) {
+            s.parse::<u32>().ok()
+        } else {
+            None
+        }
+    };
+    match parts.as_slice() {
+        [day, month] => near(today, number(month)?, number(day)?),
+        [day, month, year] => {
+            let year: i32 = match year.len() {
+                2 => 2000 + year.parse::<i32>().ok()?,
+                4 => year.parse().ok()?,
+                _ => return None,
+            };
+            NaiveDate::from_ymd_opt(year, number(month)?, number(day)?)
+        }
+        _ => None,
+    }
+}
+
+fn month_number(word: &str) -> Option<u32> {
+    let word = word.trim_end_matches('.');
+    MONTHS
+        .iter()
+        .position(|forms| forms.contains(&word))
+        .map(|i| i as u32 + 1)
+}
+
+/// День и месяц в ближайшем к `today` году
+fn near(today: NaiveDate, month: u32, day: u32) -> Option<NaiveDate> {
+    (today.year() - 1..=today.year() + 1)
+        .filter_map(|year| NaiveDate::from_ymd_opt(year, month, day))
+        .min_by_key(|date| (*date - today).num_days().abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_and_numeric() {
+        let today = NaiveDate::from_ymd(2020, 3, 2);
+        let date = |s| parse(s, today);
+        assert_eq!(date("Сегодня"), Some(today));
+        assert_eq!(date("вчера"), Some(NaiveDate::from_ymd(2020, 3, 1)));
+        assert_eq!(date(" позавчера "), Some(NaiveDate::from_ymd(2020, 2, 29)));
+        assert_eq!(date("yesterday"), Some(NaiveDate::from_ymd(2020, 3, 1)));
+        assert_eq!(date("15.03"), Some(NaiveDate::from_ymd(2020, 3, 15)));
+        assert_eq!(date("5.1"), Some(NaiveDate::from_ymd(2020, 1, 5)));
+        assert_eq!(date("15.03.2019"), Some(NaiveDate::from_ymd(2019, 3, 15)));
+        assert_eq!(date("15.03.19"), Some(NaiveDate::from_ymd(2019, 3, 15)));
+        assert_eq!(date("2019-03-15"), Some(NaiveDate::from_ymd(2019, 3, 15)));
+        for bad in &[
+            "",
+            "31.02",
+            "01.01.0000",
+            "15",
+            "15.03.219",
+            "1.2.3.4",
+            "12 000",
+            "завтра",
+            "15.003",
+        ] {
+            assert_eq!(date(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn month_names() {
+        let today = NaiveDate::from_ymd(2020, 3, 2);
+        let date = |s| parse(s, today);
+        assert_eq!(date("15 марта"), Some(NaiveDate::from_ymd(2020, 3, 15)));
+        assert_eq!(date("1 Мая"), Some(NaiveDate::from_ymd(2020, 5, 1)));
+        assert_eq!(date("3 сент. 2019"), Some(NaiveDate::from_ymd(2019, 9, 3)));
+        assert_eq!(date("29 февраля 2019"), None);
+        assert_eq!(date("15 мартобря"), None);
+        // год выбирается ближайший к сегодня
+        let january = NaiveDate::from_ymd(2021, 1, 3);
+        assert_eq!(
+            parse("28 декабря", january),
+            Some(NaiveDate::from_ymd(2020, 12, 28))
+        );
+        assert_eq!(
+            parse("28.12", january),
+            Some(NaiveDate::from_ymd(2020, 12, 28))
+        );
+        assert_eq!(
+            parse("15.01", january),
+            Some(NaiveDate::from_ymd(2021, 1, 15))
+        );
+        let december = NaiveDate::from_ymd(2020, 12, 30);
+        assert_eq!(
+            parse("2 января", december),
+            Some(NaiveDate::from_ymd(2021, 1, 2))
+        );
+        // 29 февраля только в високосный год
+        let leap = NaiveDate::from_ymd(2021, 3, 1);
+        assert_eq!(parse("29.02", leap), Some(NaiveDate::from_ymd(2020, 2, 29)));
+    }
+
+    #[test]
+    fn zones() {
+        let moscow = zone("Europe/Moscow").unwrap();
+        let vladivostok = zone(" Asia/Vladivostok").unwrap();
+        assert_eq!(zone("Europe/Мск"), None);
+        // 2020-03-01 16:00 UTC: в Москве 19:00, во Владивостоке уже 2 марта
+        let ts = Utc.ymd(2020, 3, 1).and_hms(16, 0, 0).timestamp();
+        assert_eq!(date_at(moscow, ts), NaiveDate::from_ymd(2020, 3, 1));
+        assert_eq!(date_at(vladivostok, ts), NaiveDate::from_ymd(2020, 3, 2));
+    }
+}
@@ -0,0 +1,279 @@
+//! Прогноз выручки точки на следующую неделю и месяц. Линейный тренд и
+//! множители дней недели подбираются по истории `Revenue`, разброс
+//! прогноза - по ошибкам модели на той же истории.
+
+use crate::money::Money;
+use crate::storage::{self, DataBase, Stats};
+
+/// Сколько дней истории берется для подбора
+const HISTORY: u32 = 16 * 7;
+/// С меньшим числом дней прогноз не строится
+const MIN_DAYS: usize = 14;
+/// Границы интервала около 80% при нормальных ошибках
+const Z: f64 = 1.28;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    /// Тренд без сезонности: `level + slope * (day - origin)`
+    level: f64,
+    slope: f64,
+    origin: u32,
+    /// Множители дней недели, с понедельника
+    weekday: [f64; 7],
+    /// Стандартное отклонение ошибки за день
+    sigma: f64,
+}
+
+/// Прогноз суммы за дни с `from` по `to` с интервалом
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Forecast {
+    pub from: u32,
+    pub to: u32,
+    pub expected: Money,
+    pub low: Money,
+    pub high: Money,
+}
+
+/// День недели, 0 - понедельник. Первый день, 1 января 1 года, - понедельник.
+fn weekday(day: u32) -> usize {
+    ((day as u64 + 6) % 7) as usize
+}
+
+impl Model {
+    /// Подбирает модель по дням с выручкой `(день, сумма)`. Дни без записи
+    /// просто отсутствуют и нулями не считаются.
+    pub fn fit(history: &[(u32, Money)]) -> Option<Model> {
+        if history.len() < MIN_DAYS {
+            return None;
+        }
+        let n = history.len() as f64;
+        let mean = history.iter().map(|(_, a)| a.as_f64()).sum::<f64>() / n;
+        if mean <= 0.0 {
+            return None;
+        }
+        let mut sums = [0.0; 7];
+        let mut counts = [0u32; 7];
+        for (day, amount) in history {
+            sums[weekday(*day)] += amount.as_f64();
+            counts[weekday(*day)] += 1;
+        }
+        let mut weekday = [1.0; 7];
+        for w in 0..7 {
+            if counts[w] > 0 && sums[w] > 0.0 {
+                weekday[w] = sums[w] / counts[w] as f64 / mean;
+            }
+        }
+
+        // наименьшие квадраты по значениям без сезонности
+        let origin = history[0].0;
+        let points: Vec<(f64, f64)> = history
+            .iter()
+            .map(|(day, amount)| {
+                let x = (*day - origin) as f64;
+                (x, amount.as_f64() / weekday[self::weekday(*day)])
+            })
+            .collect();
+        let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+        let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+        let var: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+        let cov: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+        let slope = if var > 0.0 { cov / var } else { 0.0 };
+        let mut model = Model {
+            level: mean_y - slope * mean_x,
+            slope,
+            origin,
+            weekday,
+            sigma: 0.0,
+        };
+        let squares: f64 = history
+            .iter()
+            .map(|(day, amount)| (amount.as_f64() - model.predict(*day)).powi(2))
+            .sum();
+        model.sigma = (squares / (n - 2.0)).sqrt();
+        Some(model)
+    }
+
+    /// Ожидаемая выручка за день
+    pub fn predict(&self, day: u32) -> f64 {
+        let trend = self.level + self.slope * (day as f64 - self.origin as f64);
+        (trend * self.weekday[weekday(day)]).max(0.0)
+    }
+
+    /// Сумма за дни с `from` по `to`. Ошибки дней считаются независимыми.
+    pub fn forecast(&self, from: u32, to: u32) -> Forecast {
+        let expected: f64 = (from..=to).map(|day| self.predict(day)).sum();
+        let spread = Z * self.sigma * ((to - from + 1) as f64).sqrt();
+        let rubles = |sum: f64| Money::rubles(sum.round() as i64);
+        Forecast {
+            from,
+            to,
+            expected: rubles(expected),
+            low: rubles((expected - spread).max(0.0)),
+            high: rubles(expected + spread),
+        }
+    }
+}
+
+/// Прогноз точки на следующую календарную неделю и следующий месяц
+pub fn corner_forecast(
+    db: &DataBase,
+    corner_id: u32,
+    today: u32,
+) -> storage::Result<Option<(Forecast, Forecast)>> {
+    let history = db.daily_revenue(corner_id, today.saturating_sub(HISTORY), today)?;
+    let model = match Model::fit(&history) {
+        Some(model) => model,
+        None => return Ok(None),
+    };
+    let monday = today + 7 - weekday(today) as u32;
+    let month = Stats::month(today)? + 1;
+    let first = Stats::first_day(month)?;
+    let last = Stats::first_day(month + 1)? - 1;
+    Ok(Some((
+        model.forecast(monday, monday + 6),
+        model.forecast(first, last),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Revenue;
+    use chrono::NaiveDate;
+
+    fn fixture(csv: &str) -> Vec<(u32, Money)> {
+        csv.lines()
+            .skip(1)
+            .map(|line| {
+                let mut cols = line.split(',');
+                let date = NaiveDate::parse_from_str(cols.next().unwrap(), "%Y-%m-%d").unwrap();
+                (
+                    Revenue::day(date).unwrap(),
+                    Money::parse(cols.next().unwrap()).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// Прогоняет модель по истории: учится на `HISTORY` днях до `cut`,
+    /// прогнозирует `days` дней после. Возвращает среднюю ошибку суммы
+    /// в процентах, ту же ошибку у средней за день и долю попаданий в интервал.
+    fn backtest(data: &[(u32, Money)], days: u32) -> (f64, f64, f64) {
+        let (mut err, mut naive_err, mut hits, mut runs) = (0.0, 0.0, 0, 0);
+        let first = data[0].0;
+        let last = data[data.len() - 1].0;
+        let mut cut = first + HISTORY;
+        while cut + days <= last {
+            let train: Vec<_> = data
+                .iter()
+                .copied()
+                .filter(|(d, _)| *d + HISTORY > cut && *d <= cut)
+                .collect();
+            let actual: Vec<_> = data
+                .iter()
+                .filter(|(d, _)| *d > cut && *d <= cut + days)
+                .collect();
+            let model = Model::fit(&train).unwrap();
+            // в будущем тоже бывают пропуски, сравниваем только по дням с данными
+            let fact: f64 = actual.iter().map(|(_, a)| a.as_f64()).sum();
+            let predicted: f64 = actual.iter().map(|(d, _)| model.predict(*d)).sum();
+            let mean = train.iter().map(|(_, a)| a.as_f64()).sum::<f64>() / train.len() as f64;
+            let band = model.forecast(cut + 1, cut + days);
+            let scale = predicted / band.expected.as_f64();
+            err += (predicted - fact).abs() / fact;
+            naive_err += (mean * actual.len() as f64 - fact).abs() / fact;
+            if band.low.as_f64() * scale <= fact && fact <= band.high.as_f64() * scale {
+                hits += 1;
+            }
+            runs += 1;
+            cut += 7;
+        }
+        let runs = runs as f64;
+        (
+            err / runs * 100.0,
+            naive_err / runs * 100.0,
+            hits as f64 / runs,
+        )
+    }
+
+    #[test]
+    fn backtest_growing_corner() {
+        let data = fixture(include_str!("../fixtures/forecast_growing.csv"));
+        let (week, naive, hits) = backtest(&data, 7);
+        assert!(week < 6.0, "week error {:.1}%", week);
+        assert!(week < naive, "{:.1}% vs naive {:.1}%", week, naive);
+        assert!(hits >= 0.6, "week band hits {:.2}", hits);
+        let (month, naive, _) = backtest(&data, 30);
+        assert!(month < 6.0, "month error {:.1}%", month);
+        assert!(month < naive, "{:.1}% vs naive {:.1}%", month, naive);
+    }
+
+    #[test]
+    fn backtest_flat_corner_with_gaps() {
+        let data = fixture(include_str!("../fixtures/forecast_flat.csv"));
+        let (week, _, hits) = backtest(&data, 7);
+        assert!(week < 10.0, "week error {:.1}%", week);
+        assert!(hits >= 0.6, "week band hits {:.2}", hits);
+        let (month, _, _) = backtest(&data, 30);
+        assert!(month < 8.0, "month error {:.1}%", month);
+    }
+
+    #[test]
+    fn weekday_and_trend() {
+        let start = Revenue::day(NaiveDate::from_ymd(2020, 3, 2)).unwrap();
+        // будни 1000 и растут на 10 в день, выходные вдвое больше
+        let history: Vec<_> = (start..start + 28)
+            .map(|d| {
+                let base = 1_000 + (d - start) * 10;
+                let amount = if weekday(d) >= 5 { base * 2 } else { base };
+                (d, Money::rubles(amount as i64))
+            })
+            .collect();
+        assert_eq!(Model::fit(&history[..13]), None);
+        let model = Model::fit(&history).unwrap();
+        let saturday = start + 33;
+        let monday = start + 35;
+        assert!((model.predict(saturday) / model.predict(monday) - 2.0).abs() < 0.1);
+        assert!((model.predict(monday) - 1_350.0).abs() < 50.0);
+        let week = model.forecast(monday, monday + 6);
+        assert!(week.low <= week.expected && week.expected <= week.high);
+    }
+
+    #[test]
+    fn forecast_from_db() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(storage::CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let data = fixture(include_str!("../fixtures/forecast_flat.csv"));
+        let today = data[data.len() - 1].0;
+        assert_eq!(corner_forecast(&db, corner.id, today).unwrap(), None);
+        for (date, amount) in &data {
+            db.put_revenue(&Revenue {
+                corner_id: corner.id,
+                date: *date,
+                amount: *amount,
+                post_datetime: 0,
+                comment: None,
+                payments: Vec::new(),
+                photo: None,
+                staff: None,
+            })
+            .unwrap();
+        }
+        let (week, month) = corner_forecast(&db, corner.id, today).unwrap().unwrap();
+        assert_eq!(weekday(week.from), 0);
+        assert_eq!(week.to - week.from, 6);
+        assert_eq!(
+            Revenue::date(month.from).unwrap(),
+            NaiveDate::from_ymd(2020, 4, 1)
+        );
+        assert_eq!(month.to - month.from, 29);
+        // около 15000 в день
+        let rubles = week.expected.whole_rubles();
+        assert!(rubles > 90_000 && rubles < 120_000);
+    }
+}
@@ -0,0 +1,501 @@
+//! GraphQL API для администраторов, запросы приходят на `POST /admin/graphql`
+
+use crate::forecast::{self, Forecast};
+use crate::storage::{self, Chat, Corner, CornerInfo, DataBase, Group, Revenue, Role};
+use chrono::{DateTime, Utc};
+use juniper::{FieldError, FieldResult, RootNode};
+use std::convert::{TryFrom, TryInto};
+
+pub struct Context {
+    pub db: DataBase,
+    /// Момент, от которого строятся прогнозы. День отсчитывается
+    /// в поясе каждой точки.
+    pub now: DateTime<Utc>,
+}
+
+impl juniper::Context for Context {}
+
+/// Прогноз суммы за период с интервалом около 80%
+#[derive(juniper::GraphQLObject)]
+pub struct ForecastBand {
+    /// Первый день, ГГГГ-ММ-ДД
+    from: String,
+    /// Последний день включительно
+    to: String,
+    expected: f64,
+    low: f64,
+    high: f64,
+}
+
+impl TryFrom<Forecast> for ForecastBand {
+    type Error = storage::Error;
+
+    fn try_from(f: Forecast) -> storage::Result<Self> {
+        let date = |day| Revenue::date_of(day).map(|d| d.format("%Y-%m-%d").to_string());
+        Ok(ForecastBand {
+            from: date(f.from)?,
+            to: date(f.to)?,
+            expected: f.expected.as_f64(),
+            low: f.low.as_f64(),
+            high: f.high.as_f64(),
+        })
+    }
+}
+
+/// Прогноз точки, пустой если истории мало
+#[derive(juniper::GraphQLObject)]
+pub struct CornerForecast {
+    corner_id: i32,
+    name: String,
+    next_week: Option<ForecastBand>,
+    next_month: Option<ForecastBand>,
+}
+
+/// Точка из справочника
+#[derive(juniper::GraphQLObject)]
+#[graphql(name = "Corner")]
+pub struct CornerNode {
+    id: i32,
+    name: String,
+    short_name: Option<String>,
+    address: Option<String>,
+    hours: Option<String>,
+    archived: bool,
+    tags: Vec<String>,
+    group_id: Option<i32>,
+    timezone: String,
+}
+
+impl From<Corner> for CornerNode {
+    fn from(c: Corner) -> Self {
+        CornerNode {
+            id: c.id as i32,
+            name: c.name,
+            short_name: c.short_name,
+            address: c.address,
+            hours: c.hours,
+            archived: c.archived,
+            tags: c.tags,
+            group_id: c.group_id.map(|id| id as i32),
+            timezone: c.timezone,
+        }
+    }
+}
+
+/// Группа точек, например регион или город
+#[derive(juniper::GraphQLObject)]
+#[graphql(name = "Group")]
+pub struct GroupNode {
+    id: i32,
+    name: String,
+    parent_id: Option<i32>,
+}
+
+impl From<Group> for GroupNode {
+    fn from(g: Group) -> Self {
+        GroupNode {
+            id: g.id as i32,
+            name: g.name,
+            parent_id: g.parent_id.map(|id| id as i32),
+        }
+    }
+}
+
+/// Роль собеседника бота
+#[derive(juniper::GraphQLEnum, Clone, Copy)]
+pub enum ChatRole {
+    Staff,
+    Admin,
+}
+
+/// Собеседник бота
+#[derive(juniper::GraphQLObject)]
+#[graphql(name = "Chat")]
+pub struct ChatNode {
+    /// id чата Telegram, строкой: он не помещается в `Int`
+    id: String,
+    name: String,
+    corner_id: i32,
+    role: ChatRole,
+    /// Группа администратора, `null` - вся сеть
+    group_id: Option<i32>,
+}
+
+impl ChatNode {
+    fn new(id: i64, c: Chat) -> Self {
+        let (role, group_id) = match c.role {
+            Role::Staff => (ChatRole::Staff, None),
+            Role::Admin(group) => (ChatRole::Admin, group.map(|id| id as i32)),
+        };
+        ChatNode {
+            id: id.to_string(),
+            name: c.name,
+            corner_id: c.corner_id as i32,
+            role,
+            group_id,
+        }
+    }
+}
+
+/// Названия, адрес, часы и метки точки, см. `CornerInfo`
+#[derive(juniper::GraphQLInputObject)]
+pub struct CornerInput {
+    name: String,
+    short_name: Option<String>,
+    address: Option<String>,
+    hours: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+impl From<CornerInput> for CornerInfo {
+    fn from(i: CornerInput) -> Self {
+        CornerInfo {
+            name: i.name,
+            short_name: i.short_name,
+            address: i.address,
+            hours: i.hours,
+            tags: i.tags.unwrap_or_default(),
+        }
+    }
+}
+
+pub struct Query;
+
+#[juniper::object(Context = Context)]
+impl Query {
+    /// Прогноз одной точки
+    fn forecast(context: &Context, corner_id: i32) -> FieldResult<Option<CornerForecast>> {
+        context
+            .db
+            .get_corners()?
+            .into_iter()
+            .find(|c| c.id as i32 == corner_id)
+            .map(|c| corner_forecast(context, c))
+            .transpose()
+    }
+
+    /// Прогнозы всех действующих точек
+    fn forecasts(context: &Context) -> FieldResult<Vec<CornerForecast>> {
+        context
+            .db
+            .get_corners()?
+            .into_iter()
+            .filter(|c| !c.archived)
+            .map(|c| corner_forecast(context, c))
+            .collect()
+    }
+
+    /// Все точки, включая архивные, или только с меткой `tag`
+    fn corners(context: &Context, tag: Option<String>) -> FieldResult<Vec<CornerNode>> {
+        let corners = match tag {
+            Some(tag) => context.db.corners_by_tag(&tag)?,
+            None => context.db.get_corners()?,
+        };
+        Ok(corners.into_iter().map(CornerNode::from).collect())
+    }
+
+    fn groups(context: &Context) -> FieldResult<Vec<GroupNode>> {
+        Ok(context
+            .db
+            .get_groups()?
+            .into_iter()
+            .map(GroupNode::from)
+            .collect())
+    }
+}
+
+pub struct Mutation;
+
+#[juniper::object(Context = Context)]
+impl Mutation {
+    fn add_corner(context: &Context, corner: CornerInput) -> FieldResult<CornerNode> {
+        Ok(context.db.add_corner(corner.into())?.into())
+    }
+
+    /// Заменяет все поля `CornerInput`, незаданные очищаются
+    fn update_corner(context: &Context, id: i32, corner: CornerInput) -> FieldResult<CornerNode> {
+        Ok(context.db.update_corner(id as u32, corner.into())?.into())
+    }
+
+    fn archive_corner(context: &Context, id: i32, archived: bool) -> FieldResult<CornerNode> {
+        Ok(context.db.set_corner_archived(id as u32, archived)?.into())
+    }
+
+    /// Удаляет точку без истории, иначе ошибка и точку надо архивировать
+    fn remove_corner(context: &Context, id: i32) -> FieldResult<bool> {
+        context.db.remove_corner(id as u32)?;
+        Ok(true)
+    }
+
+    fn add_group(
+        context: &Context,
+        name: String,
+        parent_id: Option<i32>,
+    ) -> FieldResult<GroupNode> {
+        let parent_id = parent_id.map(|id| id as u32);
+        Ok(context.db.add_group(&name, parent_id)?.into())
+    }
+
+    fn rename_group(context: &Context, id: i32, name: String) -> FieldResult<GroupNode> {
+        Ok(context.db.rename_group(id as u32, &name)?.into())
+    }
+
+    /// Переносит группу под другого родителя, `null` - на верхний уровень
+    fn move_group(context: &Context, id: i32, parent_id: Option<i32>) -> FieldResult<GroupNode> {
+        let parent_id = parent_id.map(|id| id as u32);
+        Ok(context.db.move_group(id as u32, parent_id)?.into())
+    }
+
+    /// Удаляет пустую группу
+    fn remove_group(context: &Context, id: i32) -> FieldResult<bool> {
+        context.db.remove_group(id as u32)?;
+        Ok(true)
+    }
+
+    /// Включает точку в группу, `null` - убирает из всех
+    fn set_corner_group(
+        context: &Context,
+        corner_id: i32,
+        group_id: Option<i32>,
+    ) -> FieldResult<CornerNode> {
+        let group_id = group_id.map(|id| id as u32);
+        Ok(context
+            .db
+            .set_corner_group(corner_id as u32, group_id)?
+            .into())
+    }
+
+    /// Назначает роль собеседнику. Администратор с `groupId` управляет
+    /// этой группой со всеми подгруппами, без него - всей сетью.
+    fn set_chat_role(
+        context: &Context,
+        chat_id: String,
+        role: ChatRole,
+        group_id: Option<i32>,
+    ) -> FieldResult<ChatNode> {
+        let id: i64 = chat_id
+            .trim()
+            .parse()
+            .map_err(|_| FieldError::from(format!("bad chat id {:?}", chat_id)))?;
+        let role = match (role, group_id) {
+            (ChatRole::Staff, None) => Role::Staff,
+            (ChatRole::Staff, Some(_)) => {
+                return Err(FieldError::from("staff can't have a group"));
+            }
+            (ChatRole::Admin, group) => Role::Admin(group.map(|id| id as u32)),
+        };
+        let chat = context.db.set_chat_role(id, role)?;
+        Ok(ChatNode::new(id, chat))
+    }
+}
+
+fn corner_forecast(ctx: &Context, corner: Corner) -> FieldResult<CornerForecast> {
+    let today = corner.day_at(ctx.now.timestamp())?;
+    let bands = forecast::corner_forecast(&ctx.db, corner.id, today)
+        .map_err(|e| FieldError::from(e.to_string()))?;
+    let (next_week, next_month) = match bands {
+        Some((week, month)) => (Some(week.try_into()?), Some(month.try_into()?)),
+        None => (None, None),
+    };
+    Ok(CornerForecast {
+        corner_id: corner.id as i32,
+        name: corner.name,
+        next_week,
+        next_month,
+    })
+}
+
+pub type Schema = RootNode<'static, Query, Mutation>;
+
+pub fn schema() -> Schema {
+    Schema::new(Query, Mutation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Money;
+    use chrono::{NaiveDate, TimeZone};
+    use juniper::{DefaultScalarValue, Value, Variables};
+
+    #[test]
+    fn forecast_query() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        db.add_corner(CornerInfo {
+            name: "Новая".to_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        // понедельник, 4 недели по 1000 в день
+        let start = Revenue::day(NaiveDate::from_ymd(2020, 3, 2)).unwrap();
+        for date in start..start + 28 {
+            db.put_revenue(&Revenue {
+                corner_id: corner.id,
+                date,
+                amount: Money::rubles(1_000),
+                post_datetime: 0,
+                comment: None,
+                payments: Vec::new(),
+                photo: None,
+                staff: None,
+            })
+            .unwrap();
+        }
+        // день start + 27 в Москве
+        let ctx = Context {
+            db,
+            now: Utc.ymd(2020, 3, 29).and_hms(12, 0, 0),
+        };
+        let query =
+            "{ forecasts { name nextWeek { from to expected low high } nextMonth { from } } }";
+        let (res, errors) =
+            juniper::execute(query, None, &schema(), &Variables::new(), &ctx).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let res: Value<DefaultScalarValue> = res;
+        let json = serde_json::to_value(&res).unwrap();
+        assert_eq!(
+            json["forecasts"][0]["nextWeek"],
+            serde_json::json!({
+                "from": "2020-03-30",
+                "to": "2020-04-05",
+                "expected": 7000.0,
+                "low": 7000.0,
+                "high": 7000.0,
+            })
+        );
+        assert_eq!(json["forecasts"][0]["nextMonth"]["from"], "2020-04-01");
+        assert_eq!(json["forecasts"][1]["name"], "Новая");
+        assert_eq!(json["forecasts"][1]["nextWeek"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn corner_mutations() {
+        let ctx = Context {
+            db: DataBase::temporary(),
+            now: Utc::now(),
+        };
+        let run = |query: &str| {
+            let (res, errors) =
+                juniper::execute(query, None, &schema(), &Variables::new(), &ctx).unwrap();
+            let res: Value<DefaultScalarValue> = res;
+            (serde_json::to_value(&res).unwrap(), errors)
+        };
+        let (json, errors) = run(
+            r#"mutation { addCorner(corner: { name: "Галерея", tags: ["ТЦ"] }) { id name tags } }"#,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(json["addCorner"]["tags"], serde_json::json!(["тц"]));
+        let id = json["addCorner"]["id"].as_i64().unwrap();
+
+        let (_, errors) = run(r#"mutation { addCorner(corner: { name: "галерея" }) { id } }"#);
+        assert_eq!(errors.len(), 1);
+
+        let (json, errors) = run(&format!(
+            r#"mutation {{ updateCorner(id: {}, corner: {{ name: "Галерея", address: "Ленина, 1" }}) {{ address tags }} }}"#,
+            id
+        ));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(json["updateCorner"]["address"], "Ленина, 1");
+        assert_eq!(json["updateCorner"]["tags"], serde_json::json!([]));
+
+        run(r#"mutation { addCorner(corner: { name: "Вокзал", tags: ["улица"] }) { id } }"#);
+        let (json, _) = run(r#"{ corners(tag: "Улица") { name } }"#);
+        assert_eq!(json["corners"], serde_json::json!([{ "name": "Вокзал" }]));
+
+        let (json, errors) = run(&format!(
+            "mutation {{ archiveCorner(id: {}, archived: true) {{ archived }} }}",
+            id
+        ));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(json["archiveCorner"]["archived"], true);
+
+        let (json, errors) = run(&format!("mutation {{ removeCorner(id: {}) }}", id));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(json["removeCorner"], true);
+        let (json, _) = run("{ corners { name } }");
+        assert_eq!(json["corners"], serde_json::json!([{ "name": "Вокзал" }]));
+    }
+
+    #[test]
+    fn group_mutations() {
+        let ctx = Context {
+            db: DataBase::temporary(),
+            now: Utc::now(),
+        };
+        let corner = ctx
+            .db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let run = |query: &str| {
+            let (res, errors) =
+                juniper::execute(query, None, &schema(), &Variables::new(), &ctx).unwrap();
+            assert!(errors.is_empty(), "{:?}", errors);
+            let res: Value<DefaultScalarValue> = res;
+            serde_json::to_value(&res).unwrap()
+        };
+        let json = run(r#"mutation { addGroup(name: "Питер") { id } }"#);
+        let city = json["addGroup"]["id"].as_i64().unwrap();
+        let json = run(&format!(
+            r#"mutation {{ renameGroup(id: {}, name: "Санкт-Петербург") {{ name }} }}"#,
+            city
+        ));
+        assert_eq!(json["renameGroup"]["name"], "Санкт-Петербург");
+        let json = run(&format!(
+            "mutation {{ setCornerGroup(cornerId: {}, groupId: {}) {{ groupId }} }}",
+            corner.id, city
+        ));
+        assert_eq!(json["setCornerGroup"]["groupId"], city);
+        let json = run("{ groups { name parentId } }");
+        assert_eq!(
+            json["groups"],
+            serde_json::json!([{ "name": "Санкт-Петербург", "parentId": null }])
+        );
+
+        let chat_id = 5_000_000_000i64;
+        let chat = Chat {
+            corner_id: corner.id,
+            name: "Ольга".to_owned(),
+            is_active: true,
+            role: Role::Staff,
+            pay: None,
+            lang: None,
+        };
+        ctx.db.put_chat(chat_id, &chat).unwrap();
+        let json = run(&format!(
+            r#"mutation {{ setChatRole(chatId: "{}", role: ADMIN, groupId: {}) {{ id role groupId }} }}"#,
+            chat_id, city
+        ));
+        assert_eq!(
+            json["setChatRole"],
+            serde_json::json!({ "id": "5000000000", "role": "ADMIN", "groupId": city })
+        );
+        let chat = ctx.db.get_chat(chat_id).unwrap().unwrap();
+        assert_eq!(chat.role, Role::Admin(Some(city as u32)));
+        assert!(ctx.db.can_manage(&chat, corner.id).unwrap());
+
+        let query = format!(
+            r#"mutation {{ setChatRole(chatId: "{}", role: STAFF, groupId: {}) {{ id }} }}"#,
+            chat_id, city
+        );
+        let (_, errors) =
+            juniper::execute(&query, None, &schema(), &Variables::new(), &ctx).unwrap();
+        assert_eq!(errors.len(), 1);
+        let json = run(&format!(
+            r#"mutation {{ setChatRole(chatId: "{}", role: ADMIN) {{ role groupId }} }}"#,
+            chat_id
+        ));
+        assert_eq!(
+            json["setChatRole"],
+            serde_json::json!({ "role": "ADMIN", "groupId": null })
+        );
+    }
+}
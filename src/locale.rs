@@ -0,0 +1,863 @@
+//! Тексты бота на русском и английском. Язык берется из `language_code`
+//! пользователя Telegram, а командой /lang его можно закрепить за чатом.
+
+use crate::money::Money;
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Lang {
+    #[default]
+    Ru,
+    En,
+}
+
+/// Сообщения без подстановок
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Msg {
+    Guest,
+    FailCode,
+    Inactive,
+    Help,
+    DbError,
+    DbConflict,
+    NoCorner,
+    NotAdmin,
+    MethodUsage,
+    ExpenseUsage,
+    DayUsage,
+    TargetUsage,
+    PhotoUsage,
+    ZreportUsage,
+    NoRevenue,
+    ShiftUsage,
+    NoShift,
+    ShiftOpen,
+    PayUsage,
+    AnomalyUsage,
+    ChartUsage,
+    TzUsage,
+    LangUsage,
+    LangSet,
+    NothingPending,
+    Cancelled,
+    BadButton,
+    CommandsSet,
+    AllRight,
+    Cancel,
+    SendPhoto,
+    NoPayScheme,
+    StaffNotFound,
+    StaffAmbiguous,
+    MethodExists,
+    MethodNotFound,
+    MethodsTitle,
+    MethodOff,
+    MethodsHint,
+    ExpensesTitle,
+    HasReceipt,
+    NoPhotoFlag,
+    NotReported,
+    NoData,
+    NoStaff,
+    ForecastTitle,
+    FewData,
+    AllCorners,
+}
+
+/// Формы слова для согласования с числом: по-русски "рубль, рубля,
+/// рублей", по-английски "ruble, rubles"
+pub struct Noun {
+    ru: [&'static str; 3],
+    en: [&'static str; 2],
+}
+
+pub const DAYS: Noun = Noun {
+    ru: ["день", "дня", "дней"],
+    en: ["day", "days"],
+};
+pub const HOURS: Noun = Noun {
+    ru: ["час", "часа", "часов"],
+    en: ["hour", "hours"],
+};
+pub const MINUTES: Noun = Noun {
+    ru: ["минута", "минуты", "минут"],
+    en: ["minute", "minutes"],
+};
+
+/// Команды для меню бота с описаниями на русском и английском,
+/// по `/commands` его обновляет администратор
+const COMMANDS: &[(&str, &str, &str)] = &[
+    ("day", "Итоги за сегодня", "Today's summary"),
+    ("month", "Итоги за месяц", "Month summary"),
+    ("chart", "Графики выручки", "Revenue charts"),
+    ("forecast", "Прогноз выручки", "Revenue forecast"),
+    ("expense", "Записать расход", "Add an expense"),
+    ("expenses", "Расходы за сегодня", "Today's expenses"),
+    ("open", "Открыть смену", "Open a shift"),
+    ("close", "Закрыть смену", "Close the shift"),
+    ("shift", "Текущая смена", "Current shift"),
+    ("salary", "Заработок за месяц", "Earnings this month"),
+    ("confirm", "Подтвердить выручку", "Confirm revenue"),
+    ("lang", "Язык бота", "Bot language"),
+];
+
+const MONTHS_EN: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+impl Lang {
+    /// Язык по `language_code` из Telegram. Без него остается русский,
+    /// для остальных незнакомых языков - английский.
+    pub fn from_code(code: Option<&str>) -> Lang {
+        let code = match code {
+            Some(code) => code.to_lowercase(),
+            None => return Lang::Ru,
+        };
+        let base = code.split(['-', '_']).next().unwrap_or("");
+        match base {
+            "ru" | "uk" | "be" | "kk" => Lang::Ru,
+            _ => Lang::En,
+        }
+    }
+
+    /// Аргумент команды /lang
+    pub fn parse(s: &str) -> Option<Lang> {
+        match s.to_lowercase().as_str() {
+            "ru" | "рус" | "русский" => Some(Lang::Ru),
+            "en" | "eng" | "english" => Some(Lang::En),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::Ru => "ru",
+            Lang::En => "en",
+        }
+    }
+
+    pub fn text(self, msg: Msg) -> &'static str {
+        let (ru, en) = match msg {
+            Msg::Guest => ("Ведите код приглашения", "Enter your invite code"),
+            Msg::FailCode => (
+                "Код не найден.
+    \nВозможно у вас опечатка, либо срок действия кода истек.
+    \nПроверьте правильность написания и попробуйте еще раз",
+                "Code not found.\n\nIt may have a typo or it has expired. \
+                Check the spelling and try again",
+            ),
+            Msg::Inactive => (
+                "Ваш профиль был заблокирован администратором",
+                "Your profile has been blocked by an administrator",
+            ),
+            Msg::Help => ("Помощь", "Help"),
+            Msg::DbError => (
+                "Что-то пошло не так. Попробуйте повторить чуть позже",
+                "Something went wrong. Please try again a bit later",
+            ),
+            Msg::DbConflict => (
+                "Данные только что изменились. Повторите, пожалуйста, еще раз",
+                "The data has just changed. Please try again",
+            ),
+            Msg::NoCorner => (
+                "Ваш профиль не привязан к точке. Обратитесь к администратору",
+                "Your profile is not linked to a location. Contact an administrator",
+            ),
+            Msg::NotAdmin => (
+                "Эта команда доступна только администратору точки",
+                "Only a location administrator can use this command",
+            ),
+            Msg::MethodUsage => (
+                "Формат: /method add <код> <название>, /method off <код>, /method on <код>",
+                "Usage: /method add <code> <name>, /method off <code>, /method on <code>",
+            ),
+            Msg::ExpenseUsage => (
+                "Формат: /expense <категория> <сумма> [комментарий], \
+                к сообщению можно приложить фото чека. Удалить: /expense del <номер из /expenses>",
+                "Usage: /expense <category> <amount> [comment], a receipt photo \
+                can be attached. Delete: /expense del <number from /expenses>",
+            ),
+            Msg::DayUsage => (
+                "Формат: /day [дата], например /day вчера, /day 15.03, /day 15 марта",
+                "Usage: /day [date], e.g. /day yesterday, /day 15.03, /day 15.03.2020",
+            ),
+            Msg::TargetUsage => (
+                "Формат: /target <точка> <день|месяц> <сумма> [дата: 15.03, 15 марта]",
+                "Usage: /target <location> <day|month> <amount> [date: 15.03, 15.03.2020]",
+            ),
+            Msg::PhotoUsage => (
+                "Формат: /photo <точка> [дата: вчера, 15.03, 15 марта]",
+                "Usage: /photo <location> [date: yesterday, 15.03, 15.03.2020]",
+            ),
+            Msg::ZreportUsage => (
+                "Формат: /zreport <точка> on|off - требовать ли фото Z-отчета",
+                "Usage: /zreport <location> on|off - whether a Z-report photo is required",
+            ),
+            Msg::NoRevenue => (
+                "Сначала пришлите выручку за сегодня, \
+                фото Z-отчета можно приложить прямо к сообщению с суммой",
+                "Send today's revenue first, \
+                the Z-report photo can be attached to the message with the amount",
+            ),
+            Msg::ShiftUsage => (
+                "Формат: /open <наличные в кассе>, /close <наличные в кассе>, /shift",
+                "Usage: /open <cash in till>, /close <cash in till>, /shift",
+            ),
+            Msg::NoShift => (
+                "Открытой смены нет. Начните ее командой /open <наличные в кассе>",
+                "No shift is open. Start one with /open <cash in till>",
+            ),
+            Msg::ShiftOpen => (
+                "Смена уже открыта, посмотреть: /shift",
+                "A shift is already open, see /shift",
+            ),
+            Msg::PayUsage => (
+                "Формат: /pay <имя> [смена <ставка>] [процент <2,5>] [порог <сумма>] \
+                или /pay <имя> off. Процент берется с выручки дня сверх порога",
+                "Usage: /pay <name> [shift <rate>] [percent <2.5>] [threshold <amount>] \
+                or /pay <name> off. The percentage applies to daily revenue above the threshold",
+            ),
+            Msg::AnomalyUsage => (
+                "Формат: /anomaly <точка> <кратность больше 1, например 2,5|off>",
+                "Usage: /anomaly <location> <ratio above 1, e.g. 2.5|off>",
+            ),
+            Msg::ChartUsage => (
+                "Формат: /chart [week|month] [точка]",
+                "Usage: /chart [week|month] [location]",
+            ),
+            Msg::TzUsage => (
+                "Формат: /tz <точка> [пояс, например Asia/Vladivostok]",
+                "Usage: /tz <location> [time zone, e.g. Asia/Vladivostok]",
+            ),
+            Msg::LangUsage => (
+                "Формат: /lang ru|en|auto, auto - язык из настроек Telegram",
+                "Usage: /lang ru|en|auto, auto - the language of your Telegram settings",
+            ),
+            Msg::LangSet => ("Язык бота - русский", "Bot language is English"),
+            Msg::NothingPending => ("Подтверждать нечего", "Nothing to confirm"),
+            Msg::Cancelled => (
+                "Сумма не записана, пришлите верную",
+                "The amount was not saved, send the correct one",
+            ),
+            Msg::BadButton => (
+                "Кнопка устарела, повторите команду",
+                "This button is outdated, repeat the command",
+            ),
+            Msg::CommandsSet => ("Меню команд обновлено", "The command menu is updated"),
+            Msg::AllRight => ("Все верно", "Correct"),
+            Msg::Cancel => ("Отменить", "Cancel"),
+            Msg::SendPhoto => (
+                "Пришлите, пожалуйста, фото Z-отчета",
+                "Please send a photo of the Z-report",
+            ),
+            Msg::NoPayScheme => (
+                "Схема оплаты не задана. Обратитесь к администратору",
+                "No pay scheme is set. Contact an administrator",
+            ),
+            Msg::StaffNotFound => ("Сотрудник не найден", "Employee not found"),
+            Msg::StaffAmbiguous => (
+                "Сотрудников с таким именем несколько",
+                "There are several employees with this name",
+            ),
+            Msg::MethodExists => (
+                "Такой способ оплаты уже есть",
+                "This payment method already exists",
+            ),
+            Msg::MethodNotFound => ("Способ оплаты не найден", "Payment method not found"),
+            Msg::MethodsTitle => ("Способы оплаты:", "Payment methods:"),
+            Msg::MethodOff => (" (выключен)", " (off)"),
+            Msg::MethodsHint => (
+                "Выручку можно прислать так: нал 5000, карта 7000",
+                "Revenue can be sent like this: нал 5000, карта 7000",
+            ),
+            Msg::ExpensesTitle => ("Расходы за сегодня:", "Today's expenses:"),
+            Msg::HasReceipt => (" (есть чек)", " (receipt attached)"),
+            Msg::NoPhotoFlag => (", нет фото Z-отчета", ", no Z-report photo"),
+            Msg::NotReported => ("нет данных", "no data"),
+            Msg::NoData => ("Данных нет", "No data"),
+            Msg::NoStaff => ("Без сотрудника", "No employee"),
+            Msg::ForecastTitle => ("Прогноз выручки", "Revenue forecast"),
+            Msg::FewData => ("мало данных", "not enough data"),
+            Msg::AllCorners => ("все точки", "all locations"),
+        };
+        match self {
+            Lang::Ru => ru,
+            Lang::En => en,
+        }
+    }
+
+    /// Команды меню бота с описаниями на этом языке
+    pub fn commands(self) -> impl Iterator<Item = (&'static str, &'static str)> {
+        COMMANDS.iter().map(move |&(command, ru, en)| match self {
+            Lang::Ru => (command, ru),
+            Lang::En => (command, en),
+        })
+    }
+
+    /// Форма слова для числа `n`
+    pub fn plural(self, n: u64, noun: &Noun) -> &'static str {
+        match self {
+            Lang::Ru => match (n % 10, n % 100) {
+                (1, m) if m != 11 => noun.ru[0],
+                (2..=4, m) if !(12..=14).contains(&m) => noun.ru[1],
+                _ => noun.ru[2],
+            },
+            Lang::En if n == 1 => noun.en[0],
+            Lang::En => noun.en[1],
+        }
+    }
+
+    /// Число вместе с согласованным словом: "21 день", "5 days"
+    pub fn count(self, n: u64, noun: &Noun) -> String {
+        format!("{} {}", n, self.plural(n, noun))
+    }
+
+    /// Сумма с копейками, если они есть. По-русски разряды отделяются
+    /// пробелом, только если в рублях больше четырех цифр: "1500 ₽",
+    /// "12 345,50 ₽"; по-английски запятой: "₽1,500.50".
+    pub fn money(self, amount: Money) -> String {
+        let kopecks = amount.kopecks();
+        let digits = (kopecks.unsigned_abs() / 100).to_string();
+        let sign = if kopecks < 0 { "-" } else { "" };
+        let (sep, point, min_len) = match self {
+            Lang::Ru => (' ', ',', 5),
+            Lang::En => (',', '.', 4),
+        };
+        let mut grouped = String::new();
+        for (i, c) in digits.chars().enumerate() {
+            if digits.len() >= min_len && i > 0 && (digits.len() - i).is_multiple_of(3) {
+                grouped.push(sep);
+            }
+            grouped.push(c);
+        }
+        match kopecks.unsigned_abs() % 100 {
+            0 => {}
+            k => grouped += &format!("{}{:02}", point, k),
+        }
+        match self {
+            Lang::Ru => format!("{}{} ₽", sign, grouped),
+            Lang::En => format!("{}₽{}", sign, grouped),
+        }
+    }
+
+    /// Полная дата: "15.03.2020", "Mar 15, 2020"
+    pub fn date(self, date: NaiveDate) -> String {
+        match self {
+            Lang::Ru => date.format("%d.%m.%Y").to_string(),
+            Lang::En => format!("{}, {}", self.day_month(date), date.year()),
+        }
+    }
+
+    /// День и месяц: "15.03", "Mar 15"
+    pub fn day_month(self, date: NaiveDate) -> String {
+        match self {
+            Lang::Ru => date.format("%d.%m").to_string(),
+            Lang::En => format!("{} {}", MONTHS_EN[date.month0() as usize], date.day()),
+        }
+    }
+
+    /// Месяц и год: "03.2020", "Mar 2020"
+    pub fn month_year(self, date: NaiveDate) -> String {
+        match self {
+            Lang::Ru => date.format("%m.%Y").to_string(),
+            Lang::En => format!("{} {}", MONTHS_EN[date.month0() as usize], date.year()),
+        }
+    }
+
+    /// Период из дней: "01.03-07.03", "Mar 1-Mar 7"
+    pub fn period(self, from: NaiveDate, to: NaiveDate) -> String {
+        format!("{}-{}", self.day_month(from), self.day_month(to))
+    }
+
+    /// Длительность: "2 часа 5 минут"
+    pub fn duration(self, minutes: u64) -> String {
+        format!(
+            "{} {}",
+            self.count(minutes / 60, &HOURS),
+            self.count(minutes % 60, &MINUTES)
+        )
+    }
+
+    /// Доля в сотых ("2,5" - 250) с разделителем языка: "2,50", "2.50"
+    fn hundredths(self, n: u32) -> String {
+        let point = match self {
+            Lang::Ru => ',',
+            Lang::En => '.',
+        };
+        format!("{}{}{:02}", n / 100, point, n % 100)
+    }
+
+    fn pick(self, ru: String, en: String) -> String {
+        match self {
+            Lang::Ru => ru,
+            Lang::En => en,
+        }
+    }
+}
+
+/// Сообщения с подстановками
+impl Lang {
+    pub fn revenue_accepted(self, date: NaiveDate, amount: Money) -> String {
+        let (date, amount) = (self.day_month(date), self.money(amount));
+        self.pick(
+            format!("Выручка за {} принята: {}", date, amount),
+            format!("Revenue for {} accepted: {}", date, amount),
+        )
+    }
+
+    /// Прежняя сумма при исправлении выручки
+    pub fn was(self, old: Money) -> String {
+        let old = self.money(old);
+        self.pick(format!(" (было {})", old), format!(" (was {})", old))
+    }
+
+    pub fn whose_shift(self, name: &str) -> String {
+        self.pick(format!(", смена {}", name), format!(", {}'s shift", name))
+    }
+
+    /// Вопрос о выручке, которая `higher` больше или меньше обычной
+    pub fn unusual_revenue(self, amount: Money, usual: Money, higher: bool) -> String {
+        let (amount, usual) = (self.money(amount), self.money(usual));
+        match self {
+            Lang::Ru => format!(
+                "Сумма {} намного {} обычной (около {} в день). \
+                Если все верно, отправьте /confirm, если нет - пришлите сумму еще раз",
+                amount,
+                if higher {
+                    "больше"
+                } else {
+                    "меньше"
+                },
+                usual
+            ),
+            Lang::En => format!(
+                "The amount {} is much {} than usual (about {} a day). \
+                If it is correct, send /confirm, otherwise send the amount again",
+                amount,
+                if higher { "higher" } else { "lower" },
+                usual
+            ),
+        }
+    }
+
+    pub fn unusual_confirmed(self, corner: &str, amount: Money, date: NaiveDate) -> String {
+        let (amount, date) = (self.money(amount), self.day_month(date));
+        self.pick(
+            format!(
+                "{}: подтверждена необычная выручка {} за {}",
+                corner, amount, date
+            ),
+            format!(
+                "{}: unusual revenue {} for {} confirmed",
+                corner, amount, date
+            ),
+        )
+    }
+
+    pub fn usually(self, usual: Money) -> String {
+        let usual = self.money(usual);
+        self.pick(
+            format!(", обычно около {}", usual),
+            format!(", usually about {}", usual),
+        )
+    }
+
+    pub fn sent_by(self, staff: &str) -> String {
+        self.pick(
+            format!(". Отправил сотрудник {}", staff),
+            format!(". Sent by {}", staff),
+        )
+    }
+
+    /// `ratio` в десятых, 0 - проверка выключена
+    pub fn anomaly_set(self, corner: &str, ratio: u32) -> String {
+        let (whole, tenths) = (ratio / 10, ratio % 10);
+        match (self, ratio) {
+            (Lang::Ru, 0) => format!("{}: проверка выручки выключена", corner),
+            (Lang::En, 0) => format!("{}: revenue check is off", corner),
+            (Lang::Ru, _) => format!(
+                "{}: подтверждение, если выручка в {},{} раза больше или меньше обычной",
+                corner, whole, tenths
+            ),
+            (Lang::En, _) => format!(
+                "{}: confirmation if revenue is {}.{} times above or below usual",
+                corner, whole, tenths
+            ),
+        }
+    }
+
+    pub fn timezone(self, corner: &str, tz: &str, now: &str) -> String {
+        self.pick(
+            format!("{}: часовой пояс {}, там сейчас {}", corner, tz, now),
+            format!("{}: time zone {}, local time {}", corner, tz, now),
+        )
+    }
+
+    pub fn charts_for(self, from: NaiveDate, to: NaiveDate) -> String {
+        let period = self.period(from, to);
+        self.pick(
+            format!("Графики за {}", period),
+            format!("Charts for {}", period),
+        )
+    }
+
+    /// План `amount` в день или, при `monthly`, в месяц
+    pub fn target_set(self, corner: &str, from: NaiveDate, amount: Money, monthly: bool) -> String {
+        let (from, amount) = (self.date(from), self.money(amount));
+        match self {
+            Lang::Ru => format!(
+                "План для {} с {}: {} в {}",
+                corner,
+                from,
+                amount,
+                if monthly { "месяц" } else { "день" }
+            ),
+            Lang::En => format!(
+                "Target for {} from {}: {} a {}",
+                corner,
+                from,
+                amount,
+                if monthly { "month" } else { "day" }
+            ),
+        }
+    }
+
+    /// `opened` и `closed` - время "ЧЧ:ММ"
+    pub fn shift_closed(
+        self,
+        opened: &str,
+        closed: &str,
+        minutes: u64,
+        cash: Money,
+        open_cash: Money,
+    ) -> String {
+        let length = self.duration(minutes);
+        let (cash, open_cash) = (self.money(cash), self.money(open_cash));
+        self.pick(
+            format!(
+                "Смена {}-{} закрыта ({}). В кассе {}, на открытии было {}",
+                opened, closed, length, cash, open_cash
+            ),
+            format!(
+                "Shift {}-{} closed ({}). {} in the till, {} at opening",
+                opened, closed, length, cash, open_cash
+            ),
+        )
+    }
+
+    pub fn shift_opened(self, opened: &str, cash: Money) -> String {
+        let cash = self.money(cash);
+        self.pick(
+            format!("Смена открыта в {}, в кассе {}", opened, cash),
+            format!("Shift opened at {}, {} in the till", opened, cash),
+        )
+    }
+
+    /// Начисления с `from` по `to`: ставка за смены, процент
+    /// с выручки `revenue` и итог
+    pub fn salary(
+        self,
+        from: NaiveDate,
+        to: NaiveDate,
+        shifts: u32,
+        fixed: Money,
+        revenue: Money,
+        bonus: Money,
+    ) -> String {
+        let (from, to) = (self.day_month(from), self.day_month(to));
+        let total = self.money(fixed + bonus);
+        let (fixed, revenue, bonus) = (self.money(fixed), self.money(revenue), self.money(bonus));
+        self.pick(
+            format!(
+                "Зарплата с {} по {}: смен {} - {}, процент с выручки {} - {}. Итого {}",
+                from, to, shifts, fixed, revenue, bonus, total
+            ),
+            format!(
+                "Pay from {} to {}: {} shifts - {}, percentage of revenue {} - {}. Total {}",
+                from, to, shifts, fixed, revenue, bonus, total
+            ),
+        )
+    }
+
+    pub fn pay_off(self, name: &str) -> String {
+        self.pick(
+            format!("{}: зарплата не считается", name),
+            format!("{}: pay is not calculated", name),
+        )
+    }
+
+    /// `percent` в сотых долях процента
+    pub fn pay_scheme(
+        self,
+        name: &str,
+        per_shift: Money,
+        percent: u32,
+        threshold: Money,
+    ) -> String {
+        let (per_shift, threshold) = (self.money(per_shift), self.money(threshold));
+        let percent = self.hundredths(percent);
+        self.pick(
+            format!(
+                "{}: {} за смену, {}% с выручки дня сверх {}",
+                name, per_shift, percent, threshold
+            ),
+            format!(
+                "{}: {} per shift, {}% of daily revenue above {}",
+                name, per_shift, percent, threshold
+            ),
+        )
+    }
+
+    pub fn photo_saved(self, date: NaiveDate, amount: Money) -> String {
+        let (date, amount) = (self.day_month(date), self.money(amount));
+        self.pick(
+            format!("Фото Z-отчета к выручке за {} ({}) сохранено", date, amount),
+            format!("Z-report photo for revenue on {} ({}) saved", date, amount),
+        )
+    }
+
+    pub fn no_photo(self, corner: &str, date: NaiveDate) -> String {
+        let date = self.date(date);
+        self.pick(
+            format!("Фото Z-отчета {} за {} нет", corner, date),
+            format!("No Z-report photo for {} on {}", corner, date),
+        )
+    }
+
+    pub fn photo_required(self, corner: &str, required: bool) -> String {
+        match (self, required) {
+            (Lang::Ru, true) => format!("{}: фото Z-отчета обязательно", corner),
+            (Lang::Ru, false) => format!("{}: фото Z-отчета не обязательно", corner),
+            (Lang::En, true) => format!("{}: Z-report photo is required", corner),
+            (Lang::En, false) => format!("{}: Z-report photo is optional", corner),
+        }
+    }
+
+    pub fn expense_added(self, category: &str, amount: Money, total: Money, net: Money) -> String {
+        let (amount, total, net) = (self.money(amount), self.money(total), self.money(net));
+        self.pick(
+            format!(
+                "Расход записан: {} {}\nРасходы за день: {}, чистыми {}",
+                category, amount, total, net
+            ),
+            format!(
+                "Expense saved: {} {}\nExpenses for the day: {}, net {}",
+                category, amount, total, net
+            ),
+        )
+    }
+
+    pub fn no_expenses(self, categories: &str) -> String {
+        self.pick(
+            format!("Расходов за сегодня нет. Категории: {}", categories),
+            format!("No expenses today. Categories: {}", categories),
+        )
+    }
+
+    pub fn day_title(self, date: NaiveDate) -> String {
+        let date = self.date(date);
+        self.pick(
+            format!("Итоги за {}", date),
+            format!("Summary for {}", date),
+        )
+    }
+
+    pub fn net(self, expenses: Money, net: Money) -> String {
+        let (expenses, net) = (self.money(expenses), self.money(net));
+        self.pick(
+            format!("Расходы: {}, чистыми {}", expenses, net),
+            format!("Expenses: {}, net {}", expenses, net),
+        )
+    }
+
+    /// Выполнение плана месяца: факт, план, процент плана на дату,
+    /// прогноз на месяц и его процент
+    pub fn plan(
+        self,
+        actual: Money,
+        plan: Money,
+        percent: u64,
+        forecast: Money,
+        forecast_percent: u64,
+    ) -> String {
+        let (actual, plan, forecast) = (self.money(actual), self.money(plan), self.money(forecast));
+        self.pick(
+            format!(
+                "План месяца: {} из {}, {}% плана на дату. Прогноз {} ({}%)",
+                actual, plan, percent, forecast, forecast_percent
+            ),
+            format!(
+                "Month target: {} of {}, {}% of target to date. Forecast {} ({}%)",
+                actual, plan, percent, forecast, forecast_percent
+            ),
+        )
+    }
+
+    pub fn month_title(self, month_start: NaiveDate) -> String {
+        let month = self.month_year(month_start);
+        self.pick(
+            format!("Выручка за {}", month),
+            format!("Revenue for {}", month),
+        )
+    }
+
+    pub fn total(self, amount: Money) -> String {
+        let amount = self.money(amount);
+        self.pick(format!("Итого: {}", amount), format!("Total: {}", amount))
+    }
+
+    pub fn staff_title(self, month_start: NaiveDate) -> String {
+        let month = self.month_year(month_start);
+        self.pick(
+            format!("Сотрудники за {}", month),
+            format!("Staff for {}", month),
+        )
+    }
+
+    pub fn staff_revenue(self, name: &str, revenue: Money, days: u64) -> String {
+        let (revenue, days) = (self.money(revenue), self.count(days, &DAYS));
+        self.pick(
+            format!("{}: {} за {}", name, revenue, days),
+            format!("{}: {} in {}", name, revenue, days),
+        )
+    }
+
+    pub fn staff_shifts(self, shifts: u32, seconds: u64) -> String {
+        let hours = self.count(seconds / 3600, &HOURS);
+        self.pick(
+            format!(", смен {} ({})", shifts, hours),
+            format!(", shifts: {} ({})", shifts, hours),
+        )
+    }
+
+    /// Ожидаемая выручка за период и интервал вокруг нее
+    pub fn forecast(
+        self,
+        from: NaiveDate,
+        to: NaiveDate,
+        expected: Money,
+        low: Money,
+        high: Money,
+    ) -> String {
+        let period = self.period(from, to);
+        let (expected, low, high) = (self.money(expected), self.money(low), self.money(high));
+        self.pick(
+            format!("{}: {} (от {} до {})", period, expected, low, high),
+            format!("{}: {} ({} to {})", period, expected, low, high),
+        )
+    }
+
+    pub fn chart_days(self, from: NaiveDate, to: NaiveDate, whose: &str) -> String {
+        let period = self.period(from, to);
+        self.pick(
+            format!("Выручка по дням {}, {}, тыс. ₽", period, whose),
+            format!("Daily revenue {}, {}, ₽ thousands", period, whose),
+        )
+    }
+
+    pub fn chart_corners(self, from: NaiveDate, to: NaiveDate, legend: &str) -> String {
+        let period = self.period(from, to);
+        self.pick(
+            format!("Точки за {}, тыс. ₽: {}", period, legend),
+            format!("Locations for {}, ₽ thousands: {}", period, legend),
+        )
+    }
+
+    pub fn chart_plan(self, date: NaiveDate, legend: &str) -> String {
+        let date = self.day_month(date);
+        self.pick(
+            format!("План на {} (серым) и факт, тыс. ₽: {}", date, legend),
+            format!(
+                "Target to {} (grey) and actual, ₽ thousands: {}",
+                date, legend
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUBLES: Noun = Noun {
+        ru: ["рубль", "рубля", "рублей"],
+        en: ["ruble", "rubles"],
+    };
+
+    #[test]
+    fn russian_plurals() {
+        let rubles = |n| Lang::Ru.count(n, &RUBLES);
+        assert_eq!(rubles(1), "1 рубль");
+        assert_eq!(rubles(2), "2 рубля");
+        assert_eq!(rubles(5), "5 рублей");
+        assert_eq!(rubles(0), "0 рублей");
+        assert_eq!(rubles(11), "11 рублей");
+        assert_eq!(rubles(12), "12 рублей");
+        assert_eq!(rubles(14), "14 рублей");
+        assert_eq!(rubles(21), "21 рубль");
+        assert_eq!(rubles(22), "22 рубля");
+        assert_eq!(rubles(111), "111 рублей");
+        assert_eq!(rubles(1001), "1001 рубль");
+        assert_eq!(Lang::Ru.count(3, &HOURS), "3 часа");
+        assert_eq!(Lang::En.count(1, &DAYS), "1 day");
+        assert_eq!(Lang::En.count(0, &DAYS), "0 days");
+        assert_eq!(Lang::En.count(21, &MINUTES), "21 minutes");
+    }
+
+    #[test]
+    fn money_and_dates() {
+        let rub = Money::rubles;
+        assert_eq!(Lang::Ru.money(rub(1500)), "1500 ₽");
+        assert_eq!(Lang::Ru.money(rub(12_345)), "12 345 ₽");
+        assert_eq!(Lang::Ru.money(rub(-1_234_567)), "-1 234 567 ₽");
+        assert_eq!(Lang::Ru.money(Money::from_kopecks(5)), "0,05 ₽");
+        assert_eq!(Lang::En.money(rub(1500)), "₽1,500");
+        assert_eq!(Lang::En.money(rub(-999)), "-₽999");
+        assert_eq!(Lang::En.money(rub(100_000)), "₽100,000");
+        let date = NaiveDate::from_ymd(2020, 3, 5);
+        assert_eq!(Lang::Ru.date(date), "05.03.2020");
+        assert_eq!(Lang::Ru.day_month(date), "05.03");
+        assert_eq!(Lang::En.date(date), "Mar 5, 2020");
+        assert_eq!(Lang::En.day_month(date), "Mar 5");
+    }
+
+    #[test]
+    fn messages_with_values() {
+        let rub = Money::rubles;
+        assert_eq!(
+            Lang::Ru.pay_scheme("Иван", rub(1500), 250, rub(10_000)),
+            "Иван: 1500 ₽ за смену, 2,50% с выручки дня сверх 10 000 ₽"
+        );
+        assert_eq!(
+            Lang::En.pay_scheme("Ivan", rub(1500), 250, rub(10_000)),
+            "Ivan: ₽1,500 per shift, 2.50% of daily revenue above ₽10,000"
+        );
+        assert_eq!(
+            Lang::En.anomaly_set("Mall", 25),
+            "Mall: confirmation if revenue is 2.5 times above or below usual"
+        );
+        assert_eq!(Lang::Ru.duration(125), "2 часа 5 минут");
+        let date = NaiveDate::from_ymd(2020, 3, 5);
+        assert_eq!(Lang::Ru.month_year(date), "03.2020");
+        assert_eq!(Lang::En.month_year(date), "Mar 2020");
+        assert_eq!(
+            Lang::En.charts_for(date, date.succ()),
+            "Charts for Mar 5-Mar 6"
+        );
+    }
+
+    #[test]
+    fn language_from_telegram() {
+        assert_eq!(Lang::from_code(None), Lang::Ru);
+        assert_eq!(Lang::from_code(Some("ru")), Lang::Ru);
+        assert_eq!(Lang::from_code(Some("uk")), Lang::Ru);
+        assert_eq!(Lang::from_code(Some("en-US")), Lang::En);
+        assert_eq!(Lang::from_code(Some("de")), Lang::En);
+        assert_eq!(Lang::parse("EN"), Some(Lang::En));
+        assert_eq!(Lang::parse("auto"), None);
+        for lang in &[Lang::Ru, Lang::En] {
+            assert_eq!(Lang::parse(lang.code()), Some(*lang));
+        }
+    }
+}
@@ -3,58 +3,81 @@ use std::convert::Infallible;
 use std::env;
 use std::error::Error;
 use storage::DataBase;
-use telegram_types::bot::methods;
 use telegram_types::bot::types;
 use warp::http::StatusCode;
 use warp::{reject, Filter, Rejection, Reply};
 
+mod admin;
+mod blobs;
+mod bot;
+mod callback;
+mod chart;
 pub(crate) mod chat;
+mod cli;
+mod dates;
+mod forecast;
 pub(crate) mod graph_ql;
+mod locale;
+mod money;
 pub(crate) mod old_storage;
+mod report;
 mod storage;
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&args) {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1)
+        }
+        return;
+    }
+    let db = DataBase::open().unwrap_or_else(|e| {
+        eprintln!("ERROR: Can't open sled database: {}", e);
+        std::process::exit(1)
+    });
+    if let Ok(dir) = env::var("BACKUP_DIR") {
+        let keep = env::var("BACKUP_KEEP")
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(7);
+        let period = std::time::Duration::from_secs(86400);
+        tokio::spawn(storage::backup_job(db.clone(), dir.into(), keep, period));
+    }
+    // старая база SQLite, пока с нее не перенесены данные
+    if let Ok(file) = env::var("SQLITE_DB") {
+        let days = env::var("TRASH_DAYS")
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(30);
+        let old_db = old_storage::DataBase::open(&file);
+        tokio::spawn(old_storage::purge_job(old_db, chrono::Duration::days(days)));
+    }
     let token = env::var("TG_BOT_TOKEN").expect("env var TG_BOT_TOKEN not set");
-    let db = DataBase::open();
-    let hello = warp::post()
+    let hour = env::var("SUMMARY_HOUR")
+        .ok()
+        .and_then(|h| h.parse().ok())
+        .unwrap_or(22);
+    let bot = bot::Bot::new(&token);
+    tokio::spawn(report::summary_job(db.clone(), bot.clone(), hour));
+    let ctx = chat::Context {
+        db,
+        bot,
+        blobs: env::var("BLOB_DIR")
+            .ok()
+            .map(|dir| blobs::BlobDir::new(dir.into())),
+    };
+    let webhook = warp::post()
         .and(warp::path(token))
         .and(warp::body::json())
-        .and(with_db(db))
-        .map(|update: types::Update, db: storage::DataBase| {
-            // eprintln!("Get Update");
-            match update.content {
-                types::UpdateContent::Message(msg) => {
-                    let answ = chat::UpdateReply {
-                        method: chat::ApiMethod::SendMessage,
-                        args: methods::SendMessage::new(
-                            methods::ChatTarget::id(msg.chat.id.0),
-                            "Hello",
-                        ),
-                    };
-                    let pr = old_storage::Proceeds {
-                        id: 0,
-                        amount: 10000,
-                        date: chrono::Local::now(),
-                        post_date: chrono::Local::now(),
-                        corner_id: 1,
-                        user_id: 1,
-                        comment: None,
-                    };
-                    warp::reply::json(&answ)
-                }
-                _ => {
-                    let err = methods::ApiError {
-                        error_code: 1,
-                        description: "Error".to_owned(),
-                        parameters: None,
-                    };
-                    warp::reply::json(&err)
-                }
-            }
+        .and(with_ctx(ctx.clone()))
+        .and_then(|update: types::Update, ctx: chat::Context| async move {
+            chat::main_handler(ctx, update)
         });
+    let admin = admin::routes(ctx, env::var("ADMIN_TOKEN").ok());
 
-    warp::serve(hello)
+    warp::serve(webhook.or(admin).recover(handle_rejection))
         .tls()
         .cert_path("YOURPUBLIC.pem")
         .key_path("YOURPRIVATE.key")
@@ -62,10 +85,10 @@ async fn main() {
         .await;
 }
 
-fn with_db(
-    db: storage::DataBase,
-) -> impl Filter<Extract = (storage::DataBase,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || db.clone())
+pub(crate) fn with_ctx(
+    ctx: chat::Context,
+) -> impl Filter<Extract = (chat::Context,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || ctx.clone())
 }
 
 #[derive(Serialize)]
@@ -83,6 +106,20 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
         message = "NOT_FOUND";
+    } else if let Some(chat::HandleError::NotMessage) = err.find() {
+        // иначе Telegram будет присылать это обновление снова
+        code = StatusCode::OK;
+        message = "IGNORED";
+    } else if let Some(admin::Unauthorized) = err.find() {
+        code = StatusCode::UNAUTHORIZED;
+        message = "UNAUTHORIZED";
+    } else if let Some(admin::BadDate) = err.find() {
+        code = StatusCode::BAD_REQUEST;
+        message = "BAD_DATE";
+    } else if let Some(admin::Failed(e)) = err.find() {
+        eprintln!("admin request failed: {}", e);
+        code = StatusCode::INTERNAL_SERVER_ERROR;
+        message = "FAILED";
     // } else if let Some(DivideByZero) = err.find() {
     //     code = StatusCode::BAD_REQUEST;
     //     message = "DIVIDE_BY_ZERO";
@@ -0,0 +1,206 @@
+//! Денежные суммы в копейках. Арифметика проверяет переполнение и
+//! паникует вместо того, чтобы молча завернуть большую сумму.
+
+use crate::locale::Lang;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+#[serde(transparent)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_kopecks(kopecks: i64) -> Money {
+        Money(kopecks)
+    }
+
+    pub fn rubles(rubles: i64) -> Money {
+        Money(rubles.checked_mul(100).expect("money overflow"))
+    }
+
+    pub fn kopecks(self) -> i64 {
+        self.0
+    }
+
+    /// Целые рубли, копейки отбрасываются
+    pub fn whole_rubles(self) -> i64 {
+        self.0 / 100
+    }
+
+    /// Рубли для расчетов с плавающей точкой: прогнозов, графиков
+    pub fn as_f64(self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub fn checked_sub(self, other: Money) -> Option<Money> {
+        self.0.checked_sub(other.0).map(Money)
+    }
+
+    pub fn checked_mul(self, n: i64) -> Option<Money> {
+        self.0.checked_mul(n).map(Money)
+    }
+
+    /// Доля суммы в сотых долях процента (250 - 2,5%), копейки вниз
+    pub fn percent(self, hundredths: u32) -> Money {
+        let part = self.0 as i128 * hundredths as i128 / 10_000;
+        Money(part as i64)
+    }
+
+    /// Сумма из сообщения: "12 345,50", "12345.5", "12к", "1,5k", "500 ₽".
+    /// Отрицательные суммы и больше двух знаков копеек не принимаются.
+    pub fn parse(s: &str) -> Option<Money> {
+        let s: String = s
+            .trim()
+            .trim_end_matches('₽')
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '\u{a0}' | '\u{202f}'))
+            .collect();
+        let (s, thousands) = match s.strip_suffix(&['к', 'К', 'k', 'K'][..]) {
+            Some(s) => (s, true),
+            None => (s.as_str(), false),
+        };
+        let mut parts = s.splitn(2, &[',', '.'][..]);
+        let whole = parts.next()?;
+        let frac = parts.next().unwrap_or("");
+        let digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || !digits(whole) || !digits(frac) {
+            return None;
+        }
+        // дробная часть в тысячах - рубли, в рублях - копейки
+        let (scale, places) = if thousands { (100_000, 3) } else { (100, 2) };
+        if frac.len() > places {
+            return None;
+        }
+        let frac = format!("{:0<width$}", frac, width = places);
+        let frac: i64 = frac.parse().ok()?;
+        let frac = if thousands { frac * 100 } else { frac };
+        let whole: i64 = whole.parse().ok()?;
+        whole.checked_mul(scale)?.checked_add(frac).map(Money)
+    }
+
+    /// Сумма без разрядов и знака рубля для CSV: "12345", "12345.50"
+    pub fn plain(self) -> String {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let (rubles, kopecks) = (self.0.unsigned_abs() / 100, self.0.unsigned_abs() % 100);
+        match kopecks {
+            0 => format!("{}{}", sign, rubles),
+            k => format!("{}{}.{:02}", sign, rubles, k),
+        }
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        self.checked_add(other).expect("money overflow")
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        self.checked_sub(other).expect("money overflow")
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+/// По-русски, с разрядами и знаком рубля
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&Lang::Ru.money(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_input() {
+        let ok = |s, kopecks| assert_eq!(Money::parse(s), Some(Money(kopecks)), "{}", s);
+        ok("12 345,50", 1_234_550);
+        ok("12345.5", 1_234_550);
+        ok("12345", 1_234_500);
+        ok("12к", 1_200_000);
+        ok("12 К", 1_200_000);
+        ok("1,5k", 150_000);
+        ok("1.234к", 123_400);
+        ok("500 ₽", 50_000);
+        ok("0", 0);
+        ok("12\u{a0}345", 1_234_500);
+        for bad in &[
+            "",
+            "к",
+            ",5",
+            "12,345",
+            "1.2345к",
+            "-5",
+            "12 345,50,1",
+            "12р",
+            "абв",
+            "1e3",
+        ] {
+            assert_eq!(Money::parse(bad), None, "{}", bad);
+        }
+        assert_eq!(Money::parse("99999999999999999999"), None);
+        assert_eq!(Money::parse("92233720368547759"), None);
+    }
+
+    #[test]
+    fn checked_arithmetic() {
+        let big = Money(i64::MAX - 1);
+        assert_eq!(big.checked_add(Money(1)), Some(Money(i64::MAX)));
+        assert_eq!(big.checked_add(Money(2)), None);
+        assert_eq!(Money::ZERO.checked_sub(big), Some(Money(1 - i64::MAX)));
+        assert_eq!(big.checked_mul(2), None);
+        assert!(std::panic::catch_unwind(|| big + Money(2)).is_err());
+        // месячная сумма, которая не влезла бы в u32 рублей
+        let month: Money = (0..31).map(|_| Money::rubles(200_000_000)).sum();
+        assert_eq!(month.whole_rubles(), 6_200_000_000);
+        assert_eq!(Money::rubles(10_000).percent(250), Money::rubles(250));
+        assert_eq!(Money(999).percent(250), Money(24));
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(Money::rubles(1500).to_string(), "1500 ₽");
+        assert_eq!(Money(1_234_550).to_string(), "12 345,50 ₽");
+        assert_eq!(Lang::En.money(Money(1_234_505)), "₽12,345.05");
+        assert_eq!(Money(-150).to_string(), "-1,50 ₽");
+        assert_eq!(Money(1_234_550).plain(), "12345.50");
+        assert_eq!(Money::rubles(-7).plain(), "-7");
+        for s in &["12 345,50", "1500", "0,05"] {
+            let money = Money::parse(s).unwrap();
+            assert_eq!(Money::parse(&money.to_string()), Some(money));
+        }
+    }
+}
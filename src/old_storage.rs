@@ -1,22 +1,220 @@
-use chrono::{DateTime, Local, TimeZone};
+use crate::dates;
+use crate::money::Money;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use r2d2_sqlite::SqliteConnectionManager;
 use rand::Rng;
 use rusqlite::{
     params,
-    types::{FromSql, FromSqlError, FromSqlResult, ValueRef},
-    Connection, OptionalExtension, NO_PARAMS,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef},
+    Connection, OptionalExtension, Transaction, TransactionBehavior, NO_PARAMS,
 };
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
 const INVITE_LEN: usize = 8;
+const POOL_SIZE: u32 = 4;
+const INVITE_TTL_DAYS: i64 = 2;
+
+/// Миграции схемы по порядку. Номер последней примененной
+/// хранится в `PRAGMA user_version`, поэтому порядок менять нельзя,
+/// только дописывать новые в конец.
+const MIGRATIONS: &[fn(&Transaction) -> rusqlite::Result<()>] = &[
+    initial_schema,
+    soft_delete_columns,
+    proceeds_edit_table,
+    proceeds_date_index,
+    invite_code_expire_date,
+    proceeds_kopecks,
+    corner_timezone,
+    proceeds_edit_corner,
+    deleted_with_column,
+];
+
+/// Прогоняет недостающие миграции, каждую в своей транзакции.
+/// Внешние ключи на время миграций выключены, чтобы можно было
+/// пересобирать таблицы; нарушения после миграции только логируются,
+/// так как старые базы уже содержат ссылки на удаленные записи.
+/// После миграций внешние ключи снова проверяются.
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+    loop {
+        // версию читаем под блокировкой записи, чтобы параллельный
+        // процесс не применил ту же миграцию второй раз
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let version = tx.query_row("PRAGMA user_version", NO_PARAMS, |row| {
+            row.get::<usize, i64>(0)
+        })? as usize;
+        let migration = match MIGRATIONS.get(version) {
+            Some(migration) => migration,
+            None => break,
+        };
+        migration(&tx)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", version + 1))?;
+        tx.commit()?;
+        println!("Database migrated to version {}", version + 1);
+    }
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+    let mut rows = stmt.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        eprintln!(
+            "WARNING: {} row {} references missing {}",
+            row.get::<usize, String>(0)?,
+            row.get::<usize, i64>(1)?,
+            row.get::<usize, String>(2)?
+        );
+    }
+    conn.execute_batch("PRAGMA foreign_keys = ON")
+}
+
+fn initial_schema(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS proceeds (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            amount INTEGER NOT NULL CHECK (amount >=0),
+            date INTEGER NOT NULL,
+            post_date INTEGER NOT NULL,
+            corner_id INTEGER REFERENCES corner,
+            user_id INTEGER REFERENCES user,
+            comment TEXT
+        );
+        CREATE TABLE IF NOT EXISTS user (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            tg_id INTEGER UNIQUE NOT NULL,
+            name TEXT NOT NULL,
+            corner_id INTEGER REFERENCES corner,
+            is_active INTEGER NOT NULL,
+            step INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS corner (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT UNIQUE NOT NULL,
+            shrt_name TEXT UNIQUE
+        );
+        CREATE TABLE IF NOT EXISTS admin (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            login TEXT UNIQUE NOT NULL,
+            name TEXT NOT NULL,
+            pswd_hash TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS invite_code (
+            code TEXT PRIMARY KEY,
+            corner_id INTEGER NOT NULL,
+            admin_id INTEGER NOT NULL,
+            gen_date INTEGER NOT NULL,
+            used INTEGER NOT NULL
+        );",
+    )
+}
+
+fn soft_delete_columns(tx: &Transaction) -> rusqlite::Result<()> {
+    for table in &["proceeds", "user", "corner"] {
+        tx.add_column_if_missing(table, "deleted_at", "INTEGER")?;
+    }
+    Ok(())
+}
+
+fn proceeds_edit_table(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS proceeds_edit (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            proceeds_id INTEGER NOT NULL REFERENCES proceeds,
+            editor_id INTEGER NOT NULL,
+            edit_date INTEGER NOT NULL,
+            amount INTEGER NOT NULL,
+            date INTEGER NOT NULL,
+            comment TEXT
+        );",
+    )
+}
+
+fn proceeds_date_index(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS proceeds_date_corner ON proceeds (date, corner_id);",
+    )
+}
+
+/// Вместо даты генерации храним срок действия и ссылку на точку
+fn invite_code_expire_date(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(&format!(
+        "CREATE TABLE invite_code_new (
+            code TEXT PRIMARY KEY,
+            corner_id INTEGER NOT NULL REFERENCES corner,
+            admin_id INTEGER NOT NULL,
+            expire_date INTEGER NOT NULL,
+            used INTEGER NOT NULL
+        );
+        INSERT INTO invite_code_new (code, corner_id, admin_id, expire_date, used)
+            SELECT code, corner_id, admin_id, gen_date + {}, used FROM invite_code;
+        DROP TABLE invite_code;
+        ALTER TABLE invite_code_new RENAME TO invite_code;",
+        INVITE_TTL_DAYS * 86400
+    ))
+}
+
+/// Суммы выручки и правок переводятся из рублей в копейки
+fn proceeds_kopecks(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "UPDATE proceeds SET amount = amount * 100;
+        UPDATE proceeds_edit SET amount = amount * 100;",
+    )
+}
+
+/// Часовой пояс точки, NULL - `storage::TIMEZONE`
+fn corner_timezone(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.add_column_if_missing("corner", "timezone", "TEXT")
+}
+
+/// Правка выручки помнит прежнюю точку, выручку можно переносить
+fn proceeds_edit_corner(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.add_column_if_missing("proceeds_edit", "corner_id", "INTEGER REFERENCES corner")
+}
+
+/// Какая удаленная точка увела запись в корзину вместе с собой
+fn deleted_with_column(tx: &Transaction) -> rusqlite::Result<()> {
+    for table in &["proceeds", "user", "corner"] {
+        tx.add_column_if_missing(table, "deleted_with", "INTEGER")?;
+    }
+    Ok(())
+}
 
 trait ConnectionExt {
     fn invite_code_exist(&self, code: &str) -> anyhow::Result<bool>;
     fn use_invite_code(&self, code: &str) -> anyhow::Result<RegisterResult>;
+    fn corner_tz(&self, corner_id: i32) -> rusqlite::Result<Tz>;
+    fn add_column_if_missing(&self, table: &str, column: &str, decl: &str) -> rusqlite::Result<()>;
 }
 
 impl ConnectionExt for Connection {
+    fn add_column_if_missing(&self, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+        let mut stmt = self.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map(NO_PARAMS, |row| row.get::<usize, String>(1))?
+            .collect::<rusqlite::Result<Vec<String>>>()?
+            .iter()
+            .any(|name| name == column);
+        if !exists {
+            self.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+                NO_PARAMS,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn corner_tz(&self, corner_id: i32) -> rusqlite::Result<Tz> {
+        let name: Option<String> = self
+            .query_row(
+                "SELECT timezone FROM corner WHERE id=?1",
+                params![corner_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(name
+            .and_then(|name| dates::zone(&name))
+            .unwrap_or(crate::storage::TIMEZONE))
+    }
+
     fn invite_code_exist(&self, code: &str) -> anyhow::Result<bool> {
         let mut statement = self.prepare("SELECT * FROM invite_code WHERE code=?1")?;
         let res = statement.exists(params![code])?;
@@ -31,7 +229,7 @@ impl ConnectionExt for Connection {
                     code: row.get(0)?,
                     corner_id: row.get(1)?,
                     admin_id: row.get(2)?,
-                    gen_date: Local.timestamp(row.get(3)?, 0),
+                    expire_date: Utc.timestamp(row.get(3)?, 0),
                     used: row.get(4)?,
                 })
             })
@@ -40,20 +238,14 @@ impl ConnectionExt for Connection {
             return Ok(RegisterResult::InviteNotFound);
         }
         match code_from_db.unwrap() {
-            cd if cd.used == true => return Ok(RegisterResult::InviteUsed),
-            cd if chrono::Local::now()
-                .signed_duration_since(cd.gen_date)
-                .num_days()
-                > 1 =>
-            {
-                return Ok(RegisterResult::InviteExpired)
-            }
+            cd if cd.used => Ok(RegisterResult::InviteUsed),
+            cd if Utc::now() >= cd.expire_date => Ok(RegisterResult::InviteExpired),
             cd => {
                 self.execute(
                     "UPDATE invite_code SET used = 1 WHERE code=?1",
                     rusqlite::params![cd.code],
                 )?;
-                return Ok(RegisterResult::Succes(cd.corner_id));
+                Ok(RegisterResult::Succes(cd.corner_id))
             }
         }
     }
@@ -61,102 +253,106 @@ impl ConnectionExt for Connection {
 
 #[derive(Clone)]
 pub struct DataBase {
-    conn: Arc<Mutex<rusqlite::Connection>>,
+    pool: r2d2::Pool<SqliteConnectionManager>,
 }
 
 impl DataBase {
-    pub fn custom_init() -> Self {
-        let db_file = "./database.db3";
-        let conn = Connection::open(&db_file).expect(&format!("Can't open db3 file: {}", &db_file));
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS proceeds (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                amount INTEGER NOT NULL CHECK (amount >=0),
-                date INTEGER NOT NULL,
-                post_date INTEGER NOT NULL,
-                corner_id INTEGER REFERENCES corner,
-                user_id INTEGER REFERENCES user,
-                comment TEXT
-            )",
-            NO_PARAMS,
-        )
-        .expect("Can't check/create proceeds table");
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS user (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                tg_id INTEGER UNIQUE NOT NULL,
-                name TEXT NOT NULL,
-                corner_id INTEGER REFERENCES corner,
-                is_active INTEGER NOT NULL,
-                step INTEGER NOT NULL
-            )",
-            NO_PARAMS,
-        )
-        .expect("Can't check/create user table");
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS corner (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                name TEXT UNIQUE NOT NULL,
-                shrt_name TEXT UNIQUE
-            )",
-            NO_PARAMS,
-        )
-        .expect("Can't check/create corner table");
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS admin (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                login TEXT UNIQUE NOT NULL,
-                name TEXT NOT NULL,
-                pswd_hash TEXT NOT NULL
-            )",
-            NO_PARAMS,
-        )
-        .expect("Can't check/create user table");
+    /// Открывает файл базы, включает WAL и применяет миграции
+    pub fn open(db_file: &str) -> Self {
+        let mut conn = Connection::open(db_file)
+            .unwrap_or_else(|e| panic!("Can't open db3 file {}: {}", db_file, e));
+        conn.busy_timeout(std::time::Duration::from_secs(5))
+            .expect("Can't set busy timeout");
+        // WAL хранится в самом файле базы, достаточно включить один раз
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| {
+            row.get::<usize, String>(0)
+        })
+        .expect("Can't enable WAL");
+        migrate(&mut conn).expect("Can't migrate database schema");
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS invite_code (
-                code TEXT PRIMARY KEY,
-                corner_id INTEGER NOT NULL,
-                admin_id INTEGER NOT NULL,
-                gen_date INTEGER NOT NULL,
-                used INTEGER NOT NULL
-            )",
-            NO_PARAMS,
-        )
-        .expect("Can't check/create invite_code table");
+        let manager = SqliteConnectionManager::file(db_file).with_init(|conn| {
+            conn.busy_timeout(std::time::Duration::from_secs(5))?;
+            conn.execute_batch("PRAGMA foreign_keys = ON")
+        });
+        let pool = r2d2::Pool::builder()
+            .max_size(POOL_SIZE)
+            .build(manager)
+            .expect("Can't create sqlite connection pool");
+        DataBase { pool }
+    }
 
-        DataBase {
-            conn: Arc::new(Mutex::new(conn)),
-        }
+    /// Выполняет `f` на соединении из пула в отдельном блокирующем потоке,
+    /// чтобы не занимать потоки tokio.
+    async fn run<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<r2d2::Error> + From<tokio::task::JoinError> + Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || f(&mut *pool.get()?)).await?
     }
 
     pub async fn push_proceeds(&self, pr: Proceeds) -> anyhow::Result<usize> {
-        let conn = self.conn.lock().await;
-        let res: usize = conn.execute(
-            "INSERT INTO proceeds (amount, date, post_date, corner_id, user_id, comment)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-            params![
-                pr.amount,
-                pr.date.timestamp(),
-                pr.post_date.timestamp(),
-                pr.corner_id,
-                pr.user_id,
-                pr.comment
-            ],
-        )?;
-        Ok(res)
+        self.run(move |conn| {
+            let res: usize = conn.execute(
+                "INSERT INTO proceeds (amount, date, post_date, corner_id, user_id, comment)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    pr.amount,
+                    pr.date.timestamp(),
+                    pr.post_date.timestamp(),
+                    pr.corner_id,
+                    pr.user_id,
+                    pr.comment
+                ],
+            )?;
+            Ok(res)
+        })
+        .await
     }
 
     pub async fn get_proceeds(&self) -> anyhow::Result<Vec<Proceeds>> {
-        let conn = self.conn.lock().await;
-        let mut statement = conn.prepare("SELECT * FROM proceeds")?;
-        let res = serde_rusqlite::from_rows::<Proceeds>(statement.query(NO_PARAMS)?)
-            .collect::<serde_rusqlite::error::Result<Vec<Proceeds>>>()?;
-        Ok(res)
+        self.run(|conn| {
+            let mut statement =
+                conn.prepare_cached("SELECT * FROM proceeds WHERE deleted_at IS NULL")?;
+            let res: rusqlite::Result<Vec<Proceeds>> = statement
+                .query_and_then(NO_PARAMS, Proceeds::from_row)?
+                .collect();
+            Ok(res?)
+        })
+        .await
+    }
+
+    /// Выручка точки за рабочий день `date`, границы дня - по поясу точки
+    pub async fn get_day_proceeds(
+        &self,
+        corner_id: i32,
+        date: NaiveDate,
+    ) -> anyhow::Result<Vec<Proceeds>> {
+        self.run(move |conn| {
+            let tz = conn.corner_tz(corner_id)?;
+            // полночь может быть пропущена переходом на летнее время,
+            // тогда день начинается с первого существующего часа
+            let start = |date: NaiveDate| {
+                (0..24)
+                    .find_map(|hour| tz.from_local_datetime(&date.and_hms(hour, 0, 0)).earliest())
+                    .unwrap_or_else(|| tz.from_utc_datetime(&date.and_hms(0, 0, 0)))
+            };
+            let (from, to) = (start(date), start(date.succ()));
+            let mut stmt = conn.prepare_cached(
+                "SELECT * FROM proceeds WHERE date >= ?1 AND date < ?2 AND corner_id = ?3
+                AND deleted_at IS NULL",
+            )?;
+            let res: rusqlite::Result<Vec<Proceeds>> = stmt
+                .query_and_then(
+                    params![from.timestamp(), to.timestamp(), corner_id],
+                    Proceeds::from_row,
+                )?
+                .collect();
+            Ok(res?)
+        })
+        .await
     }
 
     pub async fn get_new_invite_code(
@@ -164,25 +360,20 @@ impl DataBase {
         corner_id: i32,
         admin_id: i32,
     ) -> anyhow::Result<String> {
-        let conn = self.conn.lock().await;
-        loop {
+        self.run(move |conn| loop {
             let code = gen_code();
             if conn.invite_code_exist(code.as_str())? {
                 continue;
             }
+            let expire_date = Utc::now() + Duration::days(INVITE_TTL_DAYS);
             conn.execute(
-                "INSERT INTO invite_code (code, corner_id, admin_id, gen_date, used) 
+                "INSERT INTO invite_code (code, corner_id, admin_id, expire_date, used) 
             VALUES (?1, ?2, ?3, ?4, ?5)",
-                params![
-                    code,
-                    corner_id,
-                    admin_id,
-                    chrono::Local::now().timestamp(),
-                    false
-                ],
+                params![code, corner_id, admin_id, expire_date.timestamp(), false],
             )?;
             return Ok(code);
-        }
+        })
+        .await
     }
 
     pub async fn register_user(
@@ -190,129 +381,540 @@ impl DataBase {
         code_and_name: &str,
         tg_id: i32,
     ) -> anyhow::Result<RegisterResult> {
-        let conn = self.conn.lock().await;
-        if code_and_name.len() < 15 {
-            Ok(RegisterResult::TooShortName)
-        } else {
-            match conn.use_invite_code(&code_and_name[..INVITE_LEN])? {
+        let code_and_name = code_and_name.to_owned();
+        self.run(move |conn| {
+            if code_and_name.len() < 15 {
+                return Ok(RegisterResult::TooShortName);
+            }
+            let tx = conn.transaction()?;
+            let res = match tx.use_invite_code(&code_and_name[..INVITE_LEN])? {
                 RegisterResult::Succes(corner_id) => {
-                    conn.execute(
-                        "INSERT INTO user (tg_id, name, corner_id, is_active, step) 
-                        VALUES (?1, ?2, ?3, ?4, ?5)",
-                        params![tg_id, &code_and_name[INVITE_LEN + 1..], corner_id, true, 0],
-                    )?;
-                    Ok(RegisterResult::Succes(conn.last_insert_rowid() as i32))
+                    let name = &code_and_name[INVITE_LEN + 1..];
+                    // пользователь из корзины регистрируется заново под тем же id,
+                    // чтобы его старая выручка осталась за ним
+                    let deleted_id: Option<i32> = tx
+                        .query_row(
+                            "SELECT id FROM user WHERE tg_id=?1 AND deleted_at IS NOT NULL",
+                            params![tg_id],
+                            |row| row.get(0),
+                        )
+                        .optional()?;
+                    if let Some(id) = deleted_id {
+                        tx.execute(
+                            "UPDATE user SET name=?1, corner_id=?2, is_active=1, step=0,
+                            deleted_at=NULL WHERE id=?3",
+                            params![name, corner_id, id],
+                        )?;
+                        RegisterResult::Succes(id)
+                    } else {
+                        tx.execute(
+                            "INSERT INTO user (tg_id, name, corner_id, is_active, step) 
+                            VALUES (?1, ?2, ?3, ?4, ?5)",
+                            params![tg_id, name, corner_id, true, 0],
+                        )?;
+                        RegisterResult::Succes(tx.last_insert_rowid() as i32)
+                    }
                 }
-                invite_error => Ok(invite_error),
-            }
-        }
+                invite_error => invite_error,
+            };
+            tx.commit()?;
+            Ok(res)
+        })
+        .await
     }
 
     pub async fn get_user(&self, id: i32) -> anyhow::Result<User> {
-        let conn = self.conn.lock().await;
-        let mut stmt = conn.prepare_cached("SELECT * FROM user WHERE id=?1")?;
-        let user: User = stmt.query_row(&[id], User::from_row)?;
-        Ok(user)
+        self.run(move |conn| {
+            let mut stmt =
+                conn.prepare_cached("SELECT * FROM user WHERE id=?1 AND deleted_at IS NULL")?;
+            let user: User = stmt.query_row([id], User::from_row)?;
+            Ok(user)
+        })
+        .await
     }
 
     pub async fn get_users_by_corner(&self, corner_id: i32) -> anyhow::Result<Vec<User>> {
-        let conn = self.conn.lock().await;
-        let mut stmt = conn.prepare_cached("SELECT * FROM user WHERE corner_id=?1")?;
-        let res: rusqlite::Result<Vec<User>> = stmt
-            .query_and_then(params![corner_id], User::from_row)?
-            .collect();
-        Ok(res?)
+        self.run(move |conn| {
+            let mut stmt = conn
+                .prepare_cached("SELECT * FROM user WHERE corner_id=?1 AND deleted_at IS NULL")?;
+            let res: rusqlite::Result<Vec<User>> = stmt
+                .query_and_then(params![corner_id], User::from_row)?
+                .collect();
+            Ok(res?)
+        })
+        .await
     }
 
     pub async fn get_step(&self, tg_id: i64) -> anyhow::Result<ChatStep> {
-        let conn = self.conn.lock().await;
-        let mut stmt = conn.prepare("SELECT is_active, step FROM user WHERE tg_id=?1")?;
-        let step: ChatStep = stmt
-            .query_row(rusqlite::params![tg_id], |row| {
-                Ok(match row.get::<usize, bool>(0)? {
-                    true => row.get::<usize, ChatStep>(1)?,
-                    false => ChatStep::Deactive
+        self.run(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT is_active, step FROM user WHERE tg_id=?1 AND deleted_at IS NULL",
+            )?;
+            let step: ChatStep = stmt
+                .query_row(rusqlite::params![tg_id], |row| {
+                    Ok(match row.get::<usize, bool>(0)? {
+                        true => row.get::<usize, ChatStep>(1)?,
+                        false => ChatStep::Deactive,
+                    })
                 })
-                
-            })
-            .optional()?.unwrap_or(ChatStep::NotRegister);
-        Ok(step)
+                .optional()?
+                .unwrap_or(ChatStep::NotRegister);
+            Ok(step)
+        })
+        .await
     }
 
     pub async fn deactive_user(&self, id: i32) -> anyhow::Result<()> {
-        let conn = self.conn.lock().await;
-        conn.execute("UPDATE user SET is_active=0 WHERE id=?1", params![id])?;
-        Ok(())
+        self.run(move |conn| {
+            conn.execute("UPDATE user SET is_active=0 WHERE id=?1", params![id])?;
+            Ok(())
+        })
+        .await
     }
 
     pub async fn active_user(&self, id: i32) -> anyhow::Result<()> {
-        let conn = self.conn.lock().await;
-        conn.execute("UPDATE user SET is_active=1 WHERE id=?1", params![id])?;
-        Ok(())
+        self.run(move |conn| {
+            conn.execute("UPDATE user SET is_active=1 WHERE id=?1", params![id])?;
+            Ok(())
+        })
+        .await
     }
 
     pub async fn set_name_for_user(&self, name: &str, id: i32) -> anyhow::Result<()> {
-        let conn = self.conn.lock().await;
-        conn.execute("UPDATE user SET name=?1 WHERE id=?2", params![name, id])?;
-        Ok(())
+        let name = name.to_owned();
+        self.run(move |conn| {
+            conn.execute("UPDATE user SET name=?1 WHERE id=?2", params![name, id])?;
+            Ok(())
+        })
+        .await
     }
 
     pub async fn del_user(&self, id: i32) -> anyhow::Result<()> {
-        let conn = self.conn.lock().await;
-        conn.execute("DELETE FROM user WHERE id=?1", params![id])?;
-        Ok(())
+        self.soft_delete("user", id).await
     }
 
     pub async fn del_proceeds(&self, id: i32) -> anyhow::Result<()> {
-        let conn = self.conn.lock().await;
-        conn.execute("DELETE FROM proceeds WHERE id=?1", params![id])?;
-        Ok(())
+        self.soft_delete("proceeds", id).await
+    }
+
+    pub async fn restore_user(&self, id: i32) -> anyhow::Result<bool> {
+        self.restore("user", id).await
+    }
+
+    pub async fn restore_proceeds(&self, id: i32) -> anyhow::Result<bool> {
+        self.restore("proceeds", id).await
+    }
+
+    /// Возвращает точку вместе с сотрудниками и выручкой,
+    /// удаленными вместе с ней
+    pub async fn restore_corner(&self, id: i32) -> anyhow::Result<bool> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let deleted = tx
+                .prepare("SELECT 1 FROM corner WHERE id=?1 AND deleted_at IS NOT NULL")?
+                .exists(params![id])?;
+            if !deleted {
+                return Ok(false);
+            }
+            tx.execute("UPDATE corner SET deleted_at=NULL WHERE id=?1", params![id])?;
+            for table in &["user", "proceeds"] {
+                tx.execute(
+                    &format!(
+                        "UPDATE {} SET deleted_at=NULL, deleted_with=NULL
+                        WHERE corner_id=?1 AND deleted_with=?1 AND deleted_at IS NOT NULL",
+                        table
+                    ),
+                    params![id],
+                )?;
+            }
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
     }
 
-    pub fn update_proceeds(&self) -> anyhow::Result<()> {
-        todo!();
+    async fn soft_delete(&self, table: &'static str, id: i32) -> anyhow::Result<()> {
+        self.run(move |conn| {
+            conn.execute(
+                &format!(
+                    "UPDATE {} SET deleted_at=?2, deleted_with=NULL
+                    WHERE id=?1 AND deleted_at IS NULL",
+                    table
+                ),
+                params![id, Utc::now().timestamp()],
+            )?;
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn push_corner(
+    async fn restore(&self, table: &'static str, id: i32) -> anyhow::Result<bool> {
+        self.run(move |conn| {
+            let res = conn.execute(
+                &format!(
+                    "UPDATE {} SET deleted_at=NULL WHERE id=?1 AND deleted_at IS NOT NULL",
+                    table
+                ),
+                params![id],
+            )?;
+            Ok(res > 0)
+        })
+        .await
+    }
+
+    pub async fn get_trash(&self) -> anyhow::Result<Trash> {
+        self.run(|conn| {
+            Ok(Trash {
+                users: deleted_rows(conn, "user", User::from_row)?,
+                proceeds: deleted_rows(conn, "proceeds", Proceeds::from_row)?,
+                corners: deleted_rows(conn, "corner", Corner::from_row)?,
+            })
+        })
+        .await
+    }
+
+    /// Окончательно удаляет записи, пролежавшие в корзине дольше `retention`.
+    /// Точка удаляется только когда на нее больше не ссылается ни выручка, ни пользователь.
+    pub async fn purge_deleted(&self, retention: chrono::Duration) -> anyhow::Result<usize> {
+        self.run(move |conn| {
+            let border = (Utc::now() - retention).timestamp();
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM proceeds_edit WHERE proceeds_id IN
+                (SELECT id FROM proceeds WHERE deleted_at IS NOT NULL AND deleted_at <= ?1)",
+                params![border],
+            )?;
+            let mut res = tx.execute(
+                "DELETE FROM proceeds WHERE deleted_at IS NOT NULL AND deleted_at <= ?1",
+                params![border],
+            )?;
+            res += tx.execute(
+                "DELETE FROM user WHERE deleted_at IS NOT NULL AND deleted_at <= ?1
+                AND NOT EXISTS (SELECT 1 FROM proceeds WHERE proceeds.user_id = user.id)",
+                params![border],
+            )?;
+            tx.execute(
+                "DELETE FROM invite_code WHERE corner_id IN
+                (SELECT id FROM corner WHERE deleted_at IS NOT NULL AND deleted_at <= ?1)",
+                params![border],
+            )?;
+            let purged = "SELECT id FROM corner WHERE deleted_at IS NOT NULL AND deleted_at <= ?1
+                AND NOT EXISTS (SELECT 1 FROM proceeds WHERE proceeds.corner_id = corner.id)
+                AND NOT EXISTS (SELECT 1 FROM user WHERE user.corner_id = corner.id)";
+            // история правок остается, но без ссылки на удаляемую точку
+            tx.execute(
+                &format!(
+                    "UPDATE proceeds_edit SET corner_id = NULL WHERE corner_id IN ({})",
+                    purged
+                ),
+                params![border],
+            )?;
+            res += tx.execute(
+                &format!("DELETE FROM corner WHERE id IN ({})", purged),
+                params![border],
+            )?;
+            tx.commit()?;
+            Ok(res)
+        })
+        .await
+    }
+
+    /// Частично обновляет выручку. Прежние значения вместе с `editor_id`
+    /// и временем правки сохраняются в `proceeds_edit`.
+    pub async fn update_proceeds(
         &self,
-        name: &str,
-        shrt_name: Option<&str>,
-    ) -> anyhow::Result<()> {
-        let conn = self.conn.lock().await;
-        conn.execute(
-            "INSERT INTO corner (name, shrt_name) VALUES (?1, ?2)",
-            params![name, shrt_name],
-        )?;
-        Ok(())
+        id: i32,
+        upd: ProceedsUpdate,
+        editor_id: i32,
+    ) -> Result<Proceeds, UpdateError> {
+        self.run(move |conn| {
+            let tx = conn.transaction()?;
+            let old = tx
+                .query_row(
+                    "SELECT * FROM proceeds WHERE id=?1 AND deleted_at IS NULL",
+                    params![id],
+                    Proceeds::from_row,
+                )
+                .optional()?
+                .ok_or(UpdateError::NotFound(id))?;
+
+            let amount = upd.amount.unwrap_or(old.amount);
+            if amount < Money::ZERO {
+                return Err(UpdateError::NegativeAmount(amount));
+            }
+            // выручку нельзя править на удаленной точке и переносить на удаленную
+            let corner_id = upd.corner_id.unwrap_or(old.corner_id);
+            let mut stmt = tx.prepare("SELECT 1 FROM corner WHERE id=?1 AND deleted_at IS NULL")?;
+            for &id in &[old.corner_id, corner_id] {
+                if !stmt.exists(params![id])? {
+                    return Err(UpdateError::CornerNotFound(id));
+                }
+            }
+            drop(stmt);
+            let date = upd.date.unwrap_or(old.date);
+            let comment = upd.comment.unwrap_or_else(|| old.comment.clone());
+
+            tx.execute(
+                "INSERT INTO proceeds_edit
+                (proceeds_id, editor_id, edit_date, amount, date, comment, corner_id)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    id,
+                    editor_id,
+                    Utc::now().timestamp(),
+                    old.amount,
+                    old.date.timestamp(),
+                    old.comment,
+                    old.corner_id
+                ],
+            )?;
+            tx.execute(
+                "UPDATE proceeds SET amount=?1, date=?2, comment=?3, corner_id=?4 WHERE id=?5",
+                params![amount, date.timestamp(), comment, corner_id, id],
+            )?;
+            let res = tx.query_row(
+                "SELECT * FROM proceeds WHERE id=?1",
+                params![id],
+                Proceeds::from_row,
+            )?;
+            tx.commit()?;
+            Ok(res)
+        })
+        .await
+    }
+
+    pub async fn get_proceeds_edits(&self, proceeds_id: i32) -> anyhow::Result<Vec<ProceedsEdit>> {
+        self.run(move |conn| {
+            let mut stmt = conn
+                .prepare_cached("SELECT * FROM proceeds_edit WHERE proceeds_id=?1 ORDER BY id")?;
+            let res: rusqlite::Result<Vec<ProceedsEdit>> = stmt
+                .query_and_then(params![proceeds_id], ProceedsEdit::from_row)?
+                .collect();
+            Ok(res?)
+        })
+        .await
+    }
+
+    pub async fn push_corner(&self, name: &str, shrt_name: Option<&str>) -> anyhow::Result<()> {
+        let name = name.to_owned();
+        let shrt_name = shrt_name.map(str::to_owned);
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO corner (name, shrt_name) VALUES (?1, ?2)",
+                params![name, shrt_name],
+            )?;
+            Ok(())
+        })
+        .await
     }
 
+    pub async fn set_corner_timezone(&self, id: i32, tz: Tz) -> anyhow::Result<()> {
+        self.run(move |conn| {
+            conn.execute(
+                "UPDATE corner SET timezone=?1 WHERE id=?2",
+                params![tz.name(), id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Убирает в корзину точку, ее сотрудников и выручку. Записи помечаются
+    /// `deleted_with`, чтобы `restore_corner` вернул только их.
     pub async fn del_corner(&self, id: i32) -> anyhow::Result<()> {
-        let conn = self.conn.lock().await;
-        conn.execute("DELETE FROM corner WHERE id=?1", params![id])?;
-        Ok(())
+        self.run(move |conn| {
+            let now = Utc::now().timestamp();
+            let tx = conn.transaction()?;
+            let deleted = tx.execute(
+                "UPDATE corner SET deleted_at=?2 WHERE id=?1 AND deleted_at IS NULL",
+                params![id, now],
+            )?;
+            if deleted > 0 {
+                for table in &["user", "proceeds"] {
+                    tx.execute(
+                        &format!(
+                            "UPDATE {} SET deleted_at=?2, deleted_with=?1
+                            WHERE corner_id=?1 AND deleted_at IS NULL",
+                            table
+                        ),
+                        params![id, now],
+                    )?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
     }
 
     pub async fn get_corners(&self) -> anyhow::Result<Vec<Corner>> {
-        let conn = self.conn.lock().await;
-        let mut stmt = conn.prepare_cached("SELECT * FROM corner")?;
-        let res: rusqlite::Result<Vec<Corner>> =
-            stmt.query_and_then(NO_PARAMS, Corner::from_row)?.collect();
-        Ok(res?)
+        self.run(|conn| {
+            let mut stmt = conn.prepare_cached("SELECT * FROM corner WHERE deleted_at IS NULL")?;
+            let res: rusqlite::Result<Vec<Corner>> =
+                stmt.query_and_then(NO_PARAMS, Corner::from_row)?.collect();
+            Ok(res?)
+        })
+        .await
+    }
+}
+
+/// Раз в сутки чистит корзину от записей старше `retention`
+pub async fn purge_job(db: DataBase, retention: chrono::Duration) {
+    let mut interval_day = tokio::time::interval(std::time::Duration::from_secs(86400));
+    loop {
+        interval_day.tick().await;
+        match db.purge_deleted(retention).await {
+            Ok(n) => println!("Purged {} deleted records", n),
+            Err(e) => eprintln!("ERROR: Can't purge deleted records: {}", e),
+        }
     }
 }
 
+fn deleted_rows<T>(
+    conn: &Connection,
+    table: &str,
+    from_row: fn(&rusqlite::Row) -> rusqlite::Result<T>,
+) -> rusqlite::Result<Vec<Deleted<T>>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT *, deleted_at FROM {} WHERE deleted_at IS NOT NULL",
+        table
+    ))?;
+    let res = stmt
+        .query_and_then(NO_PARAMS, |row| {
+            Ok(Deleted {
+                deleted_at: Utc.timestamp(row.get(row.column_count() - 1)?, 0),
+                item: from_row(row)?,
+            })
+        })?
+        .collect();
+    res
+}
+
+#[derive(Debug)]
+pub struct Deleted<T> {
+    pub item: T,
+    pub deleted_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct Trash {
+    pub users: Vec<Deleted<User>>,
+    pub proceeds: Vec<Deleted<Proceeds>>,
+    pub corners: Vec<Deleted<Corner>>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Proceeds {
     #[serde(skip_serializing_if = "i32_is_null")]
     pub id: i32,
-    pub amount: i32,
-    pub date: chrono::DateTime<chrono::Local>,
-    pub post_date: chrono::DateTime<chrono::Local>,
+    pub amount: Money,
+    pub date: DateTime<Utc>,
+    pub post_date: DateTime<Utc>,
     pub corner_id: i32,
     pub user_id: i32,
     pub comment: Option<String>,
 }
 
+impl Proceeds {
+    /// Рабочий день выручки в поясе точки
+    pub fn day(&self, tz: Tz) -> NaiveDate {
+        self.date.with_timezone(&tz).date().naive_local()
+    }
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Proceeds {
+            id: row.get(0)?,
+            amount: row.get(1)?,
+            date: Utc.timestamp(row.get(2)?, 0),
+            post_date: Utc.timestamp(row.get(3)?, 0),
+            corner_id: row.get(4)?,
+            user_id: row.get(5)?,
+            comment: row.get(6)?,
+        })
+    }
+}
+
+/// Поля, которые нужно изменить. `None` оставляет прежнее значение,
+/// `comment: Some(None)` стирает комментарий.
+#[derive(Debug, Default)]
+pub struct ProceedsUpdate {
+    pub amount: Option<Money>,
+    pub date: Option<DateTime<Utc>>,
+    pub comment: Option<Option<String>>,
+    /// Перенос на другую точку
+    pub corner_id: Option<i32>,
+}
+
+/// Значения выручки до правки
+#[derive(Debug)]
+pub struct ProceedsEdit {
+    pub id: i32,
+    pub proceeds_id: i32,
+    pub editor_id: i32,
+    pub edit_date: DateTime<Utc>,
+    pub amount: Money,
+    pub date: DateTime<Utc>,
+    pub comment: Option<String>,
+    /// `None` у правок, записанных до переносов между точками
+    pub corner_id: Option<i32>,
+}
+
+impl ProceedsEdit {
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(ProceedsEdit {
+            id: row.get(0)?,
+            proceeds_id: row.get(1)?,
+            editor_id: row.get(2)?,
+            edit_date: Utc.timestamp(row.get(3)?, 0),
+            amount: row.get(4)?,
+            date: Utc.timestamp(row.get(5)?, 0),
+            comment: row.get(6)?,
+            corner_id: row.get("corner_id")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum UpdateError {
+    NotFound(i32),
+    NegativeAmount(Money),
+    CornerNotFound(i32),
+    Sql(rusqlite::Error),
+    Pool(String),
+}
+
+impl std::fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateError::NotFound(id) => write!(f, "proceeds {} not found", id),
+            UpdateError::NegativeAmount(amount) => write!(f, "negative amount {}", amount),
+            UpdateError::CornerNotFound(id) => write!(f, "corner {} not found", id),
+            UpdateError::Sql(e) => write!(f, "sqlite error: {}", e),
+            UpdateError::Pool(e) => write!(f, "connection pool error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {}
+
+impl From<rusqlite::Error> for UpdateError {
+    fn from(e: rusqlite::Error) -> Self {
+        UpdateError::Sql(e)
+    }
+}
+
+impl From<r2d2::Error> for UpdateError {
+    fn from(e: r2d2::Error) -> Self {
+        UpdateError::Pool(e.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for UpdateError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        UpdateError::Pool(e.to_string())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct User {
     id: i32,
@@ -340,11 +942,11 @@ impl User {
 pub enum ChatStep {
     Start,
     NotRegister,
-    Deactive
+    Deactive,
 }
 
 impl FromSql for ChatStep {
-    fn column_result(value: ValueRef) -> FromSqlResult<Self>{
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
         match value {
             ValueRef::Integer(_) => Ok(ChatStep::Start),
             _ => Err(FromSqlError::InvalidType),
@@ -352,12 +954,27 @@ impl FromSql for ChatStep {
     }
 }
 
+/// Суммы в базе хранятся в копейках
+impl FromSql for Money {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        i64::column_result(value).map(Money::from_kopecks)
+    }
+}
+
+impl ToSql for Money {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.kopecks()))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Corner {
     #[serde(skip_serializing_if = "i32_is_null")]
     id: i32,
     name: String,
     shrt_name: Option<String>,
+    /// Часовой пояс IANA, `None` - `storage::TIMEZONE`
+    timezone: Option<String>,
 }
 
 impl Corner {
@@ -365,9 +982,17 @@ impl Corner {
         Ok(Corner {
             id: row.get(0)?,
             name: row.get(1)?,
-            shrt_name: row.get(3)?,
+            shrt_name: row.get(2)?,
+            timezone: row.get("timezone")?,
         })
     }
+
+    pub fn tz(&self) -> Tz {
+        self.timezone
+            .as_deref()
+            .and_then(dates::zone)
+            .unwrap_or(crate::storage::TIMEZONE)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -375,7 +1000,7 @@ pub struct InviteCode {
     code: String,
     corner_id: i32,
     admin_id: i32,
-    gen_date: chrono::DateTime<chrono::Local>,
+    expire_date: DateTime<Utc>,
     used: bool,
 }
 
@@ -409,21 +1034,96 @@ fn gen_code() -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::PathBuf;
+
+    /// Отдельный файл базы на тест, удаляется вместе с WAL после теста
+    struct TempDb {
+        db: DataBase,
+        dir: PathBuf,
+    }
+
+    impl std::ops::Deref for TempDb {
+        type Target = DataBase;
+
+        fn deref(&self) -> &DataBase {
+            &self.db
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn temp_db() -> TempDb {
+        let dir = std::env::temp_dir().join(format!("cmbot-sqlite-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = DataBase::open(dir.join("database.db3").to_str().unwrap());
+        TempDb { db, dir }
+    }
 
     fn rand_id() -> i32 {
         rand::thread_rng().gen_range(1325039, 9142134)
     }
 
+    async fn new_corner(db: &DataBase) -> i32 {
+        let name = format!("Точка {}", rand_id());
+        db.push_corner(&name, None).await.unwrap();
+        db.get_corners()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|c| c.name == name)
+            .unwrap()
+            .id
+    }
+
+    async fn new_user(db: &DataBase, corner_id: i32) -> i32 {
+        let mut code = db.get_new_invite_code(corner_id, 1).await.unwrap();
+        code.push_str(" Петров Петр");
+        match db.register_user(code.as_str(), rand_id()).await.unwrap() {
+            RegisterResult::Succes(id) => id,
+            res => panic!("{:?}", res),
+        }
+    }
+
+    /// Выручка 500 ₽ сейчас, возвращает ее id
+    async fn push(db: &DataBase, corner_id: i32, user_id: i32) -> i32 {
+        let comment = format!("push {}", rand_id());
+        db.push_proceeds(Proceeds {
+            id: 0,
+            amount: Money::rubles(500),
+            date: Utc::now(),
+            post_date: Utc::now(),
+            corner_id,
+            user_id,
+            comment: Some(comment.clone()),
+        })
+        .await
+        .unwrap();
+        db.pool
+            .get()
+            .unwrap()
+            .query_row(
+                "SELECT id FROM proceeds WHERE comment=?1",
+                params![comment],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
     #[tokio::test]
     async fn full_invite_check() {
-        let db = DataBase::custom_init();
-        let code = db.get_new_invite_code(1, 1).await.unwrap();
-        let conn = db.conn.lock().await;
+        let db = temp_db();
+        let corner_id = new_corner(&db).await;
+        let code = db.get_new_invite_code(corner_id, 1).await.unwrap();
+        let conn = db.pool.get().unwrap();
         assert!(!conn.invite_code_exist("1234ABCD").unwrap());
         assert!(conn.invite_code_exist(code.as_str()).unwrap());
         let res = conn.use_invite_code(code.as_str()).unwrap();
         match res {
-            RegisterResult::Succes(x) => assert_eq!(x, 1),
+            RegisterResult::Succes(x) => assert_eq!(x, corner_id),
             _ => panic!(),
         }
 
@@ -437,9 +1137,9 @@ mod tests {
     #[tokio::test]
     async fn register_check() {
         let tg_id: i32 = rand_id();
-        let db = DataBase::custom_init();
-        let mut code = db.get_new_invite_code(10, 1).await.unwrap();
-        dbg!(code.clone());
+        let db = temp_db();
+        let corner_id = new_corner(&db).await;
+        let mut code = db.get_new_invite_code(corner_id, 1).await.unwrap();
         code.push_str(" Иванов Иван");
         let res = db.register_user(code.as_str(), tg_id).await.unwrap();
         let mut last_id: i32 = 0;
@@ -451,10 +1151,455 @@ mod tests {
             id: last_id,
             tg_id,
             name: String::from("Иванов Иван"),
-            corner_id: 10,
+            corner_id,
             is_active: true,
-            step: ChatStep::Start
+            step: ChatStep::Start,
         };
         assert_eq!(user_in, user_out);
     }
+
+    #[test]
+    fn migrations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        migrate(&mut conn).unwrap();
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+
+        let res = conn.execute(
+            "INSERT INTO invite_code (code, corner_id, admin_id, expire_date, used)
+            VALUES ('ABCD1234', 100500, 1, 0, 0)",
+            NO_PARAMS,
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn migrate_baseline_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // база до миграций: user_version 0, у приглашений gen_date, суммы в рублях
+        let tx = conn.transaction().unwrap();
+        initial_schema(&tx).unwrap();
+        tx.commit().unwrap();
+        conn.execute_batch(
+            "INSERT INTO corner (id, name) VALUES (1, 'Вокзал');
+            INSERT INTO user (id, tg_id, name, corner_id, is_active, step)
+                VALUES (1, 100, 'Иван', 1, 1, 0);
+            INSERT INTO proceeds (amount, date, post_date, corner_id, user_id)
+                VALUES (1500, 1600000000, 1600000000, 1, 1);
+            INSERT INTO invite_code (code, corner_id, admin_id, gen_date, used)
+                VALUES ('ABCD1234', 1, 1, 1600000000, 0);",
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+        let expire: i64 = conn
+            .query_row(
+                "SELECT expire_date FROM invite_code WHERE code='ABCD1234'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(expire, 1_600_000_000 + INVITE_TTL_DAYS * 86400);
+        let amount: Money = conn
+            .query_row("SELECT amount FROM proceeds", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(amount, Money::rubles(1500));
+
+        // внешние ключи снова проверяются, в том числе у пересобранной таблицы
+        let insert = |sql: &str| conn.execute(sql, NO_PARAMS);
+        assert!(insert(
+            "INSERT INTO invite_code (code, corner_id, admin_id, expire_date, used)
+            VALUES ('EFGH5678', 2, 1, 0, 0)"
+        )
+        .is_err());
+        assert!(insert(
+            "INSERT INTO proceeds (amount, date, post_date, corner_id, user_id)
+            VALUES (100, 0, 0, 2, 1)"
+        )
+        .is_err());
+        insert(
+            "INSERT INTO invite_code (code, corner_id, admin_id, expire_date, used)
+            VALUES ('EFGH5678', 1, 1, 0, 0)",
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn soft_delete_restore_purge() {
+        let db = temp_db();
+        let corner_id = new_corner(&db).await;
+        let user_id = new_user(&db, corner_id).await;
+        let comment = format!("trash {}", rand_id());
+        db.push_proceeds(Proceeds {
+            id: 0,
+            amount: Money::rubles(500),
+            date: Utc::now(),
+            post_date: Utc::now(),
+            corner_id,
+            user_id,
+            comment: Some(comment.clone()),
+        })
+        .await
+        .unwrap();
+        let id = db
+            .get_proceeds()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|pr| pr.comment.as_ref() == Some(&comment))
+            .unwrap()
+            .id;
+        let in_trash = |trash: Trash| trash.proceeds.iter().any(|d| d.item.id == id);
+
+        db.del_proceeds(id).await.unwrap();
+        let proceeds = db.get_proceeds().await.unwrap();
+        assert!(proceeds.iter().all(|pr| pr.id != id));
+        assert!(in_trash(db.get_trash().await.unwrap()));
+
+        assert!(db.restore_proceeds(id).await.unwrap());
+        assert!(!db.restore_proceeds(id).await.unwrap());
+        let proceeds = db.get_proceeds().await.unwrap();
+        assert!(proceeds.iter().any(|pr| pr.id == id));
+
+        db.del_proceeds(id).await.unwrap();
+        db.purge_deleted(chrono::Duration::days(30)).await.unwrap();
+        assert!(in_trash(db.get_trash().await.unwrap()));
+        let retention = chrono::Duration::seconds(-1);
+        db.purge_deleted(retention).await.unwrap();
+        assert!(!in_trash(db.get_trash().await.unwrap()));
+        assert!(!db.restore_proceeds(id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn update_proceeds_check() {
+        let db = temp_db();
+        let corner_id = new_corner(&db).await;
+        let user_id = new_user(&db, corner_id).await;
+        let comment = format!("edit {}", rand_id());
+        db.push_proceeds(Proceeds {
+            id: 0,
+            amount: Money::rubles(1000),
+            date: Utc::now(),
+            post_date: Utc::now(),
+            corner_id,
+            user_id,
+            comment: Some(comment.clone()),
+        })
+        .await
+        .unwrap();
+        let id = db
+            .get_proceeds()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|pr| pr.comment.as_ref() == Some(&comment))
+            .unwrap()
+            .id;
+
+        let upd = ProceedsUpdate {
+            amount: Some(Money::from_kopecks(150_050)),
+            comment: Some(None),
+            ..Default::default()
+        };
+        let pr = db.update_proceeds(id, upd, 7).await.unwrap();
+        assert_eq!(pr.amount, Money::from_kopecks(150_050));
+        assert_eq!(pr.comment, None);
+        let edits = db.get_proceeds_edits(id).await.unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].editor_id, 7);
+        assert_eq!(edits[0].amount, Money::rubles(1000));
+        assert_eq!(edits[0].comment, Some(comment));
+
+        let upd = ProceedsUpdate {
+            amount: Some(Money::from_kopecks(-1)),
+            ..Default::default()
+        };
+        match db.update_proceeds(id, upd, 7).await {
+            Err(UpdateError::NegativeAmount(amount)) => assert_eq!(amount.kopecks(), -1),
+            res => panic!("{:?}", res),
+        }
+        match db.update_proceeds(-1, ProceedsUpdate::default(), 7).await {
+            Err(UpdateError::NotFound(-1)) => {}
+            res => panic!("{:?}", res),
+        }
+
+        // перенос только на действующую точку
+        let deleted = new_corner(&db).await;
+        db.del_corner(deleted).await.unwrap();
+        let move_to = |corner_id| ProceedsUpdate {
+            corner_id: Some(corner_id),
+            ..Default::default()
+        };
+        match db.update_proceeds(id, move_to(deleted), 7).await {
+            Err(UpdateError::CornerNotFound(c)) => assert_eq!(c, deleted),
+            res => panic!("{:?}", res),
+        }
+        let other = new_corner(&db).await;
+        let pr = db.update_proceeds(id, move_to(other), 7).await.unwrap();
+        assert_eq!(pr.corner_id, other);
+        let edits = db.get_proceeds_edits(id).await.unwrap();
+        assert_eq!(edits[1].corner_id, Some(corner_id));
+
+        // выручка уходит в корзину вместе с точкой и возвращается с ней
+        db.del_corner(other).await.unwrap();
+        match db.update_proceeds(id, move_to(corner_id), 7).await {
+            Err(UpdateError::NotFound(i)) => assert_eq!(i, id),
+            res => panic!("{:?}", res),
+        }
+        assert!(db.restore_corner(other).await.unwrap());
+        assert!(!db.restore_corner(other).await.unwrap());
+        db.update_proceeds(id, move_to(corner_id), 7).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_corner_with_staff() {
+        let db = temp_db();
+        let corner_id = new_corner(&db).await;
+        let user_id = new_user(&db, corner_id).await;
+        let other_id = new_user(&db, corner_id).await;
+        // сотрудник, удаленный раньше точки, с ней не возвращается
+        db.del_user(other_id).await.unwrap();
+        db.del_corner(corner_id).await.unwrap();
+        assert!(db.get_user(user_id).await.is_err());
+        let trash = db.get_trash().await.unwrap();
+        assert!(trash.corners.iter().any(|d| d.item.id == corner_id));
+        assert!(trash.users.iter().any(|d| d.item.id == user_id));
+
+        // второе удаление ничего не меняет
+        db.del_corner(corner_id).await.unwrap();
+        assert!(db.restore_corner(corner_id).await.unwrap());
+        assert_eq!(db.get_user(user_id).await.unwrap().corner_id, corner_id);
+        assert!(db.get_user(other_id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn purge_by_age() {
+        let db = temp_db();
+        let old = new_corner(&db).await;
+        let user_id = new_user(&db, old).await;
+        push(&db, old, user_id).await;
+        db.del_corner(old).await.unwrap();
+        let kept = new_corner(&db).await;
+        let kept_user = new_user(&db, kept).await;
+        let recent = push(&db, kept, kept_user).await;
+        db.del_proceeds(recent).await.unwrap();
+        // точка в корзине, но на нее еще ссылается действующий сотрудник
+        let referenced = new_corner(&db).await;
+        new_user(&db, referenced).await;
+
+        let month_ago = (Utc::now() - chrono::Duration::days(40)).timestamp();
+        let conn = db.pool.get().unwrap();
+        for table in &["corner", "user", "proceeds"] {
+            conn.execute(
+                &format!(
+                    "UPDATE {} SET deleted_at=?1 WHERE deleted_at IS NOT NULL AND {}",
+                    table,
+                    if *table == "corner" {
+                        "id=?2"
+                    } else {
+                        "corner_id=?2"
+                    }
+                ),
+                params![month_ago, old],
+            )
+            .unwrap();
+        }
+        conn.execute(
+            "UPDATE corner SET deleted_at=?1 WHERE id=?2",
+            params![month_ago, referenced],
+        )
+        .unwrap();
+
+        db.purge_deleted(chrono::Duration::days(30)).await.unwrap();
+        let count = |sql: &str, id: i32| -> i64 {
+            conn.query_row(sql, params![id], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(count("SELECT count(*) FROM corner WHERE id=?1", old), 0);
+        assert_eq!(
+            count("SELECT count(*) FROM user WHERE corner_id=?1", old),
+            0
+        );
+        assert_eq!(
+            count("SELECT count(*) FROM proceeds WHERE corner_id=?1", old),
+            0
+        );
+        assert_eq!(
+            count("SELECT count(*) FROM invite_code WHERE corner_id=?1", old),
+            0
+        );
+        assert_eq!(
+            count("SELECT count(*) FROM proceeds WHERE id=?1", recent),
+            1
+        );
+        assert_eq!(
+            count("SELECT count(*) FROM corner WHERE id=?1", referenced),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn purge_corner_with_edits() {
+        let db = temp_db();
+        let kept = new_corner(&db).await;
+        let user_id = new_user(&db, kept).await;
+        let old = new_corner(&db).await;
+        // выручку перенесли с точки, которую потом удалили
+        let id = push(&db, old, user_id).await;
+        let upd = ProceedsUpdate {
+            corner_id: Some(kept),
+            ..Default::default()
+        };
+        db.update_proceeds(id, upd, 7).await.unwrap();
+        db.del_corner(old).await.unwrap();
+        let month_ago = (Utc::now() - chrono::Duration::days(40)).timestamp();
+        let conn = db.pool.get().unwrap();
+        conn.execute(
+            "UPDATE corner SET deleted_at=?1 WHERE id=?2",
+            params![month_ago, old],
+        )
+        .unwrap();
+
+        db.purge_deleted(chrono::Duration::days(30)).await.unwrap();
+        let corners: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM corner WHERE id=?1",
+                params![old],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(corners, 0);
+        let edits = db.get_proceeds_edits(id).await.unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].corner_id, None);
+    }
+
+    #[tokio::test]
+    async fn corner_timezone_days() {
+        let db = temp_db();
+        let corner_id = new_corner(&db).await;
+        let vladivostok = dates::zone("Asia/Vladivostok").unwrap();
+        db.set_corner_timezone(corner_id, vladivostok)
+            .await
+            .unwrap();
+        let corner = db
+            .get_corners()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|c| c.id == corner_id)
+            .unwrap();
+        assert_eq!(corner.tz(), vladivostok);
+
+        // приглашение действует INVITE_TTL_DAYS суток с момента создания
+        let before = Utc::now().timestamp();
+        let code = db.get_new_invite_code(corner_id, 1).await.unwrap();
+        let expire: i64 = db
+            .pool
+            .get()
+            .unwrap()
+            .query_row(
+                "SELECT expire_date FROM invite_code WHERE code=?1",
+                params![code],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let ttl = INVITE_TTL_DAYS * 86400;
+        assert!((before + ttl..=Utc::now().timestamp() + ttl).contains(&expire));
+
+        // 2 марта 16:00 UTC во Владивостоке уже 3 марта
+        let user_id = new_user(&db, corner_id).await;
+        for &(day, hour) in &[(1, 14), (2, 13), (2, 16)] {
+            let date = Utc.ymd(2020, 3, day).and_hms(hour, 0, 0);
+            let pr = Proceeds {
+                id: 0,
+                amount: Money::rubles(100),
+                date,
+                post_date: date,
+                corner_id,
+                user_id,
+                comment: None,
+            };
+            db.push_proceeds(pr).await.unwrap();
+        }
+        let march2 = NaiveDate::from_ymd(2020, 3, 2);
+        let proceeds = db.get_day_proceeds(corner_id, march2).await.unwrap();
+        let hours: Vec<String> = proceeds
+            .iter()
+            .map(|pr| pr.date.format("%d %H").to_string())
+            .collect();
+        assert_eq!(hours, vec!["01 14", "02 13"]);
+        assert!(proceeds.iter().all(|pr| pr.day(vladivostok) == march2));
+        assert_eq!(proceeds[0].day(crate::storage::TIMEZONE), march2.pred());
+    }
+
+    /// Грубый бенчмарк: чтения не должны ждать записи
+    #[tokio::test(threaded_scheduler)]
+    async fn concurrent_proceeds_bench() {
+        const TASKS: usize = 32;
+        let db = temp_db();
+        let corner_id = new_corner(&db).await;
+        let user_id = new_user(&db, corner_id).await;
+        let bound = std::time::Duration::from_secs(2);
+
+        // пока открыта транзакция записи, чтение идет без ожидания
+        // и не видит незафиксированную выручку
+        let writer = db.pool.get().unwrap();
+        writer
+            .execute_batch(&format!(
+                "BEGIN EXCLUSIVE;
+                INSERT INTO proceeds (amount, date, post_date, corner_id, user_id, comment)
+                VALUES (100, 0, 0, {}, {}, 'uncommitted');",
+                corner_id, user_id
+            ))
+            .unwrap();
+        let proceeds = tokio::time::timeout(bound, db.get_proceeds())
+            .await
+            .expect("read waits for the writer")
+            .unwrap();
+        assert!(proceeds.iter().all(|pr| pr.comment.is_none()));
+        writer.execute_batch("ROLLBACK").unwrap();
+        drop(writer);
+
+        let start = std::time::Instant::now();
+        let mut handles = Vec::with_capacity(TASKS * 2);
+        for i in 0..TASKS {
+            let writer = db.clone();
+            handles.push(tokio::spawn(async move {
+                writer
+                    .push_proceeds(Proceeds {
+                        id: 0,
+                        amount: Money::rubles(i as i64),
+                        date: Utc::now(),
+                        post_date: Utc::now(),
+                        corner_id,
+                        user_id,
+                        comment: Some("bench".to_owned()),
+                    })
+                    .await
+                    .map(|_| ())
+            }));
+            let reader = db.clone();
+            handles.push(tokio::spawn(async move {
+                reader.get_proceeds().await.map(|_| ())
+            }));
+        }
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+        assert_eq!(db.get_proceeds().await.unwrap().len(), TASKS);
+        assert!(start.elapsed() < bound * 5, "{:?}", start.elapsed());
+        println!(
+            "{} push_proceeds + {} get_proceeds: {:?}",
+            TASKS,
+            TASKS,
+            start.elapsed()
+        );
+    }
 }
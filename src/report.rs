@@ -0,0 +1,547 @@
+//! Текстовые отчеты для чата
+
+use crate::bot::Bot;
+use crate::chart;
+use crate::forecast::{self, Forecast};
+use crate::locale::{Lang, Msg};
+use crate::money::Money;
+use crate::storage::{self, Chat, Corner, DataBase, Group, Node, PlanFact, Revenue, Role, Stats};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+/// Выручка, расходы и чистая выручка точек за день с выполнением плана месяца
+pub fn day_summary(db: &DataBase, chat: &Chat, lang: Lang, day: u32) -> storage::Result<String> {
+    let mut out = lang.day_title(Revenue::date_of(day)?) + "\n";
+    let names = db.payment_method_names()?;
+    for corner in db.visible_corners(chat)? {
+        if corner.archived {
+            continue;
+        }
+        let revenue = db.get_revenue(day, corner.id)?;
+        match &revenue {
+            Some(rev) => {
+                let parts: Vec<_> = rev
+                    .breakdown()
+                    .iter()
+                    .map(|p| (p.method, p.amount))
+                    .collect();
+                let flag = if rev.missing_photo(&corner) {
+                    lang.text(Msg::NoPhotoFlag)
+                } else {
+                    ""
+                };
+                writeln!(
+                    out,
+                    "{}: {}{}{}",
+                    corner.name,
+                    lang.money(rev.amount),
+                    breakdown(&names, lang, &parts),
+                    flag
+                )
+            }
+            None => writeln!(out, "{}: {}", corner.name, lang.text(Msg::NotReported)),
+        }
+        .unwrap();
+        let expenses: Money = db
+            .get_expenses(day, corner.id)?
+            .iter()
+            .map(|e| e.amount)
+            .sum();
+        if expenses > Money::ZERO {
+            let revenue = revenue.map_or(Money::ZERO, |r| r.amount);
+            writeln!(out, "  {}", lang.net(expenses, revenue - expenses)).unwrap();
+        }
+        if let Some(pf) = db.plan_fact(corner.id, day)? {
+            writeln!(out, "  {}", plan_line(&pf, lang)).unwrap();
+        }
+    }
+    Ok(out)
+}
+
+pub fn plan_line(pf: &PlanFact, lang: Lang) -> String {
+    lang.plan(
+        pf.actual,
+        pf.month_plan,
+        pf.percent(),
+        pf.forecast,
+        pf.forecast_percent(),
+    )
+}
+
+/// " (Наличные 500 ₽, Карта 700 ₽)", если известно больше, чем "не указано"
+fn breakdown(names: &HashMap<u32, String>, lang: Lang, parts: &[(u32, Money)]) -> String {
+    match parts {
+        [] | [(storage::UNSPECIFIED, _)] => String::new(),
+        parts => format!(
+            " ({})",
+            storage::format_breakdown(names, lang, parts.iter().copied())
+        ),
+    }
+}
+
+/// Каждый день в `hour` часов по поясу администратора рассылает ему
+/// итоги дня и графики за неделю. Поясов много, поэтому проверка раз в час.
+pub async fn summary_job(db: DataBase, bot: Bot, hour: u32) {
+    loop {
+        let now = Utc::now().timestamp();
+        let wait = 3600 - now.rem_euclid(3600) as u64;
+        tokio::time::delay_for(std::time::Duration::from_secs(wait)).await;
+
+        if let Err(e) = send_summaries(&db, &bot, hour, Utc::now()).await {
+            eprintln!("ERROR: daily summary: {}", e);
+        }
+    }
+}
+
+async fn send_summaries(
+    db: &DataBase,
+    bot: &Bot,
+    hour: u32,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    for (chat_id, chat) in db.get_chats()? {
+        if let Some(day) = summary_day(db, &chat, hour, now)? {
+            let lang = chat.lang.unwrap_or_default();
+            let text = day_summary(db, &chat, lang, day)?;
+            bot.send_message(chat_id, text).await?;
+            for (png, caption) in charts(db, &chat, lang, None, day.saturating_sub(6), day)? {
+                bot.send_png(chat_id, png, caption).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// День, итоги которого пора отправить администратору `chat`:
+/// `Some`, если в его поясе сейчас идет час `hour`
+fn summary_day(
+    db: &DataBase,
+    chat: &Chat,
+    hour: u32,
+    now: DateTime<Utc>,
+) -> storage::Result<Option<u32>> {
+    if let (true, Role::Admin(_)) = (chat.is_active, chat.role) {
+        let local = now.with_timezone(&db.chat_tz(chat)?);
+        if local.hour() == hour {
+            return Revenue::day_of(local.date().naive_local()).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Выручка за месяц по всем уровням иерархии, которые видит собеседник
+pub fn month_summary(
+    db: &DataBase,
+    chat: &Chat,
+    lang: Lang,
+    month: u32,
+) -> storage::Result<String> {
+    let mut out = lang.month_title(Stats::month_start(month)?) + "\n";
+    let tree = Hierarchy {
+        db,
+        lang,
+        month,
+        names: db.payment_method_names()?,
+        groups: db.get_groups()?,
+        corners: db.visible_corners(chat)?,
+    };
+    match chat.role {
+        Role::Staff => {
+            for corner in &tree.corners {
+                tree.corner(&mut out, corner, 0)?;
+            }
+        }
+        Role::Admin(Some(scope)) => {
+            if let Some(group) = tree.groups.iter().find(|g| g.id == scope) {
+                tree.group(&mut out, group, 0)?;
+            }
+        }
+        Role::Admin(None) => {
+            let total = tree.children(&mut out, None, 0)?;
+            let _ = writeln!(out, "{}", lang.total(total));
+        }
+    }
+    Ok(out)
+}
+
+/// Выручка и смены по сотрудникам за месяц на видимых точках
+pub fn staff_summary(
+    db: &DataBase,
+    chat: &Chat,
+    lang: Lang,
+    month: u32,
+) -> storage::Result<String> {
+    let corners: HashSet<u32> = db.visible_corners(chat)?.iter().map(|c| c.id).collect();
+    let from = Stats::first_day(month)?;
+    let to = Stats::first_day(month + 1)? - 1;
+    let names: HashMap<i64, String> = db
+        .get_chats()?
+        .into_iter()
+        .map(|(id, c)| (id, c.name))
+        .collect();
+    let mut totals: Vec<_> = db.staff_totals(&corners, from, to)?.into_iter().collect();
+    // без сотрудника - в конце
+    totals.sort_by_key(|(staff, t)| (staff.is_none(), std::cmp::Reverse(t.revenue)));
+
+    let mut out = lang.staff_title(Stats::month_start(month)?) + "\n";
+    if totals.is_empty() {
+        out += lang.text(Msg::NoData);
+        out += "\n";
+    }
+    for (staff, t) in totals {
+        let name = match staff {
+            Some(id) => names.get(&id).cloned().unwrap_or_else(|| id.to_string()),
+            None => lang.text(Msg::NoStaff).to_owned(),
+        };
+        out += &lang.staff_revenue(&name, t.revenue, t.days as u64);
+        if t.shifts > 0 {
+            out += &lang.staff_shifts(t.shifts, t.seconds);
+        }
+        out += "\n";
+    }
+    Ok(out)
+}
+
+/// Прогноз выручки видимых точек на следующую неделю и месяц
+pub fn forecast_summary(
+    db: &DataBase,
+    chat: &Chat,
+    lang: Lang,
+    today: u32,
+) -> storage::Result<String> {
+    let mut out = lang.text(Msg::ForecastTitle).to_owned() + "\n";
+    for corner in db.visible_corners(chat)? {
+        if corner.archived {
+            continue;
+        }
+        match forecast::corner_forecast(db, corner.id, today)? {
+            Some((week, month)) => {
+                let _ = writeln!(out, "{}:", corner.name);
+                let _ = writeln!(out, "  {}", forecast_line(&week, lang)?);
+                let _ = writeln!(out, "  {}", forecast_line(&month, lang)?);
+            }
+            None => {
+                let _ = writeln!(out, "{}: {}", corner.name, lang.text(Msg::FewData));
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn forecast_line(f: &Forecast, lang: Lang) -> storage::Result<String> {
+    Ok(lang.forecast(
+        Revenue::date_of(f.from)?,
+        Revenue::date_of(f.to)?,
+        f.expected,
+        f.low,
+        f.high,
+    ))
+}
+
+/// Графики PNG с подписями за дни с `from` по `to`: выручка по дням, сравнение
+/// точек и план-факт на `to`. Точки на графиках пронумерованы, номера
+/// расшифрованы в подписи. `only` - показать одну точку.
+pub fn charts(
+    db: &DataBase,
+    chat: &Chat,
+    lang: Lang,
+    only: Option<u32>,
+    from: u32,
+    to: u32,
+) -> storage::Result<Vec<(Vec<u8>, String)>> {
+    let mut corners = db.visible_corners(chat)?;
+    corners.retain(|c| !c.archived && only.iter().all(|id| *id == c.id));
+    let (from_date, to_date) = (Revenue::date_of(from)?, Revenue::date_of(to)?);
+    let legend = |corners: &[&Corner]| {
+        let names: Vec<_> = corners
+            .iter()
+            .enumerate()
+            .map(|(i, c)| format!("{} - {}", i + 1, c.name))
+            .collect();
+        names.join(", ")
+    };
+    let mut res = Vec::new();
+
+    // столбцы в целых рублях
+    let rubles = |sum: Money| sum.whole_rubles().max(0) as u64;
+    let mut days = vec![Money::ZERO; (to + 1 - from) as usize];
+    let mut totals = Vec::new();
+    for corner in &corners {
+        let mut total = Money::ZERO;
+        for (day, amount) in db.daily_revenue(corner.id, from, to)? {
+            days[(day - from) as usize] += amount;
+            total += amount;
+        }
+        totals.push(total);
+    }
+    let mut bars = Vec::new();
+    for (date, v) in from_date.iter_days().zip(&days) {
+        bars.push(chart::Bar::new(date.day(), rubles(*v)));
+    }
+    let whose = match corners.as_slice() {
+        [corner] => corner.name.clone(),
+        _ => lang.text(Msg::AllCorners).to_owned(),
+    };
+    res.push((
+        chart::bars(&bars),
+        lang.chart_days(from_date, to_date, &whose),
+    ));
+
+    if corners.len() > 1 {
+        let bars: Vec<_> = totals
+            .iter()
+            .enumerate()
+            .map(|(i, v)| chart::Bar::new(i + 1, rubles(*v)))
+            .collect();
+        let all: Vec<_> = corners.iter().collect();
+        res.push((
+            chart::bars(&bars),
+            lang.chart_corners(from_date, to_date, &legend(&all)),
+        ));
+    }
+
+    let mut planned = Vec::new();
+    let mut bars = Vec::new();
+    for corner in &corners {
+        if let Some(pf) = db.plan_fact(corner.id, to)? {
+            planned.push(corner);
+            bars.push(chart::Bar {
+                label: planned.len().to_string(),
+                value: rubles(pf.actual),
+                plan: Some(rubles(pf.plan_to_date)),
+            });
+        }
+    }
+    if !bars.is_empty() {
+        res.push((
+            chart::bars(&bars),
+            lang.chart_plan(to_date, &legend(&planned)),
+        ));
+    }
+    Ok(res)
+}
+
+struct Hierarchy<'a> {
+    db: &'a DataBase,
+    lang: Lang,
+    month: u32,
+    names: HashMap<u32, String>,
+    groups: Vec<Group>,
+    corners: Vec<Corner>,
+}
+
+impl Hierarchy<'_> {
+    fn group(&self, out: &mut String, group: &Group, depth: usize) -> storage::Result<Money> {
+        let stats = self.db.stats(Node::Group(group.id), self.month)?;
+        self.line(out, &group.name, &stats, depth);
+        self.children(out, Some(group.id), depth + 1)?;
+        Ok(stats.revenue)
+    }
+
+    /// Подгруппы и точки группы `parent`. Возвращает их общую выручку.
+    fn children(
+        &self,
+        out: &mut String,
+        parent: Option<u32>,
+        depth: usize,
+    ) -> storage::Result<Money> {
+        let mut total = Money::ZERO;
+        for group in self.groups.iter().filter(|g| g.parent_id == parent) {
+            total += self.group(out, group, depth)?;
+        }
+        for corner in self.corners.iter().filter(|c| c.group_id == parent) {
+            total += self.corner(out, corner, depth)?;
+        }
+        Ok(total)
+    }
+
+    fn corner(&self, out: &mut String, corner: &Corner, depth: usize) -> storage::Result<Money> {
+        let stats = self.db.stats(Node::Corner(corner.id), self.month)?;
+        if !corner.archived || stats.revenue > Money::ZERO {
+            self.line(out, &corner.name, &stats, depth);
+        }
+        Ok(stats.revenue)
+    }
+
+    fn line(&self, out: &mut String, name: &str, stats: &Stats, depth: usize) {
+        let parts: Vec<_> = stats.by_method.iter().map(|(m, a)| (*m, *a)).collect();
+        let _ = write!(
+            out,
+            "{:indent$}{}: {}{}",
+            "",
+            name,
+            self.lang.money(stats.revenue),
+            breakdown(&self.names, self.lang, &parts),
+            indent = depth * 2
+        );
+        if stats.expenses > Money::ZERO {
+            let net = stats.revenue - stats.expenses;
+            let _ = write!(out, ". {}", self.lang.net(stats.expenses, net));
+        }
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{CornerInfo, Expense, TargetPeriod};
+    use chrono::{NaiveDate, TimeZone};
+
+    #[test]
+    fn summary_by_scope() {
+        let db = DataBase::temporary();
+        let region = db.add_group("Регион", None).unwrap();
+        let city = db.add_group("Город", Some(region.id)).unwrap();
+        let date = NaiveDate::from_ymd(2020, 3, 5);
+        for (name, amount, group) in &[("А", 100, Some(city.id)), ("Б", 20, None)] {
+            let corner = db
+                .add_corner(CornerInfo {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .unwrap();
+            db.set_corner_group(corner.id, *group).unwrap();
+            db.put_revenue(&Revenue {
+                corner_id: corner.id,
+                date: Revenue::day(date).unwrap(),
+                amount: Money::rubles(*amount),
+                post_datetime: 0,
+                comment: None,
+                payments: Vec::new(),
+                photo: None,
+                staff: None,
+            })
+            .unwrap();
+        }
+        let month = Stats::month(Revenue::day(date).unwrap()).unwrap();
+        let chat = |role| Chat {
+            corner_id: 2,
+            name: "Мария".to_owned(),
+            is_active: true,
+            role,
+            pay: None,
+            lang: None,
+        };
+
+        assert_eq!(
+            month_summary(&db, &chat(Role::Admin(None)), Lang::Ru, month).unwrap(),
+            "Выручка за 03.2020\nРегион: 100 ₽\n  Город: 100 ₽\n    А: 100 ₽\nБ: 20 ₽\nИтого: 120 ₽\n"
+        );
+        assert_eq!(
+            month_summary(&db, &chat(Role::Admin(Some(city.id))), Lang::Ru, month).unwrap(),
+            "Выручка за 03.2020\nГород: 100 ₽\n  А: 100 ₽\n"
+        );
+        assert_eq!(
+            month_summary(&db, &chat(Role::Staff), Lang::Ru, month).unwrap(),
+            "Выручка за 03.2020\nБ: 20 ₽\n"
+        );
+        assert_eq!(
+            month_summary(&db, &chat(Role::Admin(Some(city.id))), Lang::En, month).unwrap(),
+            "Revenue for Mar 2020\nГород: ₽100\n  А: ₽100\n"
+        );
+    }
+
+    #[test]
+    fn summary_hour_by_zone() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Набережная".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        db.set_corner_timezone(corner.id, "Asia/Vladivostok")
+            .unwrap();
+        // у администратора без точки пояс по умолчанию, раз пояса точек разные
+        db.add_corner(CornerInfo {
+            name: "Вокзал".to_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        let admin = |corner_id| Chat {
+            corner_id,
+            name: "Анна".to_owned(),
+            is_active: true,
+            role: Role::Admin(None),
+            pay: None,
+            lang: None,
+        };
+        let (east, moscow) = (admin(corner.id), admin(0));
+        // 2020-03-01 13:00 UTC: 23:00 во Владивостоке, 16:00 в Москве
+        let now = Utc.ymd(2020, 3, 1).and_hms(13, 0, 0);
+        let march1 = Revenue::day(NaiveDate::from_ymd(2020, 3, 1)).unwrap();
+        assert_eq!(summary_day(&db, &east, 23, now).unwrap(), Some(march1));
+        assert_eq!(summary_day(&db, &moscow, 23, now).unwrap(), None);
+        let later = now + chrono::Duration::hours(7);
+        assert_eq!(summary_day(&db, &moscow, 23, later).unwrap(), Some(march1));
+        assert_eq!(summary_day(&db, &east, 23, later).unwrap(), None);
+        let staff = Chat {
+            role: Role::Staff,
+            ..east
+        };
+        assert_eq!(summary_day(&db, &staff, 23, now).unwrap(), None);
+    }
+
+    #[test]
+    fn day_with_plan() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let date = NaiveDate::from_ymd(2020, 4, 2);
+        let day = Revenue::day(date).unwrap();
+        db.set_target(corner.id, day - 1, TargetPeriod::Day, Money::rubles(1_000))
+            .unwrap();
+        db.put_revenue(&Revenue {
+            corner_id: corner.id,
+            date: day,
+            amount: Money::rubles(1_500),
+            post_datetime: 0,
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff: None,
+        })
+        .unwrap();
+        db.add_expense(Expense {
+            corner_id: corner.id,
+            date: day,
+            id: 0,
+            category: "уборка".to_owned(),
+            amount: Money::rubles(200),
+            comment: None,
+            photo: None,
+            post_datetime: 0,
+        })
+        .unwrap();
+        let admin = Chat {
+            corner_id: 0,
+            name: "Админ".to_owned(),
+            is_active: true,
+            role: Role::Admin(None),
+            pay: None,
+            lang: None,
+        };
+        assert_eq!(
+            day_summary(&db, &admin, Lang::Ru, day).unwrap(),
+            "Итоги за 02.04.2020\nГалерея: 1500 ₽\n  Расходы: 200 ₽, чистыми 1300 ₽\n  План месяца: 1500 ₽ из 30 000 ₽, 75% плана на дату. Прогноз 22 500 ₽ (75%)\n"
+        );
+        assert_eq!(
+            month_summary(&db, &admin, Lang::Ru, Stats::month(day).unwrap()).unwrap(),
+            "Выручка за 04.2020\nГалерея: 1500 ₽. Расходы: 200 ₽, чистыми 1300 ₽\nИтого: 1500 ₽\n"
+        );
+
+        db.set_photo_required(corner.id, true).unwrap();
+        let summary = day_summary(&db, &admin, Lang::Ru, day).unwrap();
+        assert!(summary.contains("Галерея: 1500 ₽, нет фото Z-отчета\n"));
+        db.set_revenue_photo(day, corner.id, "AgAD").unwrap();
+        assert!(!day_summary(&db, &admin, Lang::Ru, day)
+            .unwrap()
+            .contains("фото"));
+    }
+}
@@ -1,5 +1,39 @@
-use chrono::{Date, DateTime, Local, TimeZone};
+use crate::dates;
+use crate::locale::Lang;
+use crate::money::Money;
+use chrono::{Datelike, NaiveDate};
+use chrono_tz::Tz;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sled::transaction::ConflictableTransactionError;
+use sled::Transactional;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+mod anomaly;
+mod backup;
+mod corners;
+mod error;
+mod expenses;
+mod export;
+mod groups;
+mod import;
+mod legacy;
+mod payments;
+mod payroll;
+mod photos;
+mod secrets;
+mod shifts;
+mod stats;
+mod targets;
+pub use anomaly::Anomaly;
+pub use backup::backup_job;
+pub use corners::CornerInfo;
+pub use error::{Error, Result};
+pub use expenses::EXPENSE_CATEGORIES;
+pub use import::{ImportFormat, OnConflict};
+pub use payments::format_breakdown;
+pub use stats::Node;
+pub use targets::PlanFact;
 
 #[derive(Clone)]
 pub struct DataBase {
@@ -7,92 +41,421 @@ pub struct DataBase {
 }
 
 impl DataBase {
-    pub fn open() -> Self {
-        let sled = sled::Config::new()
-            .path("database".to_owned())
-            .cache_capacity(250_000_000)
-            .mode(sled::Mode::HighThroughput)
-            .open()
-            .map_err(|e| {
-                eprintln!("ERROR: Can't open sled database");
-                e
-            })
-            .unwrap();
+    pub fn open() -> Result<Self> {
+        Self::open_path(std::path::Path::new("database"))
+    }
+
+    pub fn open_path(path: &std::path::Path) -> Result<Self> {
+        Self::open_with(
+            sled::Config::new()
+                .path(path)
+                .cache_capacity(250_000_000)
+                .mode(sled::Mode::HighThroughput),
+        )
+    }
+
+    /// Отдельная база в памяти для тестов
+    #[cfg(test)]
+    pub fn temporary() -> Self {
+        Self::open_with(sled::Config::new().temporary(true)).unwrap()
+    }
+
+    fn open_with(config: sled::Config) -> Result<Self> {
+        let sled = config.open()?;
         sled.iter().count();
         for name in sled.tree_names() {
-            sled.open_tree(name).unwrap().iter().count();
+            sled.open_tree(name)?.iter().count();
         }
-        DataBase { sled }
+        let db = DataBase { sled };
+        db.index_corner_names()?;
+        db.default_payment_methods()?;
+        Ok(db)
     }
 
-    pub fn get_chat(&self, id: i64) -> Option<Chat> {
-        self.tree(Tree::Chats)
-            .get(Chat::key(id))
-            .unwrap()
-            .and_then(|ivec| Some(Chat::from_val(ivec)))
+    pub fn get_chat(&self, id: i64) -> Result<Option<Chat>> {
+        self.get(Tree::Chats, Chat::key(id))
     }
 
-    pub fn register(&self, code: String, name: String) -> bool {
-        todo!();
+    pub fn put_chat(&self, id: i64, chat: &Chat) -> Result<()> {
+        self.tree(Tree::Chats)?
+            .insert(Chat::key(id), chat.to_val()?)?;
+        Ok(())
     }
 
-    pub fn tree(&self, t: Tree) -> sled::Tree {
-        self.sled.open_tree([t as u8]).unwrap()
+    pub fn set_chat_lang(&self, id: i64, lang: Option<Lang>) -> Result<Chat> {
+        let mut chat = self.get_chat(id)?.ok_or(Error::NotFound)?;
+        chat.lang = lang;
+        self.put_chat(id, &chat)?;
+        Ok(chat)
+    }
+
+    /// Все собеседники бота вместе с id чатов
+    pub fn get_chats(&self) -> Result<Vec<(i64, Chat)>> {
+        self.tree(Tree::Chats)?
+            .iter()
+            .map(|kv| {
+                let (key, val) = kv?;
+                let mut id = [0u8; 8];
+                if key.len() != id.len() {
+                    return Err(Error::Corrupt(format!("bad chat id {:?}", key)));
+                }
+                id.copy_from_slice(&key);
+                Ok((i64::from_be_bytes(id), Chat::from_val(val)?))
+            })
+            .collect()
+    }
+
+    pub fn get_revenue(&self, day: u32, corner_id: u32) -> Result<Option<Revenue>> {
+        self.get(Tree::Revenues, Revenue::key(day, corner_id))
+    }
+
+    /// Выручка точки по дням с `from` по `to`, дни без записи пропускаются
+    pub fn daily_revenue(&self, corner_id: u32, from: u32, to: u32) -> Result<Vec<(u32, Money)>> {
+        let range = Revenue::key(from, 0)..=Revenue::key(to, u32::MAX);
+        let mut res = Vec::new();
+        for val in self.tree(Tree::Revenues)?.range(range).values() {
+            let rev = Revenue::from_val(val?)?;
+            if rev.corner_id == corner_id {
+                res.push((rev.date, rev.amount));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Регистрирует сотрудника по коду приглашения. Код одноразовый:
+    /// при успехе он удаляется. `false` - кода нет или он истек.
+    pub fn register(&self, chat_id: i64, code: &str, name: String) -> Result<bool> {
+        let now = chrono::Utc::now().timestamp() as u32;
+        let invites = self.tree(Tree::Invites)?;
+        let chats = self.tree(Tree::Chats)?;
+        let ok = (&invites, &chats).transaction(|(invites, chats)| {
+            let invite = match invites.get(InviteCode::key(code.trim()))? {
+                Some(val) => InviteCode::from_val(val).or_else(abort)?,
+                None => return Ok(false),
+            };
+            if invite.expire < now || chats.get(Chat::key(chat_id))?.is_some() {
+                return Ok(false);
+            }
+            let chat = Chat {
+                corner_id: invite.corner_id,
+                name: name.clone(),
+                is_active: true,
+                role: Role::Staff,
+                pay: None,
+                lang: None,
+            };
+            chats.insert(Chat::key(chat_id), chat.to_val().or_else(abort)?)?;
+            invites.remove(invite.to_key())?;
+            Ok(true)
+        })?;
+        Ok(ok)
+    }
+
+    pub fn get_corners(&self) -> Result<Vec<Corner>> {
+        self.tree(Tree::Corners)?
+            .iter()
+            .values()
+            .map(|val| Corner::from_val(val?))
+            .collect()
+    }
+
+    /// Следующий id для дерева с ключами `u32` big-endian.
+    /// Счетчик в `Tree::Counters` только растет, поэтому id удаленных
+    /// записей не достаются новым. В старых базах счетчик начинается
+    /// с последнего ключа дерева.
+    fn next_id(&self, t: Tree) -> Result<u32> {
+        let counter = [t as u8];
+        let last = match self.sled.open_tree(counter)?.last()? {
+            Some((key, _)) => id_from_key(&key)?,
+            None => 0,
+        };
+        let counters = self.tree(Tree::Counters)?;
+        loop {
+            let old = counters.get(counter)?;
+            let current = match old.as_ref() {
+                Some(val) => id_from_key(val)?.max(last),
+                None => last,
+            };
+            let id = current.checked_add(1).ok_or_else(|| {
+                Error::Invalid(format!("ids of tree {} are exhausted", counter[0]))
+            })?;
+            let swap = counters.compare_and_swap(counter, old, Some(&id.to_be_bytes()))?;
+            if swap.is_ok() {
+                return Ok(id);
+            }
+        }
+    }
+
+    pub fn tree(&self, t: Tree) -> Result<sled::Tree> {
+        Ok(self.sled.open_tree([t as u8])?)
+    }
+
+    /// Читает запись и, если она была в старом формате, сразу перезаписывает ее в текущем
+    fn get<T: BinVals>(&self, t: Tree, key: sled::IVec) -> Result<Option<T>> {
+        let tree = self.tree(t)?;
+        let old = match tree.get(&key)? {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+        let (val, outdated) = T::decode_val(&old)?;
+        if outdated {
+            // если запись успели поменять, то ее уже записали в новом формате
+            let _ = tree.compare_and_swap(&key, Some(old), Some(val.to_val()?))?;
+        }
+        Ok(Some(val))
+    }
+
+    /// Переписывает все записи старых версий в текущем формате.
+    /// Возвращает число обновленных записей.
+    pub fn upgrade_all(&self) -> Result<usize> {
+        Ok(self.upgrade_tree::<Revenue>(Tree::Revenues)?
+            + self.upgrade_tree::<Chat>(Tree::Chats)?
+            + self.upgrade_tree::<Corner>(Tree::Corners)?
+            + self.upgrade_tree::<InviteCode>(Tree::Invites)?
+            + self.upgrade_tree::<Stats>(Tree::Stats)?
+            + self.upgrade_tree::<Group>(Tree::Groups)?
+            + self.upgrade_tree::<Target>(Tree::Targets)?
+            + self.upgrade_tree::<PaymentMethod>(Tree::PaymentMethods)?
+            + self.upgrade_tree::<Expense>(Tree::Expenses)?
+            + self.upgrade_tree::<Shift>(Tree::Shifts)?
+            + self.upgrade_tree::<Revenue>(Tree::Pending)?)
+    }
+
+    fn upgrade_tree<T: BinVals>(&self, t: Tree) -> Result<usize> {
+        let tree = self.tree(t)?;
+        let mut res = 0;
+        for kv in tree.iter() {
+            let (key, old) = kv?;
+            let (val, outdated) = T::decode_val(&old)?;
+            if outdated {
+                let _ = tree.compare_and_swap(key, Some(old), Some(val.to_val()?))?;
+                res += 1;
+            }
+        }
+        tree.flush()?;
+        Ok(res)
     }
 }
 
+/// Начало каждого значения: `VAL_MAGIC` и версия формата типа.
+/// Записи без заголовка (до появления версий) считаются версией 0.
+/// Старая запись спутается с заголовком только если начинается с 0xCB 0x56,
+/// т.е. с id больше 3.4 млрд.
+const VAL_MAGIC: [u8; 2] = [0xCB, 0x56];
+
 trait BinVals
 where
     Self: Serialize + DeserializeOwned,
 {
-    fn into_val(&self) -> sled::IVec {
+    /// Текущая версия формата. Увеличивается при любом изменении полей,
+    /// а разбор предыдущих версий добавляется в `upgrade`.
+    const VERSION: u8 = 1;
+
+    /// Собирает текущую версию из записи версии `version`.
+    /// Версия 0 по умолчанию совпадает по полям с версией 1.
+    fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+        match version {
+            0 => decode(body),
+            v => Err(unknown_version(v)),
+        }
+    }
+
+    fn to_val(&self) -> Result<sled::IVec> {
+        let mut val = Vec::with_capacity(64);
+        val.extend_from_slice(&VAL_MAGIC);
+        val.push(Self::VERSION);
         bincode::config()
             .big_endian()
-            .serialize(self)
-            .unwrap()
-            .into()
+            .serialize_into(&mut val, self)?;
+        Ok(val.into())
     }
 
-    fn from_val(vec: sled::IVec) -> Self {
-        bincode::config().big_endian().deserialize(&vec).unwrap()
+    fn from_val(vec: sled::IVec) -> Result<Self> {
+        Ok(Self::decode_val(&vec)?.0)
+    }
+
+    /// Декодирует значение любой известной версии.
+    /// Второй элемент - запись хранится в устаревшем формате.
+    fn decode_val(val: &[u8]) -> Result<(Self, bool)> {
+        let (version, body) = match val {
+            [m0, m1, version, body @ ..] if [*m0, *m1] == VAL_MAGIC => (*version, body),
+            body => (0, body),
+        };
+        if version == Self::VERSION {
+            Ok((decode(body)?, false))
+        } else if version > Self::VERSION {
+            Err(Error::Corrupt(format!(
+                "record version {} is newer than {}",
+                version,
+                Self::VERSION
+            )))
+        } else {
+            Ok((Self::upgrade(version, body)?, true))
+        }
     }
 }
 
+fn decode<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    Ok(bincode::config().big_endian().deserialize(body)?)
+}
+
+fn unknown_version(version: u8) -> Error {
+    Error::Corrupt(format!("unknown record version {}", version))
+}
+
+fn id_from_key(key: &[u8]) -> Result<u32> {
+    let mut id = [0u8; 4];
+    if key.len() != id.len() {
+        return Err(Error::Corrupt(format!("bad id {:?}", key)));
+    }
+    id.copy_from_slice(key);
+    Ok(u32::from_be_bytes(id))
+}
+
+/// Отмена транзакции sled с нашей ошибкой
+fn abort<T>(e: Error) -> std::result::Result<T, ConflictableTransactionError<Error>> {
+    Err(ConflictableTransactionError::Abort(e))
+}
+
 pub enum Tree {
     Revenues,
     Chats,
     Corners,
     Stats,
     Invites,
+    CornerNames,
+    Groups,
+    Targets,
+    PaymentMethods,
+    Expenses,
+    Shifts,
+    Pending,
+    Secrets,
+    /// Последний выданный id по деревьям, см. `next_id`
+    Counters,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Revenue {
     pub corner_id: u32,
     pub date: u32,
-    pub amount: u32,
+    /// Итог за день, равен сумме `payments`, если разбивка есть
+    pub amount: Money,
     pub post_datetime: u32,
+    pub comment: Option<String>,
+    /// Разбивка по способам оплаты, пустая если ее не вводили
+    pub payments: Vec<Payment>,
+    /// file_id фото Z-отчета в Telegram
+    pub photo: Option<String>,
+    /// Чат сотрудника, который был на смене
+    pub staff: Option<i64>,
+}
+
+impl BinVals for Revenue {
+    const VERSION: u8 = 6;
+
+    fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+        use legacy::{RevenueV1, RevenueV2, RevenueV3, RevenueV4, RevenueV5};
+        let v5 = |r: RevenueV4| RevenueV5::from(r).into();
+        match version {
+            0 | 1 => decode::<RevenueV1>(body)
+                .map(|r| v5(RevenueV4::from(RevenueV3::from(RevenueV2::from(r))))),
+            2 => decode::<RevenueV2>(body).map(|r| v5(RevenueV4::from(RevenueV3::from(r)))),
+            3 => decode::<RevenueV3>(body).map(|r| v5(RevenueV4::from(r))),
+            4 => decode::<RevenueV4>(body).map(v5),
+            5 => decode::<RevenueV5>(body).map(Into::into),
+            v => Err(unknown_version(v)),
+        }
+    }
+}
+
+/// Способ оплаты для записей без разбивки
+pub const UNSPECIFIED: u32 = 0;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Payment {
+    /// id из `Tree::PaymentMethods` или `UNSPECIFIED`
+    pub method: u32,
+    pub amount: Money,
+}
+
+/// Способ оплаты из настраиваемого списка: наличные, карта и т.п.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PaymentMethod {
+    pub id: u32,
+    pub name: String,
+    /// Короткое слово для ввода в чате, например "нал"
+    pub code: String,
+    /// Выключенный способ не принимается при вводе, но остается в отчетах
+    pub archived: bool,
 }
 
-impl BinVals for Revenue {}
+impl BinVals for PaymentMethod {}
 
 impl Revenue {
-    fn key(date: u32, corner_id: u32) -> sled::IVec {
-        let mut key: [u8; 8] = [0u8; 8];
-        let mut v: [u8; 4] = date.to_be_bytes();
-        key[0] = v[0];
-        key[1] = v[1];
-        key[2] = v[2];
-        key[3] = v[3];
-        v = corner_id.to_be_bytes();
-        key[4] = v[0];
-        key[5] = v[1];
-        key[6] = v[2];
-        key[7] = v[3];
+    /// Разбивка для отчетов. Без разбивки вся сумма идет в `UNSPECIFIED`.
+    pub fn breakdown(&self) -> Vec<Payment> {
+        if self.payments.is_empty() {
+            vec![Payment {
+                method: UNSPECIFIED,
+                amount: self.amount,
+            }]
+        } else {
+            self.payments.clone()
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        let sum = self
+            .payments
+            .iter()
+            .try_fold(Money::ZERO, |sum, p| sum.checked_add(p.amount));
+        if !self.payments.is_empty() && sum != Some(self.amount) {
+            return Err(Error::Invalid(format!(
+                "payments sum {:?} differs from amount {}",
+                sum, self.amount
+            )));
+        }
+        Ok(())
+    }
+
+    /// Номер дня для поля `date`: дни от начала нашей эры, 1 января 1 года - 1.
+    /// Номера идут подряд, поэтому соседние дни отличаются на единицу.
+    /// `None` для дат до нашей эры.
+    pub fn day(date: NaiveDate) -> Option<u32> {
+        u32::try_from(date.num_days_from_ce())
+            .ok()
+            .filter(|&day| day > 0)
+    }
+
+    /// Дата по номеру дня, обратно к `day`. `None` за пределами календаря.
+    pub fn date(day: u32) -> Option<NaiveDate> {
+        i32::try_from(day)
+            .ok()
+            .filter(|&day| day > 0)
+            .and_then(NaiveDate::from_num_days_from_ce_opt)
+    }
+
+    /// `day` с ошибкой `Error::Invalid` вместо `None`
+    pub fn day_of(date: NaiveDate) -> Result<u32> {
+        Revenue::day(date).ok_or_else(|| Error::out_of_range(date))
+    }
+
+    /// `date` с ошибкой `Error::Invalid` вместо `None`
+    pub fn date_of(day: u32) -> Result<NaiveDate> {
+        Revenue::date(day).ok_or_else(|| Error::out_of_range(day))
+    }
+
+    /// Номер дня big-endian, затем id точки: записи идут по датам,
+    /// а внутри дня - по точкам
+    fn key(day: u32, corner_id: u32) -> sled::IVec {
+        let mut key = [0u8; 8];
+        key[..4].copy_from_slice(&day.to_be_bytes());
+        key[4..].copy_from_slice(&corner_id.to_be_bytes());
         (&key).into()
     }
 
-    fn into_key(&self) -> sled::IVec {
+    fn to_key(&self) -> sled::IVec {
         Self::key(self.date, self.corner_id)
     }
 }
@@ -103,9 +466,46 @@ pub struct Chat {
     // state: ChatState,
     pub name: String,
     pub is_active: bool,
+    pub role: Role,
+    /// Как считать зарплату сотрудника, `None` - не считать
+    pub pay: Option<PayScheme>,
+    /// Язык, выбранный командой /lang, `None` - из настроек Telegram
+    pub lang: Option<Lang>,
 }
 
-impl BinVals for Chat {}
+impl BinVals for Chat {
+    const VERSION: u8 = 5;
+
+    fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+        use legacy::{ChatV1, ChatV2, ChatV3, ChatV4};
+        let v4 = |c: ChatV3| ChatV4::from(c).into();
+        match version {
+            0 | 1 => decode::<ChatV1>(body).map(|c| v4(ChatV3::from(ChatV2::from(c)))),
+            2 => decode::<ChatV2>(body).map(|c| v4(ChatV3::from(c))),
+            3 => decode::<ChatV3>(body).map(v4),
+            4 => decode::<ChatV4>(body).map(Into::into),
+            v => Err(unknown_version(v)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Видит только свою точку
+    Staff,
+    /// Администратор группы точек со всеми подгруппами, `None` - всей сети
+    Admin(Option<u32>),
+}
+
+/// Оплата сотрудника: ставка за смену плюс процент с выручки дня
+/// сверх порога. Нулевая часть схемы просто не участвует.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PayScheme {
+    pub per_shift: Money,
+    /// Сотые доли процента: 250 - это 2,5%
+    pub percent: u32,
+    pub threshold: Money,
+}
 
 impl Chat {
     fn key(chat_id: i64) -> sled::IVec {
@@ -121,23 +521,191 @@ impl Chat {
 
 // }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Corner {
-    id: u32,
-    name: String,
-    tag: Option<String>,
+    pub id: u32,
+    pub name: String,
+    pub short_name: Option<String>,
+    pub address: Option<String>,
+    /// Часы работы в свободной форме, например "10:00-22:00"
+    pub hours: Option<String>,
+    pub archived: bool,
+    /// Метки в нижнем регистре, отсортированы
+    pub tags: Vec<String>,
+    /// Группа, в которую входит точка, например город
+    pub group_id: Option<u32>,
+    /// Выручку нужно подтверждать фото Z-отчета
+    pub photo_required: bool,
+    /// Во сколько раз (в десятых) выручка может отличаться от обычной
+    /// без подтверждения, 0 - не проверять
+    pub anomaly_ratio: u32,
+    /// Часовой пояс IANA, по которому считаются рабочие дни точки
+    pub timezone: String,
 }
 
-impl BinVals for Corner {}
+/// `Corner::anomaly_ratio` по умолчанию: втрое больше или меньше обычного
+pub const ANOMALY_RATIO: u32 = 30;
+
+/// `Corner::timezone` по умолчанию и для точек, заведенных до поясов
+pub const TIMEZONE: Tz = chrono_tz::Europe::Moscow;
+
+impl BinVals for Corner {
+    const VERSION: u8 = 7;
+
+    fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+        use legacy::{CornerV1, CornerV2, CornerV3, CornerV4, CornerV5, CornerV6};
+        let v4 = |c: CornerV4| CornerV6::from(CornerV5::from(c)).into();
+        match version {
+            0 | 1 => decode::<CornerV1>(body)
+                .map(|c| v4(CornerV4::from(CornerV3::from(CornerV2::from(c))))),
+            2 => decode::<CornerV2>(body).map(|c| v4(CornerV4::from(CornerV3::from(c)))),
+            3 => decode::<CornerV3>(body).map(|c| v4(CornerV4::from(c))),
+            4 => decode::<CornerV4>(body).map(v4),
+            5 => decode::<CornerV5>(body).map(|c| CornerV6::from(c).into()),
+            6 => decode::<CornerV6>(body).map(Into::into),
+            v => Err(unknown_version(v)),
+        }
+    }
+}
 
 impl Corner {
     fn key(chat_id: u32) -> sled::IVec {
         (&chat_id.to_be_bytes()).into()
     }
 
-    fn into_key(&self) -> sled::IVec {
+    fn to_key(&self) -> sled::IVec {
         Self::key(self.id)
     }
+
+    /// Часовой пояс точки, `TIMEZONE` если имя не разбирается
+    pub fn tz(&self) -> Tz {
+        dates::zone(&self.timezone).unwrap_or(TIMEZONE)
+    }
+
+    /// Рабочий день точки в момент `ts` (секунды Unix) в номерах `Revenue::date`
+    pub fn day_at(&self, ts: i64) -> Result<u32> {
+        Revenue::day_of(dates::date_at(self.tz(), ts))
+    }
+}
+
+/// Узел иерархии точек: регион, город и т.п.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Group {
+    pub id: u32,
+    pub name: String,
+    /// `None` у групп верхнего уровня
+    pub parent_id: Option<u32>,
+}
+
+impl BinVals for Group {}
+
+impl Group {
+    fn key(id: u32) -> sled::IVec {
+        (&id.to_be_bytes()).into()
+    }
+}
+
+/// Сводка выручки узла (точки или группы) за месяц
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub revenue: Money,
+    /// Сколько дневных выручек вошло в сумму
+    pub days: u32,
+    /// Выручка по способам оплаты, без нулевых
+    pub by_method: BTreeMap<u32, Money>,
+    pub expenses: Money,
+}
+
+impl BinVals for Stats {
+    const VERSION: u8 = 4;
+
+    fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+        use legacy::{StatsV1, StatsV2, StatsV3};
+        match version {
+            0 | 1 => decode::<StatsV1>(body).map(|s| StatsV3::from(StatsV2::from(s)).into()),
+            2 => decode::<StatsV2>(body).map(|s| StatsV3::from(s).into()),
+            3 => decode::<StatsV3>(body).map(Into::into),
+            v => Err(unknown_version(v)),
+        }
+    }
+}
+
+/// Расход точки за день: закупка расходников, доставка и т.п.
+/// Расходов за день может быть несколько, поэтому к ключу `Revenue`
+/// добавляется `id`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Expense {
+    pub corner_id: u32,
+    pub date: u32,
+    pub id: u64,
+    pub category: String,
+    pub amount: Money,
+    pub comment: Option<String>,
+    /// `file_id` фото чека в Telegram
+    pub photo: Option<String>,
+    pub post_datetime: u32,
+}
+
+impl BinVals for Expense {
+    const VERSION: u8 = 2;
+
+    fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+        match version {
+            0 | 1 => decode::<legacy::ExpenseV1>(body).map(Into::into),
+            v => Err(unknown_version(v)),
+        }
+    }
+}
+
+/// Смена сотрудника на точке. Пока смена открыта, `closed` пустое.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Shift {
+    pub corner_id: u32,
+    pub chat_id: i64,
+    pub opened: u32,
+    pub closed: Option<u32>,
+    /// Наличные в кассе на открытии и закрытии
+    pub open_cash: Money,
+    pub close_cash: Option<Money>,
+}
+
+impl BinVals for Shift {
+    const VERSION: u8 = 2;
+
+    fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+        match version {
+            0 | 1 => decode::<legacy::ShiftV1>(body).map(Into::into),
+            v => Err(unknown_version(v)),
+        }
+    }
+}
+
+/// Версия плана выручки точки
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Target {
+    pub corner_id: u32,
+    /// Первый день действия, как `Revenue::date`
+    pub from: u32,
+    pub period: TargetPeriod,
+    pub amount: Money,
+    pub set_at: u32,
+}
+
+impl BinVals for Target {
+    const VERSION: u8 = 2;
+
+    fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+        match version {
+            0 | 1 => decode::<legacy::TargetV1>(body).map(Into::into),
+            v => Err(unknown_version(v)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TargetPeriod {
+    Day,
+    Month,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -154,7 +722,7 @@ impl InviteCode {
         code.as_bytes().into()
     }
 
-    fn into_key(&self) -> sled::IVec {
+    fn to_key(&self) -> sled::IVec {
         Self::key(self.code.as_str())
     }
 }
@@ -176,22 +744,313 @@ mod tests {
 
     #[test]
     fn open_sled() {
-        DataBase::open();
+        DataBase::open().unwrap();
     }
 
     #[test]
     fn insert_get() {
-        let db = DataBase::open();
-        let tree = db.tree(Tree::Revenues);
+        let db = DataBase::temporary();
+        let tree = db.tree(Tree::Revenues).unwrap();
         let mut rng = rand::thread_rng();
         let rev = Revenue {
             corner_id: rng.next_u32(),
             date: rng.next_u32(),
-            amount: rng.next_u32(),
+            amount: Money::from_kopecks(rng.next_u32() as i64),
             post_datetime: rng.next_u32(),
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff: None,
+        };
+        let key = rev.to_key();
+        tree.insert(&key, rev.to_val().unwrap()).unwrap();
+        assert_eq!(
+            Revenue::from_val(tree.get(&key).unwrap().unwrap()).unwrap(),
+            rev
+        );
+    }
+
+    #[test]
+    fn day_numbers() {
+        let first = NaiveDate::from_ymd(1, 1, 1);
+        assert_eq!(Revenue::day(first).unwrap(), 1);
+        assert_eq!(Revenue::date(1).unwrap(), first);
+        let mut date = NaiveDate::from_ymd(1999, 12, 25);
+        let mut prev = Revenue::day(date).unwrap() - 1;
+        // переходы через месяцы, годы и 29 февраля
+        while date.year() < 2025 {
+            let day = Revenue::day(date).unwrap();
+            assert_eq!(day, prev + 1, "{}", date);
+            assert_eq!(Revenue::date(day).unwrap(), date);
+            assert!(Revenue::key(prev, u32::MAX) < Revenue::key(day, 0));
+            prev = day;
+            date = date.succ();
+        }
+        assert_eq!(
+            Revenue::day(NaiveDate::from_ymd(2020, 3, 1)).unwrap()
+                - Revenue::day(NaiveDate::from_ymd(2020, 2, 28)).unwrap(),
+            2
+        );
+        // до нашей эры и за пределами календаря chrono номеров нет
+        assert_eq!(Revenue::day(NaiveDate::from_ymd(0, 6, 1)), None);
+        assert_eq!(Revenue::day(NaiveDate::from_ymd(-5, 1, 1)), None);
+        assert_eq!(Revenue::date(0), None);
+        assert_eq!(Revenue::date(u32::MAX), None);
+        match Stats::month(u32::MAX) {
+            Err(Error::Invalid(_)) => {}
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn corrupt_chat() {
+        let db = DataBase::temporary();
+        let id = -(rand::thread_rng().next_u32() as i64);
+        db.tree(Tree::Chats)
+            .unwrap()
+            .insert(Chat::key(id), &[1u8, 2, 3])
+            .unwrap();
+        match db.get_chat(id) {
+            Err(Error::Corrupt(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        db.tree(Tree::Chats).unwrap().remove(Chat::key(id)).unwrap();
+        assert_eq!(db.get_chat(id).unwrap(), None);
+    }
+
+    #[test]
+    fn register_by_invite() {
+        let db = DataBase::temporary();
+        let now = chrono::Utc::now().timestamp() as u32;
+        let tree = db.tree(Tree::Invites).unwrap();
+        for (code, expire) in &[("12345", now + 3600), ("54321", now - 1)] {
+            let invite = InviteCode {
+                code: (*code).to_owned(),
+                corner_id: 7,
+                expire: *expire,
+            };
+            tree.insert(invite.to_key(), invite.to_val().unwrap())
+                .unwrap();
+        }
+
+        assert!(!db.register(1, "00000", "Иван".to_owned()).unwrap());
+        assert!(!db.register(1, "54321", "Иван".to_owned()).unwrap());
+        assert_eq!(db.get_chat(1).unwrap(), None);
+        assert!(db.register(1, " 12345\n", "Иван".to_owned()).unwrap());
+        let chat = db.get_chat(1).unwrap().unwrap();
+        assert_eq!((chat.corner_id, chat.role), (7, Role::Staff));
+        assert!(chat.is_active);
+        // код одноразовый
+        assert!(!db.register(2, "12345", "Петр".to_owned()).unwrap());
+        assert_eq!(db.get_chat(2).unwrap(), None);
+    }
+
+    #[test]
+    fn legacy_chat_upgraded_on_read() {
+        let db = DataBase::temporary();
+        let tree = db.tree(Tree::Chats).unwrap();
+        let id = -(rand::thread_rng().next_u32() as i64);
+        // запись без заголовка, как до появления версий и ролей
+        let legacy = bincode::config()
+            .big_endian()
+            .serialize(&(3u32, "Иван", true))
+            .unwrap();
+        tree.insert(Chat::key(id), legacy).unwrap();
+        let chat = Chat {
+            corner_id: 3,
+            name: "Иван".to_owned(),
+            is_active: true,
+            role: Role::Staff,
+            pay: None,
+            lang: None,
+        };
+
+        assert_eq!(db.get_chat(id).unwrap(), Some(chat.clone()));
+        let stored = tree.get(Chat::key(id)).unwrap().unwrap();
+        assert_eq!(stored, chat.to_val().unwrap());
+        assert_eq!(stored[2], Chat::VERSION);
+        tree.remove(Chat::key(id)).unwrap();
+    }
+
+    /// `Revenue` версии 5 с суммами в целых рублях
+    #[derive(Serialize, Deserialize)]
+    struct RubleRevenue {
+        corner_id: u32,
+        date: u32,
+        amount: u32,
+        post_datetime: u32,
+        comment: Option<String>,
+        payments: Vec<(u32, u32)>,
+        photo: Option<String>,
+        staff: Option<i64>,
+    }
+
+    impl BinVals for RubleRevenue {
+        const VERSION: u8 = 5;
+    }
+
+    #[test]
+    fn legacy_revenue_in_kopecks() {
+        let old = RubleRevenue {
+            corner_id: 1,
+            date: 737_500,
+            amount: 4_000_000_000,
+            post_datetime: 0,
+            comment: None,
+            payments: vec![(1, 1_500_000_000), (2, 2_500_000_000)],
+            photo: None,
+            staff: Some(7),
+        };
+        let rev = Revenue::from_val(old.to_val().unwrap()).unwrap();
+        assert_eq!(rev.amount, Money::rubles(4_000_000_000));
+        assert_eq!(
+            rev.payments,
+            vec![
+                Payment::new(1, Money::rubles(1_500_000_000)),
+                Payment::new(2, Money::rubles(2_500_000_000))
+            ]
+        );
+        assert_eq!(rev.staff, Some(7));
+        rev.check().unwrap();
+    }
+
+    /// `Corner` версии 6, до часовых поясов
+    #[derive(Serialize, Deserialize)]
+    struct ZonelessCorner {
+        id: u32,
+        name: String,
+        short_name: Option<String>,
+        address: Option<String>,
+        hours: Option<String>,
+        archived: bool,
+        tags: Vec<String>,
+        group_id: Option<u32>,
+        photo_required: bool,
+        anomaly_ratio: u32,
+    }
+
+    impl BinVals for ZonelessCorner {
+        const VERSION: u8 = 6;
+    }
+
+    #[test]
+    fn legacy_corner_gets_timezone() {
+        let old = ZonelessCorner {
+            id: 3,
+            name: "Вокзал".to_owned(),
+            short_name: None,
+            address: None,
+            hours: None,
+            archived: false,
+            tags: vec!["station".to_owned()],
+            group_id: Some(1),
+            photo_required: true,
+            anomaly_ratio: 25,
+        };
+        let corner = Corner::from_val(old.to_val().unwrap()).unwrap();
+        assert_eq!(corner.timezone, "Europe/Moscow");
+        assert_eq!(corner.tz(), TIMEZONE);
+        assert_eq!((corner.anomaly_ratio, corner.group_id), (25, Some(1)));
+    }
+
+    /// `Chat` версии 4 со схемой оплаты в целых рублях
+    #[derive(Serialize, Deserialize)]
+    struct RubleChat {
+        corner_id: u32,
+        name: String,
+        is_active: bool,
+        role: Role,
+        pay: Option<(u32, u32, u32)>,
+        lang: Option<Lang>,
+    }
+
+    impl BinVals for RubleChat {
+        const VERSION: u8 = 4;
+    }
+
+    /// `Shift` версии 1 с наличными в целых рублях
+    #[derive(Serialize, Deserialize)]
+    struct RubleShift {
+        corner_id: u32,
+        chat_id: i64,
+        opened: u32,
+        closed: Option<u32>,
+        open_cash: u32,
+        close_cash: Option<u32>,
+    }
+
+    impl BinVals for RubleShift {}
+
+    #[test]
+    fn legacy_sums_in_kopecks() {
+        let old = RubleChat {
+            corner_id: 1,
+            name: "Аня".to_owned(),
+            is_active: true,
+            role: Role::Staff,
+            pay: Some((1_500, 250, 10_000)),
+            lang: Some(Lang::En),
+        };
+        let chat = Chat::from_val(old.to_val().unwrap()).unwrap();
+        let pay = chat.pay.unwrap();
+        assert_eq!(
+            (pay.per_shift, pay.percent, pay.threshold),
+            (Money::rubles(1_500), 250, Money::rubles(10_000))
+        );
+        assert_eq!(chat.lang, Some(Lang::En));
+
+        let old = RubleShift {
+            corner_id: 1,
+            chat_id: 7,
+            opened: 100,
+            closed: Some(200),
+            open_cash: 2_000,
+            close_cash: Some(5_500),
+        };
+        let shift = Shift::from_val(old.to_val().unwrap()).unwrap();
+        assert_eq!(shift.open_cash, Money::rubles(2_000));
+        assert_eq!(shift.close_cash, Some(Money::rubles(5_500)));
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Old {
+        a: u32,
+    }
+
+    impl BinVals for Old {}
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct New {
+        a: u32,
+        b: Option<String>,
+    }
+
+    impl BinVals for New {
+        const VERSION: u8 = 2;
+
+        fn upgrade(version: u8, body: &[u8]) -> Result<Self> {
+            match version {
+                0 | 1 => decode::<Old>(body).map(|old| New { a: old.a, b: None }),
+                v => Err(unknown_version(v)),
+            }
+        }
+    }
+
+    #[test]
+    fn versions() {
+        let old = Old { a: 7 }.to_val().unwrap();
+        assert_eq!(
+            New::decode_val(&old).unwrap(),
+            (New { a: 7, b: None }, true)
+        );
+        let new = New {
+            a: 8,
+            b: Some("b".to_owned()),
         };
-        let key = rev.into_key();
-        tree.insert(&key, rev.into_val()).unwrap();
-        assert_eq!(Revenue::from_val(tree.get(&key).unwrap().unwrap()), rev);
+        assert_eq!(New::from_val(new.to_val().unwrap()).unwrap(), new);
+        match Old::from_val(new.to_val().unwrap()) {
+            Err(Error::Corrupt(_)) => {}
+            res => panic!("{:?}", res),
+        }
     }
 }
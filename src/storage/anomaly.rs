@@ -0,0 +1,193 @@
+//! Проверка выручки на выбросы. Обычная выручка точки - медиана средней
+//! дневной выручки по месячным сводкам за последние полгода.
+//! Выручка-выброс ждет подтверждения сотрудника в `Tree::Pending`.
+
+use super::{BinVals, Corner, DataBase, Error, Node, Result, Revenue, Stats, Tree};
+use crate::money::Money;
+
+/// Сколько месяцев сводок берется для медианы, включая текущий
+const MONTHS: u32 = 6;
+/// С меньшим числом дней сравнивать не с чем
+const MIN_DAYS: u32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anomaly {
+    /// Во много раз больше обычной выручки, например лишний ноль
+    High { usual: Money },
+    /// Подозрительно мало
+    Low { usual: Money },
+}
+
+impl DataBase {
+    /// Обычная выручка точки за день, если истории хватает
+    pub fn usual_revenue(&self, corner_id: u32, day: u32) -> Result<Option<Money>> {
+        let month = Stats::month(day)?;
+        let from = month.saturating_sub(MONTHS - 1);
+        let stats = self.stats_range(Node::Corner(corner_id), from, month)?;
+        if stats.iter().map(|(_, s)| s.days).sum::<u32>() < MIN_DAYS {
+            return Ok(None);
+        }
+        let mut avg: Vec<i64> = stats
+            .iter()
+            .filter(|(_, s)| s.days > 0)
+            .map(|(_, s)| s.revenue.kopecks() / s.days as i64)
+            .collect();
+        avg.sort_unstable();
+        let mid = avg.len() / 2;
+        Ok(Some(Money::from_kopecks(match avg.len() % 2 {
+            0 => (avg[mid - 1] + avg[mid]) / 2,
+            _ => avg[mid],
+        })))
+    }
+
+    /// Выброс ли `amount` для точки с учетом ее `anomaly_ratio`
+    pub fn check_revenue(
+        &self,
+        corner: &Corner,
+        day: u32,
+        amount: Money,
+    ) -> Result<Option<Anomaly>> {
+        if corner.anomaly_ratio == 0 {
+            return Ok(None);
+        }
+        let usual = match self.usual_revenue(corner.id, day)? {
+            Some(usual) if usual > Money::ZERO => usual,
+            _ => return Ok(None),
+        };
+        // сравнение в десятых, как хранится кратность
+        let (amount, ratio) = (amount.kopecks() as i128 * 10, corner.anomaly_ratio as i128);
+        let kopecks = usual.kopecks() as i128;
+        Ok(if amount > kopecks * ratio {
+            Some(Anomaly::High { usual })
+        } else if amount * ratio < kopecks * 100 {
+            Some(Anomaly::Low { usual })
+        } else {
+            None
+        })
+    }
+
+    /// Кратность в десятых больше 1, либо 0 чтобы выключить проверку
+    pub fn set_anomaly_ratio(&self, corner_id: u32, ratio: u32) -> Result<Corner> {
+        if ratio != 0 && ratio <= 10 {
+            return Err(Error::Invalid(format!("anomaly ratio {}", ratio)));
+        }
+        self.modify_corner(corner_id, |c| {
+            c.anomaly_ratio = ratio;
+            Ok(())
+        })
+    }
+
+    /// Откладывает выручку до подтверждения, прежняя отложенная заменяется
+    pub fn put_pending(&self, chat_id: i64, rev: &Revenue) -> Result<()> {
+        self.tree(Tree::Pending)?
+            .insert(chat_id.to_be_bytes(), rev.to_val()?)?;
+        Ok(())
+    }
+
+    /// Отложенная выручка чата, остается в очереди
+    pub fn get_pending(&self, chat_id: i64) -> Result<Option<Revenue>> {
+        match self.tree(Tree::Pending)?.get(chat_id.to_be_bytes())? {
+            Some(val) => Ok(Some(Revenue::from_val(val)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Забирает отложенную выручку, только если это все еще `rev`.
+    /// `false` - ее успели подтвердить, отменить или заменить.
+    pub fn take_pending_if(&self, chat_id: i64, rev: &Revenue) -> Result<bool> {
+        let tree = self.tree(Tree::Pending)?;
+        let key = chat_id.to_be_bytes();
+        let raw = match tree.get(key)? {
+            Some(raw) => raw,
+            None => return Ok(false),
+        };
+        if Revenue::from_val(raw.clone())? != *rev {
+            return Ok(false);
+        }
+        Ok(tree
+            .compare_and_swap(key, Some(raw), None as Option<&[u8]>)?
+            .is_ok())
+    }
+
+    /// Забирает отложенную выручку чата
+    pub fn take_pending(&self, chat_id: i64) -> Result<Option<Revenue>> {
+        match self.tree(Tree::Pending)?.remove(chat_id.to_be_bytes())? {
+            Some(val) => Ok(Some(Revenue::from_val(val)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::CornerInfo;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn median_rule() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let feb = Revenue::day(NaiveDate::from_ymd(2020, 2, 1)).unwrap();
+        let rev = |date, amount| Revenue {
+            corner_id: corner.id,
+            date,
+            amount: Money::rubles(amount),
+            post_datetime: 0,
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff: None,
+        };
+        let today = Revenue::day(NaiveDate::from_ymd(2020, 3, 2)).unwrap();
+        assert_eq!(db.usual_revenue(corner.id, today).unwrap(), None);
+        for i in 0..6 {
+            db.put_revenue(&rev(feb + i, 10_000)).unwrap();
+        }
+        assert_eq!(
+            db.check_revenue(&corner, today, Money::rubles(1)).unwrap(),
+            None
+        );
+        // средние февраля и марта, медиана посередине
+        db.put_revenue(&rev(today - 1, 30_000)).unwrap();
+        assert_eq!(
+            db.usual_revenue(corner.id, today).unwrap(),
+            Some(Money::rubles(20_000))
+        );
+        db.put_revenue(&rev(feb + 6, 10_000)).unwrap();
+        db.put_revenue(&rev(feb + 7, 10_000)).unwrap();
+
+        let check = |amount| {
+            db.check_revenue(&corner, today, Money::rubles(amount))
+                .unwrap()
+        };
+        let usual = Money::rubles(20_000);
+        assert_eq!(check(200_000), Some(Anomaly::High { usual }));
+        assert_eq!(check(6_000), Some(Anomaly::Low { usual }));
+        assert_eq!(check(7_000), None);
+        assert_eq!(check(60_000), None);
+
+        match db.set_anomaly_ratio(corner.id, 10) {
+            Err(Error::Invalid(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        let corner = db.set_anomaly_ratio(corner.id, 0).unwrap();
+        assert_eq!(db.check_revenue(&corner, today, usual).unwrap(), None);
+
+        db.put_pending(5, &rev(today, 200_000)).unwrap();
+        assert_eq!(
+            db.get_pending(5).unwrap().unwrap().amount,
+            Money::rubles(200_000)
+        );
+        assert!(!db.take_pending_if(5, &rev(today, 100_000)).unwrap());
+        assert!(db.take_pending_if(5, &rev(today, 200_000)).unwrap());
+        db.put_pending(5, &rev(today, 200_000)).unwrap();
+        assert!(db.take_pending(5).unwrap().is_some());
+        assert_eq!(db.take_pending(5).unwrap(), None);
+    }
+}
@@ -0,0 +1,366 @@
+//! Переносимый архив всех деревьев sled.
+//!
+//! Формат (все числа big-endian):
+//! ```text
+//! "CMBK" | версия u16 | время создания i64 | число деревьев u32
+//! дерево: длина имени u32 | имя | число записей u64
+//!         { длина ключа u32 | ключ | длина значения u32 | значение }
+//!         sha256 имени и записей дерева
+//! sha256 всего предыдущего содержимого файла
+//! ```
+
+use super::{DataBase, Error, Result};
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"CMBK";
+const FORMAT_VERSION: u16 = 1;
+const FILE_PREFIX: &str = "cmbot-";
+const FILE_EXT: &str = ".cmbk";
+
+#[derive(Debug, PartialEq)]
+pub struct BackupSummary {
+    pub trees: usize,
+    pub records: u64,
+}
+
+impl DataBase {
+    /// Выгружает все деревья, не останавливая работу бота.
+    /// Каждое дерево читается своим итератором, поэтому запись,
+    /// сделанная во время выгрузки, может попасть в одно дерево и не попасть в другое.
+    pub fn backup<W: Write>(&self, out: W) -> Result<BackupSummary> {
+        let mut out = HashWriter::new(out);
+        let names = self.sled.tree_names();
+        out.write_all(MAGIC)?;
+        out.write_all(&FORMAT_VERSION.to_be_bytes())?;
+        out.write_all(&chrono::Utc::now().timestamp().to_be_bytes())?;
+        out.write_all(&(names.len() as u32).to_be_bytes())?;
+
+        let mut summary = BackupSummary {
+            trees: names.len(),
+            records: 0,
+        };
+        for name in names {
+            // число записей пишется до них, поэтому дерево сначала читается целиком
+            let entries = self
+                .sled
+                .open_tree(&name)?
+                .iter()
+                .collect::<sled::Result<Vec<_>>>()?;
+            let mut tree_hash = Sha256::new();
+            write_chunk(&mut out, &mut tree_hash, &name)?;
+            out.write_all(&(entries.len() as u64).to_be_bytes())?;
+            for (key, val) in entries.iter() {
+                write_chunk(&mut out, &mut tree_hash, key)?;
+                write_chunk(&mut out, &mut tree_hash, val)?;
+            }
+            out.write_all(&tree_hash.finalize())?;
+            summary.records += entries.len() as u64;
+        }
+        let total = out.hash.clone().finalize();
+        out.write_all(&total)?;
+        out.inner.flush()?;
+        Ok(summary)
+    }
+
+    pub fn backup_to_file(&self, path: &Path) -> Result<BackupSummary> {
+        // пишем во временный файл, чтобы не оставить обрезанный архив
+        let tmp = path.with_extension("tmp");
+        let summary = self.backup(std::io::BufWriter::new(fs::File::create(&tmp)?))?;
+        fs::rename(&tmp, path)?;
+        Ok(summary)
+    }
+
+    /// Собирает новую базу в `db_path` из архива. Каталог базы должен
+    /// отсутствовать или быть пустым. После записи содержимое перечитывается
+    /// и сверяется с контрольными суммами архива.
+    pub fn restore(archive: &Path, db_path: &Path) -> Result<(DataBase, BackupSummary)> {
+        if db_path.exists() && fs::read_dir(db_path)?.next().is_some() {
+            return Err(Error::Conflict(format!(
+                "{} already exists and is not empty",
+                db_path.display()
+            )));
+        }
+        let mut data = Vec::new();
+        fs::File::open(archive)?.read_to_end(&mut data)?;
+        let trees = parse(&data)?;
+
+        let db = DataBase::open_path(db_path)?;
+        for tree in trees.iter() {
+            let sled_tree = db.sled.open_tree(tree.name)?;
+            // при открытии база могла заполнить справочники по умолчанию
+            sled_tree.clear()?;
+            let mut batch = sled::Batch::default();
+            for (key, val) in tree.entries.iter() {
+                batch.insert(*key, *val);
+            }
+            sled_tree.apply_batch(batch)?;
+        }
+        db.sled.flush()?;
+
+        let mut summary = BackupSummary {
+            trees: trees.len(),
+            records: 0,
+        };
+        for tree in trees.iter() {
+            let mut tree_hash = Sha256::new();
+            let mut count = 0;
+            hash_chunk(&mut tree_hash, tree.name);
+            for kv in db.sled.open_tree(tree.name)?.iter() {
+                let (key, val) = kv?;
+                hash_chunk(&mut tree_hash, &key);
+                hash_chunk(&mut tree_hash, &val);
+                count += 1;
+            }
+            if count != tree.entries.len() || tree_hash.finalize().as_slice() != tree.hash {
+                return Err(Error::Corrupt(format!(
+                    "restored tree {:?} differs from archive",
+                    tree.name
+                )));
+            }
+            summary.records += count as u64;
+        }
+        Ok((db, summary))
+    }
+}
+
+/// Раз в `period` кладет архив в `dir` и оставляет только `keep` последних
+pub async fn backup_job(db: DataBase, dir: PathBuf, keep: usize, period: std::time::Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let db = db.clone();
+        let dir = dir.clone();
+        let res = tokio::task::spawn_blocking(move || rotate_backup(&db, &dir, keep)).await;
+        match res {
+            Ok(Ok(path)) => println!("Backup saved to {}", path.display()),
+            Ok(Err(e)) => eprintln!("ERROR: Can't make backup: {}", e),
+            Err(e) => eprintln!("ERROR: Backup task failed: {}", e),
+        }
+    }
+}
+
+pub fn rotate_backup(db: &DataBase, dir: &Path, keep: usize) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let name = format!(
+        "{}{}{}",
+        FILE_PREFIX,
+        chrono::Utc::now().format("%Y%m%d-%H%M%S"),
+        FILE_EXT
+    );
+    let path = dir.join(name);
+    db.backup_to_file(&path)?;
+
+    // имена с датой сортируются по времени
+    let mut old: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| match p.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.starts_with(FILE_PREFIX) && n.ends_with(FILE_EXT),
+            None => false,
+        })
+        .collect();
+    old.sort();
+    let extra = old.len().saturating_sub(keep.max(1));
+    for p in old.into_iter().take(extra) {
+        fs::remove_file(p)?;
+    }
+    Ok(path)
+}
+
+struct ArchiveTree<'a> {
+    name: &'a [u8],
+    entries: Vec<(&'a [u8], &'a [u8])>,
+    hash: &'a [u8],
+}
+
+fn parse(data: &[u8]) -> Result<Vec<ArchiveTree<'_>>> {
+    if data.len() < 32 {
+        return Err(Error::Corrupt("backup is too short".to_owned()));
+    }
+    let (body, total) = data.split_at(data.len() - 32);
+    if Sha256::digest(body).as_slice() != total {
+        return Err(Error::Corrupt("backup checksum mismatch".to_owned()));
+    }
+
+    let mut r = Reader { data: body };
+    if r.take(4)? != MAGIC {
+        return Err(Error::Corrupt("not a cmbot backup".to_owned()));
+    }
+    let version = u16::from_be_bytes(r.take(2)?.try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(Error::Corrupt(format!(
+            "unsupported backup version {}",
+            version
+        )));
+    }
+    r.take(8)?;
+    let count = r.u32()?;
+    let mut trees = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut tree_hash = Sha256::new();
+        let name = r.chunk()?;
+        hash_chunk(&mut tree_hash, name);
+        let len = u64::from_be_bytes(r.take(8)?.try_into().unwrap());
+        let mut entries = Vec::new();
+        for _ in 0..len {
+            let key = r.chunk()?;
+            let val = r.chunk()?;
+            hash_chunk(&mut tree_hash, key);
+            hash_chunk(&mut tree_hash, val);
+            entries.push((key, val));
+        }
+        let hash = r.take(32)?;
+        if tree_hash.finalize().as_slice() != hash {
+            return Err(Error::Corrupt(format!("tree {:?} checksum mismatch", name)));
+        }
+        trees.push(ArchiveTree {
+            name,
+            entries,
+            hash,
+        });
+    }
+    if !r.data.is_empty() {
+        return Err(Error::Corrupt("trailing data in backup".to_owned()));
+    }
+    Ok(trees)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(Error::Corrupt("unexpected end of backup".to_owned()));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn chunk(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+fn hash_chunk(hash: &mut Sha256, chunk: &[u8]) {
+    hash.update((chunk.len() as u32).to_be_bytes());
+    hash.update(chunk);
+}
+
+fn write_chunk<W: Write>(out: &mut W, hash: &mut Sha256, chunk: &[u8]) -> Result<()> {
+    hash_chunk(hash, chunk);
+    out.write_all(&(chunk.len() as u32).to_be_bytes())?;
+    out.write_all(chunk)?;
+    Ok(())
+}
+
+/// Считает sha256 всего, что через него записано
+struct HashWriter<W> {
+    inner: W,
+    hash: Sha256,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        HashWriter {
+            inner,
+            hash: Sha256::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hash.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{BinVals, Chat, Role, Tree};
+
+    fn tmp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cmbot-{}-{}", name, rand::random::<u32>()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn backup_restore() {
+        let db = DataBase::temporary();
+        let chat = Chat {
+            corner_id: 1,
+            name: "Мария".to_owned(),
+            is_active: true,
+            role: Role::Staff,
+            pay: None,
+            lang: None,
+        };
+        db.tree(Tree::Chats)
+            .unwrap()
+            .insert(Chat::key(42), chat.to_val().unwrap())
+            .unwrap();
+        db.tree(Tree::Revenues).unwrap().insert(b"k", b"v").unwrap();
+
+        let dir = tmp_dir("backup");
+        let archive = dir.join("test.cmbk");
+        fs::create_dir_all(&dir).unwrap();
+        let saved = db.backup_to_file(&archive).unwrap();
+
+        let (restored, summary) = DataBase::restore(&archive, &dir.join("db")).unwrap();
+        assert_eq!(saved, summary);
+        assert_eq!(restored.get_chat(42).unwrap(), Some(chat));
+        match DataBase::restore(&archive, &dir.join("db")) {
+            Err(Error::Conflict(_)) => {}
+            res => panic!("{:?}", res.map(|r| r.1)),
+        }
+
+        let mut data = fs::read(&archive).unwrap();
+        let mid = data.len() / 2;
+        data[mid] ^= 0xFF;
+        fs::write(&archive, data).unwrap();
+        match DataBase::restore(&archive, &dir.join("db2")) {
+            Err(Error::Corrupt(_)) => {}
+            res => panic!("{:?}", res.map(|r| r.1)),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotation() {
+        let db = DataBase::temporary();
+        let dir = tmp_dir("rotation");
+        fs::create_dir_all(&dir).unwrap();
+        for i in 0..4 {
+            let name = format!("{}2020010{}-000000{}", FILE_PREFIX, i, FILE_EXT);
+            fs::write(dir.join(name), b"old").unwrap();
+        }
+        let newest = rotate_backup(&db, &dir, 2).unwrap();
+        let mut left: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        left.sort();
+        assert_eq!(left.len(), 2);
+        assert_eq!(left[1], newest);
+        assert!(left[0].ends_with(format!("{}20200103-000000{}", FILE_PREFIX, FILE_EXT)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
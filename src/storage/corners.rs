@@ -0,0 +1,434 @@
+//! Справочник точек. Полное и короткое название уникальны вместе,
+//! как UNIQUE в `old_storage`, но без учета регистра: индекс
+//! `Tree::CornerNames` хранит нормализованное название -> id точки.
+
+use super::stats::Node;
+use super::{
+    abort, id_from_key, BinVals, Chat, Corner, DataBase, Error, InviteCode, Result, Revenue, Role,
+    Shift, Tree, TIMEZONE,
+};
+use crate::dates;
+use chrono_tz::Tz;
+use sled::Transactional;
+
+/// То, что администратор задает при создании и правке точки
+#[derive(Debug, Clone, Default)]
+pub struct CornerInfo {
+    pub name: String,
+    pub short_name: Option<String>,
+    pub address: Option<String>,
+    pub hours: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl DataBase {
+    pub fn add_corner(&self, info: CornerInfo) -> Result<Corner> {
+        let id = self.next_id(Tree::Corners)?;
+        let mut corner = Corner {
+            id,
+            name: String::new(),
+            short_name: None,
+            address: None,
+            hours: None,
+            archived: false,
+            tags: Vec::new(),
+            group_id: None,
+            photo_required: false,
+            anomaly_ratio: super::ANOMALY_RATIO,
+            timezone: TIMEZONE.name().to_owned(),
+        };
+        corner.apply(info)?;
+        self.save_corner(None, &corner)?;
+        Ok(corner)
+    }
+
+    pub fn get_corner(&self, id: u32) -> Result<Option<Corner>> {
+        self.get(Tree::Corners, Corner::key(id))
+    }
+
+    /// Точка по полному или короткому названию
+    pub fn find_corner(&self, name: &str) -> Result<Option<Corner>> {
+        match self.tree(Tree::CornerNames)?.get(normalize(name))? {
+            Some(id) => self.get_corner(id_from_key(&id)?),
+            None => Ok(None),
+        }
+    }
+
+    pub fn corners_by_tag(&self, tag: &str) -> Result<Vec<Corner>> {
+        let tag = normalize(tag);
+        let mut res = self.get_corners()?;
+        res.retain(|c| c.tags.contains(&tag));
+        Ok(res)
+    }
+
+    /// Заменяет названия, адрес, часы работы и метки точки
+    pub fn update_corner(&self, id: u32, info: CornerInfo) -> Result<Corner> {
+        self.modify_corner(id, |c| c.apply(info))
+    }
+
+    /// Архивная точка пропадает из выбора и отчетов, но ее история остается
+    pub fn set_corner_archived(&self, id: u32, archived: bool) -> Result<Corner> {
+        self.modify_corner(id, |c| {
+            c.archived = archived;
+            Ok(())
+        })
+    }
+
+    /// Часовой пояс точки по имени IANA, например "Asia/Vladivostok"
+    pub fn set_corner_timezone(&self, id: u32, name: &str) -> Result<Corner> {
+        let tz = dates::zone(name).ok_or_else(|| Error::Invalid(format!("timezone {:?}", name)))?;
+        self.modify_corner(id, |c| {
+            c.timezone = tz.name().to_owned();
+            Ok(())
+        })
+    }
+
+    /// Пояс точки, `TIMEZONE` для неизвестной
+    pub fn corner_tz(&self, id: u32) -> Result<Tz> {
+        Ok(self.get_corner(id)?.map_or(TIMEZONE, |c| c.tz()))
+    }
+
+    /// Пояс, в котором собеседник считает "сегодня": пояс его точки,
+    /// а у администратора без точки - общий пояс видимых ему точек
+    pub fn chat_tz(&self, chat: &Chat) -> Result<Tz> {
+        if let Some(corner) = self.get_corner(chat.corner_id)? {
+            return Ok(corner.tz());
+        }
+        if let Role::Staff = chat.role {
+            return Ok(TIMEZONE);
+        }
+        let zones: Vec<Tz> = self.visible_corners(chat)?.iter().map(Corner::tz).collect();
+        Ok(match zones.first() {
+            Some(&tz) if zones.iter().all(|&z| z == tz) => tz,
+            _ => TIMEZONE,
+        })
+    }
+
+    /// Удаляет точку, на которую еще ничего не ссылается.
+    /// Точки с выручкой, расходами, планами, сменами, сотрудниками
+    /// или приглашениями можно только архивировать.
+    pub fn remove_corner(&self, id: u32) -> Result<()> {
+        let corners = self.tree(Tree::Corners)?;
+        let raw = corners.get(Corner::key(id))?.ok_or(Error::NotFound)?;
+        let corner = Corner::from_val(raw.clone())?;
+
+        let id_bytes = id.to_be_bytes();
+        // деревья, где id точки лежит в ключе, и его смещение
+        let keyed = vec![
+            (Tree::Revenues, 4, "revenue"),
+            (Tree::Expenses, 4, "expenses"),
+            (Tree::Targets, 0, "targets"),
+        ];
+        for (tree, at, what) in keyed {
+            for key in self.tree(tree)?.iter().keys() {
+                if key?.get(at..at + 4) == Some(&id_bytes[..]) {
+                    return Err(Error::Conflict(format!("corner {} has {}", id, what)));
+                }
+            }
+        }
+        let stats = self.tree(Tree::Stats)?;
+        let prefix = &Node::Corner(id).key(0)[..5];
+        if stats.scan_prefix(prefix).next().is_some() {
+            return Err(Error::Conflict(format!("corner {} has stats", id)));
+        }
+        for val in self.tree(Tree::Shifts)?.iter().values() {
+            if Shift::from_val(val?)?.corner_id == id {
+                return Err(Error::Conflict(format!("corner {} has shifts", id)));
+            }
+        }
+        for val in self.tree(Tree::Pending)?.iter().values() {
+            if Revenue::from_val(val?)?.corner_id == id {
+                return Err(Error::Conflict(format!(
+                    "corner {} has pending revenue",
+                    id
+                )));
+            }
+        }
+        for val in self.tree(Tree::Chats)?.iter().values() {
+            if Chat::from_val(val?)?.corner_id == id {
+                return Err(Error::Conflict(format!("corner {} has staff", id)));
+            }
+        }
+        for val in self.tree(Tree::Invites)?.iter().values() {
+            if InviteCode::from_val(val?)?.corner_id == id {
+                return Err(Error::Conflict(format!("corner {} has invite codes", id)));
+            }
+        }
+
+        let names = self.tree(Tree::CornerNames)?;
+        let keys = corner.name_keys();
+        (&corners, &names).transaction(|(corners, names)| {
+            if corners.remove(Corner::key(id))?.as_ref() != Some(&raw) {
+                return abort(Error::Conflict(format!("corner {} changed", id)));
+            }
+            for key in &keys {
+                names.remove(key.as_bytes())?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    pub(super) fn modify_corner<F>(&self, id: u32, f: F) -> Result<Corner>
+    where
+        F: FnOnce(&mut Corner) -> Result<()>,
+    {
+        let raw = self
+            .tree(Tree::Corners)?
+            .get(Corner::key(id))?
+            .ok_or(Error::NotFound)?;
+        let old = Corner::from_val(raw.clone())?;
+        let mut corner = old.clone();
+        f(&mut corner)?;
+        self.save_corner(Some((&raw, &old)), &corner)?;
+        Ok(corner)
+    }
+
+    /// Записывает точку и ее названия в индекс одной транзакцией.
+    /// `old` - сырое значение, прочитанное перед правкой, и его разбор.
+    fn save_corner(&self, old: Option<(&sled::IVec, &Corner)>, corner: &Corner) -> Result<()> {
+        let corners = self.tree(Tree::Corners)?;
+        let names = self.tree(Tree::CornerNames)?;
+        let val = corner.to_val()?;
+        let old_keys = old.map(|(_, c)| c.name_keys()).unwrap_or_default();
+        let new_keys = corner.name_keys();
+        let id = corner.id.to_be_bytes();
+        (&corners, &names).transaction(|(corners, names)| {
+            let stored = corners.get(corner.to_key())?;
+            if stored.as_ref() != old.map(|(raw, _)| raw) {
+                return abort(Error::Conflict(format!("corner {} changed", corner.id)));
+            }
+            for key in &old_keys {
+                names.remove(key.as_bytes())?;
+            }
+            for key in &new_keys {
+                match names.insert(key.as_bytes(), &id)? {
+                    Some(owner) if owner != id => {
+                        return abort(Error::Duplicate(format!("corner name {:?}", key)))
+                    }
+                    _ => {}
+                }
+            }
+            corners.insert(corner.to_key(), val.clone())?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Строит индекс названий заново, если его еще нет.
+    /// Нужно для баз, где точки появились раньше индекса.
+    pub(super) fn index_corner_names(&self) -> Result<()> {
+        let names = self.tree(Tree::CornerNames)?;
+        if !names.is_empty() {
+            return Ok(());
+        }
+        for corner in self.get_corners()? {
+            for key in corner.name_keys() {
+                if let Some(owner) = names.insert(key.as_bytes(), &corner.id.to_be_bytes())? {
+                    eprintln!(
+                        "WARNING: corner name {:?} is used by corners {} and {}",
+                        key,
+                        id_from_key(&owner)?,
+                        corner.id
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Corner {
+    fn apply(&mut self, info: CornerInfo) -> Result<()> {
+        let name = info.name.trim();
+        if name.is_empty() {
+            return Err(Error::Invalid("corner name is empty".to_owned()));
+        }
+        self.name = name.to_owned();
+        self.short_name = non_empty(info.short_name);
+        self.address = non_empty(info.address);
+        self.hours = non_empty(info.hours);
+        let mut tags: Vec<String> = info
+            .tags
+            .iter()
+            .map(|t| normalize(t))
+            .filter(|t| !t.is_empty())
+            .collect();
+        tags.sort();
+        tags.dedup();
+        self.tags = tags;
+        Ok(())
+    }
+
+    fn name_keys(&self) -> Vec<String> {
+        let mut keys = vec![normalize(&self.name)];
+        if let Some(short) = self.short_name.as_ref() {
+            let short = normalize(short);
+            if short != keys[0] {
+                keys.push(short);
+            }
+        }
+        keys
+    }
+}
+
+fn normalize(s: &str) -> String {
+    s.trim().to_lowercase()
+}
+
+fn non_empty(s: Option<String>) -> Option<String> {
+    s.map(|s| s.trim().to_owned()).filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Money;
+    use crate::storage::TargetPeriod;
+
+    fn info(name: &str, short: &str, tags: &[&str]) -> CornerInfo {
+        CornerInfo {
+            name: name.to_owned(),
+            short_name: Some(short.to_owned()),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn corner_crud() {
+        let db = DataBase::temporary();
+        let mall = db
+            .add_corner(info("ТЦ Галерея", "гал", &["Mall", "mall ", "центр"]))
+            .unwrap();
+        let station = db.add_corner(info("Вокзал", "вкз", &["station"])).unwrap();
+        assert_eq!((mall.id, station.id), (1, 2));
+        assert_eq!(mall.tags, vec!["mall", "центр"]);
+
+        match db.add_corner(info("Гал", "новая", &[])) {
+            Err(Error::Duplicate(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        match db.update_corner(station.id, info("Вокзал", "ТЦ ГАЛЕРЕЯ", &[])) {
+            Err(Error::Duplicate(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        assert_eq!(db.get_corner(station.id).unwrap().unwrap(), station);
+
+        let renamed = db
+            .update_corner(mall.id, info("Галерея", "гал", &["mall"]))
+            .unwrap();
+        assert_eq!(db.find_corner("галерея ").unwrap(), Some(renamed.clone()));
+        assert_eq!(db.find_corner("ТЦ Галерея").unwrap(), None);
+        db.add_corner(info("ТЦ Галерея", "тц", &[])).unwrap();
+
+        db.set_corner_archived(station.id, true).unwrap();
+        assert_eq!(db.corners_by_tag("MALL").unwrap(), vec![renamed]);
+        assert_eq!(
+            db.get_corners()
+                .unwrap()
+                .iter()
+                .filter(|c| !c.archived)
+                .count(),
+            2
+        );
+        assert_eq!(db.get_corners().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn timezones() {
+        let db = DataBase::temporary();
+        let east = db.add_corner(info("Набережная", "нбр", &[])).unwrap();
+        let west = db.add_corner(info("Вокзал", "вкз", &[])).unwrap();
+        assert_eq!(east.tz(), TIMEZONE);
+        match db.set_corner_timezone(east.id, "Asia/Владивосток") {
+            Err(Error::Invalid(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        let east = db
+            .set_corner_timezone(east.id, " Asia/Vladivostok")
+            .unwrap();
+        assert_eq!(east.timezone, "Asia/Vladivostok");
+        // 2020-03-01 16:00 UTC: в Москве еще 1 марта, во Владивостоке 2-е
+        let ts = 1_583_078_400;
+        assert_eq!(east.day_at(ts).unwrap(), west.day_at(ts).unwrap() + 1);
+
+        let chat = |corner_id, role| Chat {
+            corner_id,
+            name: "Иван".to_owned(),
+            is_active: true,
+            role,
+            pay: None,
+            lang: None,
+        };
+        assert_eq!(db.chat_tz(&chat(east.id, Role::Staff)).unwrap(), east.tz());
+        assert_eq!(db.chat_tz(&chat(0, Role::Admin(None))).unwrap(), TIMEZONE);
+        db.set_corner_timezone(west.id, "Asia/Vladivostok").unwrap();
+        assert_eq!(db.chat_tz(&chat(0, Role::Admin(None))).unwrap(), east.tz());
+    }
+
+    #[test]
+    fn remove_only_unused() {
+        let db = DataBase::temporary();
+        let used = db.add_corner(info("Вокзал", "вкз", &[])).unwrap();
+        let unused = db.add_corner(info("Парк", "прк", &[])).unwrap();
+        db.tree(Tree::Chats)
+            .unwrap()
+            .insert(
+                Chat::key(1),
+                Chat {
+                    corner_id: used.id,
+                    name: "Иван".to_owned(),
+                    is_active: true,
+                    role: Role::Staff,
+                    pay: None,
+                    lang: None,
+                }
+                .to_val()
+                .unwrap(),
+            )
+            .unwrap();
+
+        match db.remove_corner(used.id) {
+            Err(Error::Conflict(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        let planned = db.add_corner(info("Сквер", "скв", &[])).unwrap();
+        db.set_target(planned.id, 1, TargetPeriod::Month, Money::rubles(1000))
+            .unwrap();
+        match db.remove_corner(planned.id) {
+            Err(Error::Conflict(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        let pending = db.add_corner(info("Рынок", "рнк", &[])).unwrap();
+        db.put_pending(
+            1,
+            &Revenue {
+                corner_id: pending.id,
+                date: 1,
+                amount: Money::rubles(500),
+                post_datetime: 0,
+                comment: None,
+                payments: Vec::new(),
+                photo: None,
+                staff: Some(1),
+            },
+        )
+        .unwrap();
+        match db.remove_corner(pending.id) {
+            Err(Error::Conflict(_)) => {}
+            res => panic!("{:?}", res),
+        }
+
+        let last = db.add_corner(info("Пирс", "прс", &[])).unwrap();
+        db.remove_corner(last.id).unwrap();
+        db.remove_corner(unused.id).unwrap();
+        assert_eq!(db.get_corner(unused.id).unwrap(), None);
+        assert_eq!(db.find_corner("прк").unwrap(), None);
+        // id удаленных точек новым не достаются
+        assert_eq!(
+            db.add_corner(info("Парк", "прк", &[])).unwrap().id,
+            last.id + 1
+        );
+    }
+}
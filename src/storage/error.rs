@@ -0,0 +1,79 @@
+use sled::transaction::TransactionError;
+use std::fmt;
+
+/// Ошибки хранилища. `Corrupt` и `Io` стоит логировать целиком,
+/// пользователю достаточно вежливого сообщения.
+#[derive(Debug)]
+pub enum Error {
+    /// Запись есть, но не декодируется, либо sled нашел повреждение файлов
+    Corrupt(String),
+    NotFound,
+    /// Запись уже существует или изменилась параллельно
+    Conflict(String),
+    /// Нарушена уникальность, например название точки уже занято
+    Duplicate(String),
+    /// Данные не прошли проверку
+    Invalid(String),
+    Io(sled::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Дата или номер дня, которым нет места в календаре
+    pub fn out_of_range(what: impl fmt::Display) -> Error {
+        Error::Invalid(format!("{} is out of the calendar range", what))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Corrupt(e) => write!(f, "corrupted record: {}", e),
+            Error::NotFound => write!(f, "record not found"),
+            Error::Conflict(e) => write!(f, "conflict: {}", e),
+            Error::Duplicate(e) => write!(f, "already exists: {}", e),
+            Error::Invalid(e) => write!(f, "invalid data: {}", e),
+            Error::Io(e) => write!(f, "sled error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sled::Error> for Error {
+    fn from(e: sled::Error) -> Self {
+        match e {
+            sled::Error::Corruption { .. } => Error::Corrupt(e.to_string()),
+            e => Error::Io(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(sled::Error::Io(e))
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Corrupt(e.to_string())
+    }
+}
+
+impl From<TransactionError<Error>> for Error {
+    fn from(e: TransactionError<Error>) -> Self {
+        match e {
+            TransactionError::Abort(e) => e,
+            TransactionError::Storage(e) => e.into(),
+        }
+    }
+}
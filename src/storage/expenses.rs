@@ -0,0 +1,153 @@
+//! Расходы точек. Ключ: день и id точки, как у `Revenue`, плюс id расхода.
+
+use super::stats::{add_sum, corner_nodes, update_stats};
+use super::{BinVals, DataBase, Error, Expense, Result, Stats, Tree};
+use crate::money::Money;
+use sled::Transactional;
+
+/// Категории, которые бот предлагает в подсказке. Можно указать и любую другую.
+pub const EXPENSE_CATEGORIES: [&str; 4] = ["расходники", "доставка", "уборка", "прочее"];
+
+impl Expense {
+    fn key(date: u32, corner_id: u32, id: u64) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        key[..8].copy_from_slice(&Self::prefix(date, corner_id));
+        key[8..].copy_from_slice(&id.to_be_bytes());
+        key
+    }
+
+    fn prefix(date: u32, corner_id: u32) -> [u8; 8] {
+        let mut key = [0u8; 8];
+        key[..4].copy_from_slice(&date.to_be_bytes());
+        key[4..].copy_from_slice(&corner_id.to_be_bytes());
+        key
+    }
+}
+
+impl DataBase {
+    /// Записывает новый расход, `id` назначается здесь
+    pub fn add_expense(&self, mut exp: Expense) -> Result<Expense> {
+        exp.category = exp.category.trim().to_lowercase();
+        if exp.category.is_empty() || exp.amount <= Money::ZERO {
+            return Err(Error::Invalid(
+                "expense needs a category and amount".to_owned(),
+            ));
+        }
+        exp.id = self.sled.generate_id()?;
+        let month = Stats::month(exp.date)?;
+        let val = exp.to_val()?;
+
+        let expenses = self.tree(Tree::Expenses)?;
+        let stats = self.tree(Tree::Stats)?;
+        let corners = self.tree(Tree::Corners)?;
+        let groups = self.tree(Tree::Groups)?;
+        let trees = (&expenses, &stats, &corners, &groups);
+        trees.transaction(|(expenses, stats, corners, groups)| {
+            let nodes = corner_nodes(corners, groups, exp.corner_id)?;
+            expenses.insert(&Expense::key(exp.date, exp.corner_id, exp.id), val.clone())?;
+            update_stats(stats, &nodes, month, |s| {
+                add_sum(&mut s.expenses, exp.amount, 1)
+            })
+        })?;
+        Ok(exp)
+    }
+
+    /// Расходы точки за день в порядке добавления
+    pub fn get_expenses(&self, day: u32, corner_id: u32) -> Result<Vec<Expense>> {
+        self.tree(Tree::Expenses)?
+            .scan_prefix(Expense::prefix(day, corner_id))
+            .values()
+            .map(|val| Expense::from_val(val?))
+            .collect()
+    }
+
+    /// Удаляет расход, внесенный по ошибке
+    pub fn remove_expense(&self, day: u32, corner_id: u32, id: u64) -> Result<Expense> {
+        let month = Stats::month(day)?;
+        let expenses = self.tree(Tree::Expenses)?;
+        let stats = self.tree(Tree::Stats)?;
+        let corners = self.tree(Tree::Corners)?;
+        let groups = self.tree(Tree::Groups)?;
+        let trees = (&expenses, &stats, &corners, &groups);
+        let exp = trees.transaction(|(expenses, stats, corners, groups)| {
+            let nodes = corner_nodes(corners, groups, corner_id)?;
+            let exp = match expenses.remove(&Expense::key(day, corner_id, id))? {
+                Some(val) => Expense::from_val(val).or_else(super::abort)?,
+                None => return super::abort(Error::NotFound),
+            };
+            update_stats(stats, &nodes, month, |s| {
+                add_sum(&mut s.expenses, exp.amount, -1)
+            })?;
+            Ok(exp)
+        })?;
+        Ok(exp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{CornerInfo, Node, Revenue};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn expenses_in_stats() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap()
+            .id;
+        let day = Revenue::day(NaiveDate::from_ymd(2020, 3, 1)).unwrap();
+        let exp = |category: &str, amount| Expense {
+            corner_id: corner,
+            date: day,
+            id: 0,
+            category: category.to_owned(),
+            amount: Money::rubles(amount),
+            comment: None,
+            photo: None,
+            post_datetime: 0,
+        };
+        db.put_revenue(&Revenue {
+            corner_id: corner,
+            date: day,
+            amount: Money::rubles(1_000),
+            post_datetime: 0,
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff: None,
+        })
+        .unwrap();
+        let first = db.add_expense(exp(" Доставка", 150)).unwrap();
+        db.add_expense(exp("уборка", 50)).unwrap();
+        match db.add_expense(exp("уборка", 0)) {
+            Err(Error::Invalid(_)) => {}
+            res => panic!("{:?}", res),
+        }
+
+        let list = db.get_expenses(day, corner).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].category, "доставка");
+        let month = Stats::month(day).unwrap();
+        assert_eq!(
+            db.stats(Node::Corner(corner), month).unwrap().expenses,
+            Money::rubles(200)
+        );
+
+        db.remove_expense(day, corner, first.id).unwrap();
+        assert_eq!(
+            db.stats(Node::Corner(corner), month).unwrap().expenses,
+            Money::rubles(50)
+        );
+        db.rebuild_stats().unwrap();
+        let stats = db.stats(Node::Corner(corner), month).unwrap();
+        assert_eq!(
+            (stats.revenue, stats.expenses),
+            (Money::rubles(1_000), Money::rubles(50))
+        );
+    }
+}
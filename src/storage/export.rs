@@ -0,0 +1,114 @@
+//! Выгрузка выручки в CSV с разбивкой по способам оплаты.
+//! Колонки: `corner,date,amount`, затем по колонке на каждый способ
+//! (последняя - "не указано") и `comment`.
+
+use super::{BinVals, DataBase, Result, Revenue, Tree, UNSPECIFIED};
+use crate::money::Money;
+use std::collections::HashMap;
+use std::io::Write;
+
+impl DataBase {
+    /// Пишет все записи выручки по дням. Возвращает число строк.
+    pub fn export_revenue<W: Write>(&self, out: W) -> Result<usize> {
+        let corners: HashMap<u32, String> = self
+            .get_corners()?
+            .into_iter()
+            .map(|c| (c.id, c.name))
+            .collect();
+        let mut methods: Vec<(u32, String)> = self
+            .payment_methods()?
+            .into_iter()
+            .map(|m| (m.id, m.name))
+            .collect();
+        methods.push((UNSPECIFIED, "не указано".to_owned()));
+
+        let mut csv = csv::Writer::from_writer(out);
+        let mut header = vec!["corner", "date", "amount"];
+        header.extend(methods.iter().map(|(_, name)| name.as_str()));
+        header.push("comment");
+        csv.write_record(&header).map_err(csv_error)?;
+
+        let mut rows = 0;
+        for val in self.tree(Tree::Revenues)?.iter().values() {
+            let rev = Revenue::from_val(val?)?;
+            let by_method: HashMap<u32, Money> = rev
+                .breakdown()
+                .iter()
+                .map(|p| (p.method, p.amount))
+                .collect();
+            let date = Revenue::date_of(rev.date)?;
+            let mut record = vec![
+                corners
+                    .get(&rev.corner_id)
+                    .cloned()
+                    .unwrap_or_else(|| rev.corner_id.to_string()),
+                date.format("%Y-%m-%d").to_string(),
+                rev.amount.plain(),
+            ];
+            record.extend(methods.iter().map(|(id, _)| match by_method.get(id) {
+                Some(amount) => amount.plain(),
+                None => String::new(),
+            }));
+            record.push(rev.comment.unwrap_or_default());
+            csv.write_record(&record).map_err(csv_error)?;
+            rows += 1;
+        }
+        csv.flush()?;
+        Ok(rows)
+    }
+}
+
+pub(super) fn csv_error(e: csv::Error) -> super::Error {
+    match e.into_kind() {
+        csv::ErrorKind::Io(e) => e.into(),
+        kind => super::Error::Invalid(format!("{:?}", kind)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{CornerInfo, Payment};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn export_breakdown() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let date = NaiveDate::from_ymd(2020, 3, 1);
+        let mut rev = Revenue {
+            corner_id: corner.id,
+            date: Revenue::day(date).unwrap(),
+            amount: Money::rubles(100),
+            post_datetime: 0,
+            comment: Some("старая запись".to_owned()),
+            payments: Vec::new(),
+            photo: None,
+            staff: None,
+        };
+        db.put_revenue(&rev).unwrap();
+        rev.date += 1;
+        rev.comment = None;
+        rev.amount = Money::from_kopecks(10_050);
+        rev.payments = vec![
+            Payment::new(1, Money::from_kopecks(6_050)),
+            Payment::new(3, Money::rubles(40)),
+        ];
+        db.put_revenue(&rev).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(db.export_revenue(&mut out).unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "corner,date,amount,Наличные,Карта,QR/перевод,не указано,comment
+Галерея,2020-03-01,100,,,,100,старая запись
+Галерея,2020-03-02,100.50,60.50,,40,,
+"
+        );
+    }
+}
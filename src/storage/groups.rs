@@ -0,0 +1,288 @@
+//! Иерархия точек: группы любой вложенности (регион -> город -> точка)
+//! и права администраторов в пределах группы.
+
+use super::stats::{corner_nodes, group_nodes, update_stats, Node};
+use super::{abort, BinVals, Chat, Corner, DataBase, Error, Group, Result, Role, Stats, Tree};
+use sled::transaction::{ConflictableTransactionResult, TransactionalTree};
+use sled::Transactional;
+
+impl DataBase {
+    pub fn add_group(&self, name: &str, parent_id: Option<u32>) -> Result<Group> {
+        let name = self.group_name(name, parent_id, None)?;
+        let group = Group {
+            id: self.next_id(Tree::Groups)?,
+            name,
+            parent_id,
+        };
+        self.tree(Tree::Groups)?
+            .insert(Group::key(group.id), group.to_val()?)?;
+        Ok(group)
+    }
+
+    pub fn get_group(&self, id: u32) -> Result<Option<Group>> {
+        self.get(Tree::Groups, Group::key(id))
+    }
+
+    pub fn get_groups(&self) -> Result<Vec<Group>> {
+        self.tree(Tree::Groups)?
+            .iter()
+            .values()
+            .map(|val| Group::from_val(val?))
+            .collect()
+    }
+
+    pub fn rename_group(&self, id: u32, name: &str) -> Result<Group> {
+        let mut group = self.get_group(id)?.ok_or(Error::NotFound)?;
+        group.name = self.group_name(name, group.parent_id, Some(id))?;
+        self.tree(Tree::Groups)?
+            .insert(Group::key(id), group.to_val()?)?;
+        Ok(group)
+    }
+
+    /// Переносит группу со всеми точками под другого родителя.
+    /// Сводки точек группы переносятся той же транзакцией, что и группа.
+    pub fn move_group(&self, id: u32, parent_id: Option<u32>) -> Result<Group> {
+        let old = self
+            .tree(Tree::Groups)?
+            .get(Group::key(id))?
+            .ok_or(Error::NotFound)?;
+        let mut group = Group::from_val(old.clone())?;
+        if let Some(parent) = parent_id {
+            if self.group_path(Some(parent))?.contains(&id) {
+                return Err(Error::Invalid(format!(
+                    "group {} can't be moved into itself",
+                    id
+                )));
+            }
+        }
+        group.name = self.group_name(&group.name, parent_id, Some(id))?;
+        group.parent_id = parent_id;
+        let mut members = Vec::new();
+        for corner in self.get_corners()? {
+            if self.group_path(corner.group_id)?.contains(&id) {
+                members.push((corner.id, self.stats_months(corner.id)?));
+            }
+        }
+
+        let corners = self.tree(Tree::Corners)?;
+        let groups = self.tree(Tree::Groups)?;
+        let stats = self.tree(Tree::Stats)?;
+        (&corners, &groups, &stats).transaction(|(corners, groups, stats)| {
+            if groups.get(Group::key(id))?.as_ref() != Some(&old) {
+                return abort(Error::Conflict(format!("group {} changed", id)));
+            }
+            let mut old_nodes = Vec::new();
+            for (corner_id, _) in &members {
+                old_nodes.push(corner_nodes(corners, groups, *corner_id)?);
+            }
+            groups.insert(Group::key(id), group.to_val().or_else(abort)?)?;
+            for ((corner_id, months), old) in members.iter().zip(&old_nodes) {
+                let new = corner_nodes(corners, groups, *corner_id)?;
+                move_corner_stats(stats, *corner_id, months, old, &new)?;
+            }
+            Ok(())
+        })?;
+        Ok(group)
+    }
+
+    /// Удаляет пустую группу: без подгрупп, точек и администраторов
+    pub fn remove_group(&self, id: u32) -> Result<()> {
+        self.get_group(id)?.ok_or(Error::NotFound)?;
+        if self.get_groups()?.iter().any(|g| g.parent_id == Some(id)) {
+            return Err(Error::Conflict(format!("group {} has subgroups", id)));
+        }
+        if self.get_corners()?.iter().any(|c| c.group_id == Some(id)) {
+            return Err(Error::Conflict(format!("group {} has corners", id)));
+        }
+        for val in self.tree(Tree::Chats)?.iter().values() {
+            if Chat::from_val(val?)?.role == Role::Admin(Some(id)) {
+                return Err(Error::Conflict(format!("group {} has admins", id)));
+            }
+        }
+        // в пустой группе нет точек, и сводки пересчитывать не нужно
+        self.tree(Tree::Groups)?.remove(Group::key(id))?;
+        Ok(())
+    }
+
+    /// Включает точку в группу (или убирает из всех при `None`).
+    /// Сводки точки за все месяцы одной транзакцией переносятся
+    /// из групп, которые она покинула, в новые.
+    pub fn set_corner_group(&self, corner_id: u32, group_id: Option<u32>) -> Result<Corner> {
+        if let Some(group) = group_id {
+            self.get_group(group)?.ok_or(Error::NotFound)?;
+        }
+        let months = self.stats_months(corner_id)?;
+
+        let corners = self.tree(Tree::Corners)?;
+        let groups = self.tree(Tree::Groups)?;
+        let stats = self.tree(Tree::Stats)?;
+        let trees = (&corners, &groups, &stats);
+        let corner = trees.transaction(|(corners, groups, stats)| {
+            let mut corner = match corners.get(Corner::key(corner_id))? {
+                Some(val) => Corner::from_val(val).or_else(abort)?,
+                None => return abort(Error::NotFound),
+            };
+            let old = corner_nodes(corners, groups, corner_id)?;
+            let new = group_nodes(groups, Node::Corner(corner_id), group_id)?;
+            move_corner_stats(stats, corner_id, &months, &old, &new)?;
+            corner.group_id = group_id;
+            corners.insert(Corner::key(corner_id), corner.to_val().or_else(abort)?)?;
+            Ok(corner)
+        })?;
+        Ok(corner)
+    }
+
+    /// Назначает роль собеседнику. Группа администратора должна существовать.
+    pub fn set_chat_role(&self, id: i64, role: Role) -> Result<Chat> {
+        if let Role::Admin(Some(group)) = role {
+            self.get_group(group)?.ok_or(Error::NotFound)?;
+        }
+        let mut chat = self.get_chat(id)?.ok_or(Error::NotFound)?;
+        chat.role = role;
+        self.put_chat(id, &chat)?;
+        Ok(chat)
+    }
+
+    /// Группа и все ее предки, начиная с ближайшей
+    pub fn group_path(&self, group_id: Option<u32>) -> Result<Vec<u32>> {
+        let mut path = Vec::new();
+        let mut next = group_id;
+        while let Some(id) = next {
+            if path.contains(&id) {
+                return Err(Error::Corrupt(format!("group {} is its own parent", id)));
+            }
+            path.push(id);
+            next = self.get_group(id)?.and_then(|g| g.parent_id);
+        }
+        Ok(path)
+    }
+
+    /// Входит ли точка в область администратора. `None` - вся сеть.
+    pub fn in_scope(&self, scope: Option<u32>, corner: &Corner) -> Result<bool> {
+        match scope {
+            None => Ok(true),
+            Some(group) => Ok(self.group_path(corner.group_id)?.contains(&group)),
+        }
+    }
+
+    /// Точки, которые видит и которыми управляет собеседник
+    pub fn visible_corners(&self, chat: &Chat) -> Result<Vec<Corner>> {
+        let mut res = Vec::new();
+        for corner in self.get_corners()? {
+            let visible = match chat.role {
+                Role::Staff => corner.id == chat.corner_id,
+                Role::Admin(scope) => self.in_scope(scope, &corner)?,
+            };
+            if visible {
+                res.push(corner);
+            }
+        }
+        Ok(res)
+    }
+
+    pub fn can_manage(&self, chat: &Chat, corner_id: u32) -> Result<bool> {
+        match chat.role {
+            Role::Staff => Ok(false),
+            Role::Admin(scope) => match self.get_corner(corner_id)? {
+                Some(corner) => self.in_scope(scope, &corner),
+                None => Ok(false),
+            },
+        }
+    }
+
+    /// Проверяет название и родителя: имена соседних групп не повторяются
+    fn group_name(&self, name: &str, parent_id: Option<u32>, id: Option<u32>) -> Result<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(Error::Invalid("group name is empty".to_owned()));
+        }
+        if let Some(parent) = parent_id {
+            self.get_group(parent)?.ok_or(Error::NotFound)?;
+        }
+        let lower = name.to_lowercase();
+        let taken = self.get_groups()?.iter().any(|g| {
+            g.parent_id == parent_id && Some(g.id) != id && g.name.to_lowercase() == lower
+        });
+        if taken {
+            return Err(Error::Duplicate(format!("group name {:?}", name)));
+        }
+        Ok(name.to_owned())
+    }
+}
+
+/// Переносит месячные сводки точки из узлов `old`, которые она покинула,
+/// в новые узлы `new`
+fn move_corner_stats(
+    stats: &TransactionalTree,
+    corner_id: u32,
+    months: &[u32],
+    old: &[Node],
+    new: &[Node],
+) -> ConflictableTransactionResult<(), Error> {
+    let left: Vec<Node> = old.iter().filter(|n| !new.contains(n)).copied().collect();
+    let joined: Vec<Node> = new.iter().filter(|n| !old.contains(n)).copied().collect();
+    for &month in months {
+        let own = match stats.get(Node::Corner(corner_id).key(month))? {
+            Some(val) => Stats::from_val(val).or_else(abort)?,
+            None => continue,
+        };
+        update_stats(stats, &left, month, |s| s.add_stats(&own, -1))?;
+        update_stats(stats, &joined, month, |s| s.add_stats(&own, 1))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::CornerInfo;
+
+    #[test]
+    fn hierarchy_and_scope() {
+        let db = DataBase::temporary();
+        let region = db.add_group("Северо-Запад", None).unwrap();
+        let spb = db.add_group("Санкт-Петербург", Some(region.id)).unwrap();
+        let msk = db.add_group("Москва", None).unwrap();
+        match db.add_group("санкт-петербург ", Some(region.id)) {
+            Err(Error::Duplicate(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        match db.move_group(region.id, Some(spb.id)) {
+            Err(Error::Invalid(_)) => {}
+            res => panic!("{:?}", res),
+        }
+
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let corner = db.set_corner_group(corner.id, Some(spb.id)).unwrap();
+        assert_eq!(
+            db.group_path(corner.group_id).unwrap(),
+            vec![spb.id, region.id]
+        );
+
+        let admin = |scope| Chat {
+            corner_id: 0,
+            name: "Админ".to_owned(),
+            is_active: true,
+            role: Role::Admin(scope),
+            pay: None,
+            lang: None,
+        };
+        assert!(db.can_manage(&admin(Some(region.id)), corner.id).unwrap());
+        assert!(db.can_manage(&admin(None), corner.id).unwrap());
+        assert!(!db.can_manage(&admin(Some(msk.id)), corner.id).unwrap());
+        assert!(db.visible_corners(&admin(Some(msk.id))).unwrap().is_empty());
+
+        match db.remove_group(spb.id) {
+            Err(Error::Conflict(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        db.remove_group(msk.id).unwrap();
+        assert_eq!(db.get_groups().unwrap(), vec![region, spb]);
+        assert_eq!(db.add_group("Москва", None).unwrap().id, msk.id + 1);
+    }
+}
@@ -0,0 +1,302 @@
+//! Загрузка исторической выручки из таблиц.
+//!
+//! CSV с заголовком `corner,date,amount,comment` или JSON-массив объектов
+//! с теми же полями. `corner` - полное или короткое название точки,
+//! `date` - `ГГГГ-ММ-ДД` или `ДД.ММ.ГГГГ`, `amount` - рубли,
+//! копейки через точку или запятую.
+
+use super::{BinVals, DataBase, Result, Revenue, Tree};
+use crate::money::Money;
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    Csv,
+    Json,
+}
+
+/// Что делать, если выручка точки за этот день уже есть в базе
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnConflict {
+    Skip,
+    Overwrite,
+    /// Ничего не записывать, если конфликтует хотя бы одна строка
+    Fail,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub rows: usize,
+    pub inserted: usize,
+    pub overwritten: usize,
+    pub skipped: usize,
+    /// Номер строки (с 1, без заголовка) и причина
+    pub errors: Vec<(usize, String)>,
+    /// Запись отменена из-за `OnConflict::Fail`
+    pub aborted: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize)]
+struct RawRow {
+    corner: String,
+    date: String,
+    #[serde(deserialize_with = "amount_as_string")]
+    amount: String,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+impl DataBase {
+    /// Разбирает все строки и, если это не `dry_run`, записывает
+    /// корректные одним пакетом. Строки с ошибками не пишутся никогда.
+    pub fn import_revenue<R: Read>(
+        &self,
+        input: R,
+        format: ImportFormat,
+        on_conflict: OnConflict,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        let mut report = ImportReport {
+            dry_run,
+            ..Default::default()
+        };
+        let rows: Vec<std::result::Result<RawRow, String>> = match format {
+            ImportFormat::Csv => csv::Reader::from_reader(input)
+                .deserialize()
+                .map(|row| row.map_err(|e| e.to_string()))
+                .collect(),
+            ImportFormat::Json => match serde_json::from_reader::<_, Vec<serde_json::Value>>(input)
+            {
+                Ok(values) => values
+                    .into_iter()
+                    .map(|v| serde_json::from_value(v).map_err(|e| e.to_string()))
+                    .collect(),
+                Err(e) => {
+                    report.errors.push((0, e.to_string()));
+                    return Ok(report);
+                }
+            },
+        };
+        report.rows = rows.len();
+
+        let corners = self.corner_lookup()?;
+        let tree = self.tree(Tree::Revenues)?;
+        let post_datetime = chrono::Utc::now().timestamp() as u32;
+        let mut seen = HashSet::new();
+        let mut batch = sled::Batch::default();
+        for (i, row) in rows.into_iter().enumerate() {
+            let line = i + 1;
+            let rev = match row.and_then(|row| parse_row(row, &corners, post_datetime)) {
+                Ok(rev) => rev,
+                Err(e) => {
+                    report.errors.push((line, e));
+                    continue;
+                }
+            };
+            let key = rev.to_key();
+            if !seen.insert(key.clone()) {
+                report
+                    .errors
+                    .push((line, "duplicate corner and date in file".to_owned()));
+                continue;
+            }
+            if tree.contains_key(&key)? {
+                match on_conflict {
+                    OnConflict::Skip => {
+                        report.skipped += 1;
+                        continue;
+                    }
+                    OnConflict::Overwrite => report.overwritten += 1,
+                    OnConflict::Fail => {
+                        report.aborted = true;
+                        report
+                            .errors
+                            .push((line, "revenue for this date already exists".to_owned()));
+                        continue;
+                    }
+                }
+            } else {
+                report.inserted += 1;
+            }
+            batch.insert(key, rev.to_val()?);
+        }
+
+        if !dry_run && !report.aborted {
+            tree.apply_batch(batch)?;
+            tree.flush()?;
+            self.rebuild_stats()?;
+        }
+        Ok(report)
+    }
+
+    /// Точки по полному и короткому названию без учета регистра
+    fn corner_lookup(&self) -> Result<HashMap<String, u32>> {
+        let mut res = HashMap::new();
+        for corner in self.get_corners()? {
+            if let Some(short) = corner.short_name.as_ref() {
+                res.insert(short.trim().to_lowercase(), corner.id);
+            }
+            res.insert(corner.name.trim().to_lowercase(), corner.id);
+        }
+        Ok(res)
+    }
+}
+
+fn parse_row(
+    row: RawRow,
+    corners: &HashMap<String, u32>,
+    post_datetime: u32,
+) -> std::result::Result<Revenue, String> {
+    let corner_id = *corners
+        .get(&row.corner.trim().to_lowercase())
+        .ok_or_else(|| format!("unknown corner {:?}", row.corner))?;
+    let date = parse_day(row.date.trim())?;
+    let amount = parse_amount(&row.amount)?;
+    Ok(Revenue {
+        corner_id,
+        date,
+        amount,
+        post_datetime,
+        comment: row.comment.filter(|c| !c.trim().is_empty()),
+        payments: Vec::new(),
+        photo: None,
+        staff: None,
+    })
+}
+
+/// Номер дня по `ГГГГ-ММ-ДД` или `ДД.ММ.ГГГГ`
+fn parse_day(s: &str) -> std::result::Result<u32, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%d.%m.%Y"))
+        .ok()
+        .and_then(Revenue::day)
+        .ok_or_else(|| format!("bad date {:?}", s))
+}
+
+/// Рубли с копейками, допускаются пробелы между разрядами
+fn parse_amount(s: &str) -> std::result::Result<Money, String> {
+    if s.trim().starts_with('-') {
+        return Err(format!("negative amount {:?}", s));
+    }
+    Money::parse(s).ok_or_else(|| format!("bad amount {:?}", s))
+}
+
+/// В JSON сумма бывает и числом, и строкой
+fn amount_as_string<'de, D>(d: D) -> std::result::Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Amount {
+        Int(i64),
+        Float(f64),
+        Text(String),
+    }
+    Ok(match Amount::deserialize(d)? {
+        Amount::Int(n) => n.to_string(),
+        Amount::Float(n) => n.to_string(),
+        Amount::Text(s) => s,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::CornerInfo;
+
+    fn db_with_corner() -> DataBase {
+        let db = DataBase::temporary();
+        db.add_corner(CornerInfo {
+            name: "ТЦ Галерея".to_owned(),
+            short_name: Some("гал".to_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+        db
+    }
+
+    const CSV: &str = "corner,date,amount,comment
+ТЦ Галерея,2020-03-01,12 000,
+гал,02.03.2020,13000.50,дождь
+Вокзал,2020-03-03,1000,
+гал,2020-03-04,-5,
+гал,2020-03-01,1,";
+
+    #[test]
+    fn csv_dry_run() {
+        let db = db_with_corner();
+        let report = db
+            .import_revenue(CSV.as_bytes(), ImportFormat::Csv, OnConflict::Skip, true)
+            .unwrap();
+        assert_eq!(report.rows, 5);
+        assert_eq!(report.inserted, 2);
+        let lines: Vec<usize> = report.errors.iter().map(|e| e.0).collect();
+        assert_eq!(lines, vec![3, 4, 5]);
+        assert_eq!(db.tree(Tree::Revenues).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn json_conflicts() {
+        let db = db_with_corner();
+        let first = r#"[{"corner": "гал", "date": "2020-03-01", "amount": 100}]"#;
+        let report = db
+            .import_revenue(
+                first.as_bytes(),
+                ImportFormat::Json,
+                OnConflict::Fail,
+                false,
+            )
+            .unwrap();
+        assert_eq!(report.inserted, 1);
+
+        let second = r#"[
+            {"corner": "гал", "date": "2020-03-01", "amount": 200.5, "comment": "правка"},
+            {"corner": "гал", "date": "2020-03-02", "amount": 300}
+        ]"#;
+        let report = db
+            .import_revenue(
+                second.as_bytes(),
+                ImportFormat::Json,
+                OnConflict::Fail,
+                false,
+            )
+            .unwrap();
+        assert!(report.aborted);
+        assert_eq!(db.tree(Tree::Revenues).unwrap().len(), 1);
+
+        let report = db
+            .import_revenue(
+                second.as_bytes(),
+                ImportFormat::Json,
+                OnConflict::Skip,
+                false,
+            )
+            .unwrap();
+        assert_eq!((report.skipped, report.inserted), (1, 1));
+
+        let report = db
+            .import_revenue(
+                second.as_bytes(),
+                ImportFormat::Json,
+                OnConflict::Overwrite,
+                false,
+            )
+            .unwrap();
+        assert_eq!(report.overwritten, 2);
+        let day = Revenue::day(NaiveDate::from_ymd(2020, 3, 1)).unwrap();
+        let rev = db
+            .tree(Tree::Revenues)
+            .unwrap()
+            .get(Revenue::key(day, 1))
+            .unwrap()
+            .unwrap();
+        let rev = Revenue::from_val(rev).unwrap();
+        assert_eq!(rev.amount, Money::from_kopecks(20_050));
+        assert_eq!(rev.comment.as_deref(), Some("правка"));
+    }
+}
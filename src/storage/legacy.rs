@@ -0,0 +1,559 @@
+//! Прежние версии записей. Нужны только для `BinVals::upgrade`,
+//! поэтому поля здесь не меняются никогда.
+
+use super::{
+    Chat, Corner, Expense, PayScheme, Payment, Revenue, Role, Shift, Stats, Target, TargetPeriod,
+    ANOMALY_RATIO, TIMEZONE, UNSPECIFIED,
+};
+use crate::locale::Lang;
+use crate::money::Money;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// `Revenue` версий 0 и 1
+#[derive(Deserialize)]
+pub(super) struct RevenueV1 {
+    corner_id: u32,
+    date: u32,
+    amount: u32,
+    post_datetime: u32,
+}
+
+impl From<RevenueV1> for RevenueV2 {
+    fn from(old: RevenueV1) -> Self {
+        RevenueV2 {
+            corner_id: old.corner_id,
+            date: old.date,
+            amount: old.amount,
+            post_datetime: old.post_datetime,
+            comment: None,
+        }
+    }
+}
+
+/// `Revenue` версии 2
+#[derive(Deserialize)]
+pub(super) struct RevenueV2 {
+    corner_id: u32,
+    date: u32,
+    amount: u32,
+    post_datetime: u32,
+    comment: Option<String>,
+}
+
+impl From<RevenueV2> for RevenueV3 {
+    fn from(old: RevenueV2) -> Self {
+        RevenueV3 {
+            corner_id: old.corner_id,
+            date: old.date,
+            amount: old.amount,
+            post_datetime: old.post_datetime,
+            comment: old.comment,
+            payments: Vec::new(),
+        }
+    }
+}
+
+/// `Revenue` версии 3, без фото Z-отчета
+#[derive(Deserialize)]
+pub(super) struct RevenueV3 {
+    corner_id: u32,
+    date: u32,
+    amount: u32,
+    post_datetime: u32,
+    comment: Option<String>,
+    payments: Vec<PaymentV1>,
+}
+
+impl From<RevenueV3> for RevenueV4 {
+    fn from(old: RevenueV3) -> Self {
+        RevenueV4 {
+            corner_id: old.corner_id,
+            date: old.date,
+            amount: old.amount,
+            post_datetime: old.post_datetime,
+            comment: old.comment,
+            payments: old.payments,
+            photo: None,
+        }
+    }
+}
+
+/// `Revenue` версии 4, без сотрудника
+#[derive(Deserialize)]
+pub(super) struct RevenueV4 {
+    corner_id: u32,
+    date: u32,
+    amount: u32,
+    post_datetime: u32,
+    comment: Option<String>,
+    payments: Vec<PaymentV1>,
+    photo: Option<String>,
+}
+
+impl From<RevenueV4> for RevenueV5 {
+    fn from(old: RevenueV4) -> Self {
+        RevenueV5 {
+            corner_id: old.corner_id,
+            date: old.date,
+            amount: old.amount,
+            post_datetime: old.post_datetime,
+            comment: old.comment,
+            payments: old.payments,
+            photo: old.photo,
+            staff: None,
+        }
+    }
+}
+
+/// `Revenue` версии 5, суммы в целых рублях
+#[derive(Deserialize)]
+pub(super) struct RevenueV5 {
+    corner_id: u32,
+    date: u32,
+    amount: u32,
+    post_datetime: u32,
+    comment: Option<String>,
+    payments: Vec<PaymentV1>,
+    photo: Option<String>,
+    staff: Option<i64>,
+}
+
+impl From<RevenueV5> for Revenue {
+    fn from(old: RevenueV5) -> Self {
+        Revenue {
+            corner_id: old.corner_id,
+            date: old.date,
+            amount: Money::rubles(old.amount as i64),
+            post_datetime: old.post_datetime,
+            comment: old.comment,
+            payments: old.payments.into_iter().map(Into::into).collect(),
+            photo: old.photo,
+            staff: old.staff,
+        }
+    }
+}
+
+/// `Payment` в `Revenue` до версии 6, сумма в целых рублях
+#[derive(Deserialize)]
+pub(super) struct PaymentV1 {
+    method: u32,
+    amount: u32,
+}
+
+impl From<PaymentV1> for Payment {
+    fn from(old: PaymentV1) -> Self {
+        Payment::new(old.method, Money::rubles(old.amount as i64))
+    }
+}
+
+/// `Corner` версий 0 и 1
+#[derive(Deserialize)]
+pub(super) struct CornerV1 {
+    id: u32,
+    name: String,
+    tag: Option<String>,
+}
+
+impl From<CornerV1> for CornerV2 {
+    fn from(old: CornerV1) -> Self {
+        CornerV2 {
+            id: old.id,
+            name: old.name,
+            short_name: None,
+            tag: old.tag,
+        }
+    }
+}
+
+/// `Corner` версии 2
+#[derive(Deserialize)]
+pub(super) struct CornerV2 {
+    id: u32,
+    name: String,
+    short_name: Option<String>,
+    tag: Option<String>,
+}
+
+impl From<CornerV2> for CornerV3 {
+    fn from(old: CornerV2) -> Self {
+        CornerV3 {
+            id: old.id,
+            name: old.name,
+            short_name: old.short_name,
+            address: None,
+            hours: None,
+            archived: false,
+            tags: old
+                .tag
+                .map(|t| t.trim().to_lowercase())
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// `Corner` версии 3
+#[derive(Deserialize)]
+pub(super) struct CornerV3 {
+    id: u32,
+    name: String,
+    short_name: Option<String>,
+    address: Option<String>,
+    hours: Option<String>,
+    archived: bool,
+    tags: Vec<String>,
+}
+
+impl From<CornerV3> for CornerV4 {
+    fn from(old: CornerV3) -> Self {
+        CornerV4 {
+            id: old.id,
+            name: old.name,
+            short_name: old.short_name,
+            address: old.address,
+            hours: old.hours,
+            archived: old.archived,
+            tags: old.tags,
+            group_id: None,
+        }
+    }
+}
+
+/// `Corner` версии 4, без требования фото
+#[derive(Deserialize)]
+pub(super) struct CornerV4 {
+    id: u32,
+    name: String,
+    short_name: Option<String>,
+    address: Option<String>,
+    hours: Option<String>,
+    archived: bool,
+    tags: Vec<String>,
+    group_id: Option<u32>,
+}
+
+impl From<CornerV4> for CornerV5 {
+    fn from(old: CornerV4) -> Self {
+        CornerV5 {
+            id: old.id,
+            name: old.name,
+            short_name: old.short_name,
+            address: old.address,
+            hours: old.hours,
+            archived: old.archived,
+            tags: old.tags,
+            group_id: old.group_id,
+            photo_required: false,
+        }
+    }
+}
+
+/// `Corner` версии 5, без проверки выручки на выбросы
+#[derive(Deserialize)]
+pub(super) struct CornerV5 {
+    id: u32,
+    name: String,
+    short_name: Option<String>,
+    address: Option<String>,
+    hours: Option<String>,
+    archived: bool,
+    tags: Vec<String>,
+    group_id: Option<u32>,
+    photo_required: bool,
+}
+
+impl From<CornerV5> for CornerV6 {
+    fn from(old: CornerV5) -> Self {
+        CornerV6 {
+            id: old.id,
+            name: old.name,
+            short_name: old.short_name,
+            address: old.address,
+            hours: old.hours,
+            archived: old.archived,
+            tags: old.tags,
+            group_id: old.group_id,
+            photo_required: old.photo_required,
+            anomaly_ratio: ANOMALY_RATIO,
+        }
+    }
+}
+
+/// `Corner` версии 6, без часового пояса
+#[derive(Deserialize)]
+pub(super) struct CornerV6 {
+    id: u32,
+    name: String,
+    short_name: Option<String>,
+    address: Option<String>,
+    hours: Option<String>,
+    archived: bool,
+    tags: Vec<String>,
+    group_id: Option<u32>,
+    photo_required: bool,
+    anomaly_ratio: u32,
+}
+
+impl From<CornerV6> for Corner {
+    fn from(old: CornerV6) -> Self {
+        Corner {
+            id: old.id,
+            name: old.name,
+            short_name: old.short_name,
+            address: old.address,
+            hours: old.hours,
+            archived: old.archived,
+            tags: old.tags,
+            group_id: old.group_id,
+            photo_required: old.photo_required,
+            anomaly_ratio: old.anomaly_ratio,
+            timezone: TIMEZONE.name().to_owned(),
+        }
+    }
+}
+
+/// `Chat` версий 0 и 1
+#[derive(Deserialize)]
+pub(super) struct ChatV1 {
+    corner_id: u32,
+    name: String,
+    is_active: bool,
+}
+
+impl From<ChatV1> for ChatV2 {
+    fn from(old: ChatV1) -> Self {
+        ChatV2 {
+            corner_id: old.corner_id,
+            name: old.name,
+            is_active: old.is_active,
+            role: Role::Staff,
+        }
+    }
+}
+
+/// `Chat` версии 2, без схемы оплаты
+#[derive(Deserialize)]
+pub(super) struct ChatV2 {
+    corner_id: u32,
+    name: String,
+    is_active: bool,
+    role: Role,
+}
+
+impl From<ChatV2> for ChatV3 {
+    fn from(old: ChatV2) -> Self {
+        ChatV3 {
+            corner_id: old.corner_id,
+            name: old.name,
+            is_active: old.is_active,
+            role: old.role,
+            pay: None,
+        }
+    }
+}
+
+/// `Chat` версии 3, без выбранного языка
+#[derive(Deserialize)]
+pub(super) struct ChatV3 {
+    corner_id: u32,
+    name: String,
+    is_active: bool,
+    role: Role,
+    pay: Option<PaySchemeV1>,
+}
+
+impl From<ChatV3> for ChatV4 {
+    fn from(old: ChatV3) -> Self {
+        ChatV4 {
+            corner_id: old.corner_id,
+            name: old.name,
+            is_active: old.is_active,
+            role: old.role,
+            pay: old.pay,
+            lang: None,
+        }
+    }
+}
+
+/// `Chat` версии 4, схема оплаты в целых рублях
+#[derive(Deserialize)]
+pub(super) struct ChatV4 {
+    corner_id: u32,
+    name: String,
+    is_active: bool,
+    role: Role,
+    pay: Option<PaySchemeV1>,
+    lang: Option<Lang>,
+}
+
+impl From<ChatV4> for Chat {
+    fn from(old: ChatV4) -> Self {
+        Chat {
+            corner_id: old.corner_id,
+            name: old.name,
+            is_active: old.is_active,
+            role: old.role,
+            pay: old.pay.map(Into::into),
+            lang: old.lang,
+        }
+    }
+}
+
+/// `PayScheme` в `Chat` версий 3 и 4, суммы в целых рублях
+#[derive(Deserialize)]
+pub(super) struct PaySchemeV1 {
+    per_shift: u32,
+    percent: u32,
+    threshold: u32,
+}
+
+impl From<PaySchemeV1> for PayScheme {
+    fn from(old: PaySchemeV1) -> Self {
+        PayScheme {
+            per_shift: Money::rubles(old.per_shift as i64),
+            percent: old.percent,
+            threshold: Money::rubles(old.threshold as i64),
+        }
+    }
+}
+
+/// `Stats` версий 0 и 1, до разбивки по способам оплаты
+#[derive(Deserialize)]
+pub(super) struct StatsV1 {
+    revenue: u64,
+    days: u32,
+}
+
+impl From<StatsV1> for StatsV2 {
+    fn from(old: StatsV1) -> Self {
+        let by_method = Some((UNSPECIFIED, old.revenue))
+            .filter(|(_, sum)| *sum > 0)
+            .into_iter()
+            .collect();
+        StatsV2 {
+            revenue: old.revenue,
+            days: old.days,
+            by_method,
+        }
+    }
+}
+
+/// `Stats` версии 2, без расходов
+#[derive(Deserialize)]
+pub(super) struct StatsV2 {
+    revenue: u64,
+    days: u32,
+    by_method: BTreeMap<u32, u64>,
+}
+
+impl From<StatsV2> for StatsV3 {
+    fn from(old: StatsV2) -> Self {
+        StatsV3 {
+            revenue: old.revenue,
+            days: old.days,
+            by_method: old.by_method,
+            expenses: 0,
+        }
+    }
+}
+
+/// `Stats` версии 3, суммы в целых рублях
+#[derive(Deserialize)]
+pub(super) struct StatsV3 {
+    revenue: u64,
+    days: u32,
+    by_method: BTreeMap<u32, u64>,
+    expenses: u64,
+}
+
+impl From<StatsV3> for Stats {
+    fn from(old: StatsV3) -> Self {
+        let rubles = |sum: u64| Money::rubles(sum as i64);
+        Stats {
+            revenue: rubles(old.revenue),
+            days: old.days,
+            by_method: old
+                .by_method
+                .into_iter()
+                .map(|(method, sum)| (method, rubles(sum)))
+                .collect(),
+            expenses: rubles(old.expenses),
+        }
+    }
+}
+
+/// `Expense` версий 0 и 1, сумма в целых рублях
+#[derive(Deserialize)]
+pub(super) struct ExpenseV1 {
+    corner_id: u32,
+    date: u32,
+    id: u64,
+    category: String,
+    amount: u32,
+    comment: Option<String>,
+    photo: Option<String>,
+    post_datetime: u32,
+}
+
+impl From<ExpenseV1> for Expense {
+    fn from(old: ExpenseV1) -> Self {
+        Expense {
+            corner_id: old.corner_id,
+            date: old.date,
+            id: old.id,
+            category: old.category,
+            amount: Money::rubles(old.amount as i64),
+            comment: old.comment,
+            photo: old.photo,
+            post_datetime: old.post_datetime,
+        }
+    }
+}
+
+/// `Shift` версий 0 и 1, наличные в целых рублях
+#[derive(Deserialize)]
+pub(super) struct ShiftV1 {
+    corner_id: u32,
+    chat_id: i64,
+    opened: u32,
+    closed: Option<u32>,
+    open_cash: u32,
+    close_cash: Option<u32>,
+}
+
+impl From<ShiftV1> for Shift {
+    fn from(old: ShiftV1) -> Self {
+        let rubles = |cash: u32| Money::rubles(cash as i64);
+        Shift {
+            corner_id: old.corner_id,
+            chat_id: old.chat_id,
+            opened: old.opened,
+            closed: old.closed,
+            open_cash: rubles(old.open_cash),
+            close_cash: old.close_cash.map(rubles),
+        }
+    }
+}
+
+/// `Target` версий 0 и 1, план в целых рублях
+#[derive(Deserialize)]
+pub(super) struct TargetV1 {
+    corner_id: u32,
+    from: u32,
+    period: TargetPeriod,
+    amount: u32,
+    set_at: u32,
+}
+
+impl From<TargetV1> for Target {
+    fn from(old: TargetV1) -> Self {
+        Target {
+            corner_id: old.corner_id,
+            from: old.from,
+            period: old.period,
+            amount: Money::rubles(old.amount as i64),
+            set_at: old.set_at,
+        }
+    }
+}
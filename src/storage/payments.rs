@@ -0,0 +1,141 @@
+//! Настраиваемый список способов оплаты
+
+use super::{BinVals, DataBase, Error, Payment, PaymentMethod, Result, Tree, UNSPECIFIED};
+use crate::locale::Lang;
+use crate::money::Money;
+use std::collections::HashMap;
+
+/// Способы, которые появляются в новой базе
+const DEFAULT_METHODS: [(&str, &str); 3] = [
+    ("Наличные", "нал"),
+    ("Карта", "карта"),
+    ("QR/перевод", "qr"),
+];
+
+impl DataBase {
+    pub fn payment_methods(&self) -> Result<Vec<PaymentMethod>> {
+        self.tree(Tree::PaymentMethods)?
+            .iter()
+            .values()
+            .map(|val| PaymentMethod::from_val(val?))
+            .collect()
+    }
+
+    pub fn add_payment_method(&self, name: &str, code: &str) -> Result<PaymentMethod> {
+        let (name, code) = (name.trim(), code.trim().to_lowercase());
+        if name.is_empty() || code.is_empty() || code.contains(char::is_whitespace) {
+            return Err(Error::Invalid(format!(
+                "payment method {:?} {:?}",
+                name, code
+            )));
+        }
+        if self.find_payment_method(&code)?.is_some() || self.find_payment_method(name)?.is_some() {
+            return Err(Error::Duplicate(format!("payment method {:?}", code)));
+        }
+        let method = PaymentMethod {
+            id: self.next_id(Tree::PaymentMethods)?,
+            name: name.to_owned(),
+            code,
+            archived: false,
+        };
+        self.tree(Tree::PaymentMethods)?
+            .insert(method.id.to_be_bytes(), method.to_val()?)?;
+        Ok(method)
+    }
+
+    /// Выключает или снова включает способ оплаты
+    pub fn set_payment_method_archived(&self, code: &str, archived: bool) -> Result<PaymentMethod> {
+        let mut method = self.find_payment_method(code)?.ok_or(Error::NotFound)?;
+        method.archived = archived;
+        self.tree(Tree::PaymentMethods)?
+            .insert(method.id.to_be_bytes(), method.to_val()?)?;
+        Ok(method)
+    }
+
+    /// Способ по коду или названию без учета регистра
+    pub fn find_payment_method(&self, word: &str) -> Result<Option<PaymentMethod>> {
+        let word = word.trim().to_lowercase();
+        Ok(self
+            .payment_methods()?
+            .into_iter()
+            .find(|m| m.code == word || m.name.to_lowercase() == word))
+    }
+
+    /// Названия всех способов, включая "не указано", для отчетов
+    pub fn payment_method_names(&self) -> Result<HashMap<u32, String>> {
+        let mut names: HashMap<u32, String> = self
+            .payment_methods()?
+            .into_iter()
+            .map(|m| (m.id, m.name))
+            .collect();
+        names.insert(UNSPECIFIED, "не указано".to_owned());
+        Ok(names)
+    }
+
+    pub(super) fn default_payment_methods(&self) -> Result<()> {
+        if self.tree(Tree::PaymentMethods)?.is_empty() {
+            for (name, code) in DEFAULT_METHODS.iter() {
+                self.add_payment_method(name, code)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// "Наличные 5000 ₽, карта 7000 ₽" для отчетов
+pub fn format_breakdown<I>(names: &HashMap<u32, String>, lang: Lang, payments: I) -> String
+where
+    I: IntoIterator<Item = (u32, Money)>,
+{
+    payments
+        .into_iter()
+        .map(|(method, amount)| {
+            let name = names.get(&method).map(String::as_str).unwrap_or("?");
+            format!("{} {}", name, lang.money(amount))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Payment {
+    pub fn new(method: u32, amount: Money) -> Self {
+        Payment { method, amount }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn methods_config() {
+        let db = DataBase::temporary();
+        assert_eq!(db.payment_methods().unwrap().len(), 3);
+        assert_eq!(db.find_payment_method("НАЛ").unwrap().unwrap().id, 1);
+        assert_eq!(db.find_payment_method("карта").unwrap().unwrap().id, 2);
+
+        let sbp = db.add_payment_method("СБП", "сбп").unwrap();
+        match db.add_payment_method("Наличка", "наличные") {
+            Err(Error::Duplicate(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        assert!(
+            db.set_payment_method_archived("сбп", true)
+                .unwrap()
+                .archived
+        );
+
+        let names = db.payment_method_names().unwrap();
+        assert_eq!(
+            format_breakdown(
+                &names,
+                Lang::Ru,
+                vec![
+                    (UNSPECIFIED, Money::rubles(5)),
+                    (sbp.id, Money::from_kopecks(1050))
+                ]
+            ),
+            "не указано 5 ₽, СБП 10,50 ₽"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Зарплата сотрудников за любой период по их сменам и выручке.
+//! Процент считается с выручки каждого дня отдельно.
+
+use super::export::csv_error;
+use super::{BinVals, Chat, DataBase, Error, PayScheme, Result, Revenue, Tree};
+use crate::money::Money;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Начисление сотруднику за период
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PayLine {
+    pub chat_id: i64,
+    pub name: String,
+    pub shifts: u32,
+    /// Дней с выручкой, записанной на сотрудника
+    pub days: u32,
+    pub revenue: Money,
+    /// Ставка за смены
+    pub fixed: Money,
+    /// Процент с выручки
+    pub bonus: Money,
+}
+
+impl PayLine {
+    pub fn total(&self) -> Money {
+        self.fixed + self.bonus
+    }
+}
+
+impl PayScheme {
+    /// Процент с выручки одного дня сверх порога, доли копеек отбрасываются
+    pub fn bonus(&self, amount: Money) -> Money {
+        let above = amount - self.threshold;
+        above.max(Money::ZERO).percent(self.percent)
+    }
+}
+
+impl DataBase {
+    pub fn set_pay_scheme(&self, chat_id: i64, pay: Option<PayScheme>) -> Result<Chat> {
+        let mut chat = self.get_chat(chat_id)?.ok_or(Error::NotFound)?;
+        chat.pay = pay;
+        self.put_chat(chat_id, &chat)?;
+        Ok(chat)
+    }
+
+    /// Начисления всем, у кого есть схема оплаты, за дни с `from` по `to`
+    pub fn payroll(&self, from: u32, to: u32) -> Result<Vec<PayLine>> {
+        let mut lines: HashMap<i64, (PayScheme, PayLine)> = HashMap::new();
+        for (chat_id, chat) in self.get_chats()? {
+            if let Some(pay) = chat.pay {
+                let line = PayLine {
+                    chat_id,
+                    name: chat.name,
+                    ..Default::default()
+                };
+                lines.insert(chat_id, (pay, line));
+            }
+        }
+        let range = Revenue::key(from, 0)..=Revenue::key(to, u32::MAX);
+        for val in self.tree(Tree::Revenues)?.range(range).values() {
+            let rev = Revenue::from_val(val?)?;
+            if let Some((pay, line)) = rev.staff.and_then(|id| lines.get_mut(&id)) {
+                line.days += 1;
+                line.revenue += rev.amount;
+                line.bonus += pay.bonus(rev.amount);
+            }
+        }
+        for (pay, line) in lines.values_mut() {
+            for shift in self.shifts(line.chat_id)? {
+                if (from..=to).contains(&self.shift_day(&shift)?) {
+                    line.shifts += 1;
+                }
+            }
+            line.fixed = pay
+                .per_shift
+                .checked_mul(line.shifts as i64)
+                .expect("money overflow");
+        }
+        let mut lines: Vec<PayLine> = lines.into_iter().map(|(_, (_, line))| line).collect();
+        lines.sort_by(|a, b| a.name.cmp(&b.name).then(a.chat_id.cmp(&b.chat_id)));
+        Ok(lines)
+    }
+
+    /// Ведомость за период в CSV. Возвращает число сотрудников.
+    pub fn export_payroll<W: Write>(&self, from: u32, to: u32, out: W) -> Result<usize> {
+        let lines = self.payroll(from, to)?;
+        let mut csv = csv::Writer::from_writer(out);
+        csv.write_record([
+            "name", "chat_id", "shifts", "fixed", "days", "revenue", "bonus", "total",
+        ])
+        .map_err(csv_error)?;
+        for line in &lines {
+            csv.write_record([
+                line.name.clone(),
+                line.chat_id.to_string(),
+                line.shifts.to_string(),
+                line.fixed.plain(),
+                line.days.to_string(),
+                line.revenue.plain(),
+                line.bonus.plain(),
+                line.total().plain(),
+            ])
+            .map_err(csv_error)?;
+        }
+        csv.flush()?;
+        Ok(lines.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Role;
+
+    #[test]
+    fn payroll_csv() {
+        let db = DataBase::temporary();
+        let chat = |name: &str| Chat {
+            corner_id: 1,
+            name: name.to_owned(),
+            is_active: true,
+            role: Role::Staff,
+            pay: None,
+            lang: None,
+        };
+        db.put_chat(2, &chat("Анна")).unwrap();
+        db.put_chat(3, &chat("Олег")).unwrap();
+        db.put_chat(4, &chat("Без схемы")).unwrap();
+        let mixed = PayScheme {
+            per_shift: Money::rubles(1_000),
+            percent: 250,
+            threshold: Money::rubles(10_000),
+        };
+        db.set_pay_scheme(2, Some(mixed)).unwrap();
+        db.set_pay_scheme(
+            3,
+            Some(PayScheme {
+                percent: 500,
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+
+        let now = 1_585_000_000;
+        db.open_shift(2, 1, Money::ZERO, now).unwrap();
+        db.close_shift(2, Money::ZERO, now + 3_600).unwrap();
+        db.open_shift(2, 1, Money::ZERO, now + 86_400).unwrap();
+        let day = db.shift_day(&db.shifts(2).unwrap()[0]).unwrap();
+        let rev = |date, kopecks, staff| Revenue {
+            corner_id: 1,
+            date,
+            amount: Money::from_kopecks(kopecks),
+            post_datetime: 0,
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff: Some(staff),
+        };
+        db.put_revenue(&rev(day, 1_400_050, 2)).unwrap();
+        db.put_revenue(&rev(day + 1, 900_000, 2)).unwrap();
+        db.put_revenue(&rev(day + 2, 300_010, 3)).unwrap();
+        db.put_revenue(&rev(day + 3, 300_000, 4)).unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(db.export_payroll(day, day + 2, &mut out).unwrap(), 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "name,chat_id,shifts,fixed,days,revenue,bonus,total
+Анна,2,2,2000,2,23000.50,100.01,2100.01
+Олег,3,0,0,1,3000.10,150,150
+"
+        );
+        let lines = db.payroll(day + 1, day + 1).unwrap();
+        assert_eq!(
+            (lines[0].shifts, lines[0].total()),
+            (1, Money::rubles(1_000))
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! Фото Z-отчета к дневной выручке. В базе хранится только `file_id`
+//! из Telegram, сам снимок при необходимости скачивается в `BLOB_DIR`.
+
+use super::{abort, BinVals, Corner, DataBase, Error, Result, Revenue, Tree};
+
+impl DataBase {
+    /// Прикрепляет фото к уже внесенной выручке. Сводки не меняются.
+    pub fn set_revenue_photo(&self, day: u32, corner_id: u32, file_id: &str) -> Result<Revenue> {
+        let key = Revenue::key(day, corner_id);
+        let rev = self.tree(Tree::Revenues)?.transaction(|revenues| {
+            let mut rev = match revenues.get(&key)? {
+                Some(val) => Revenue::from_val(val).or_else(abort)?,
+                None => return abort(Error::NotFound),
+            };
+            rev.photo = Some(file_id.to_owned());
+            revenues.insert(&key, rev.to_val().or_else(abort)?)?;
+            Ok(rev)
+        })?;
+        Ok(rev)
+    }
+
+    /// Требовать ли у точки фото Z-отчета вместе с выручкой
+    pub fn set_photo_required(&self, corner_id: u32, required: bool) -> Result<Corner> {
+        self.modify_corner(corner_id, |c| {
+            c.photo_required = required;
+            Ok(())
+        })
+    }
+}
+
+impl Revenue {
+    /// Выручка без фото у точки, где оно обязательно
+    pub fn missing_photo(&self, corner: &Corner) -> bool {
+        corner.photo_required && self.photo.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::Money;
+    use crate::storage::CornerInfo;
+
+    #[test]
+    fn attach_photo() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap();
+        let corner = db.set_photo_required(corner.id, true).unwrap();
+        match db.set_revenue_photo(10, corner.id, "AgAD") {
+            Err(Error::NotFound) => {}
+            res => panic!("{:?}", res),
+        }
+
+        let rev = Revenue {
+            corner_id: corner.id,
+            date: 10,
+            amount: Money::rubles(500),
+            post_datetime: 0,
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff: None,
+        };
+        db.put_revenue(&rev).unwrap();
+        assert!(rev.missing_photo(&corner));
+        let rev = db.set_revenue_photo(10, corner.id, "AgAD").unwrap();
+        assert!(!rev.missing_photo(&corner));
+        assert_eq!(db.get_revenue(10, corner.id).unwrap(), Some(rev));
+        assert!(db.get_corner(corner.id).unwrap().unwrap().photo_required);
+    }
+}
@@ -0,0 +1,38 @@
+//! Случайные ключи, которые создаются при первом обращении и дальше
+//! хранятся в базе, например для подписи данных кнопок
+
+use super::{DataBase, Result, Tree};
+use rand::RngCore;
+
+const KEY_LEN: usize = 32;
+
+impl DataBase {
+    /// Ключ с именем `name`. Если его еще нет, создается случайный.
+    pub fn secret(&self, name: &str) -> Result<Vec<u8>> {
+        let tree = self.tree(Tree::Secrets)?;
+        if let Some(key) = tree.get(name)? {
+            return Ok(key.to_vec());
+        }
+        let mut key = vec![0u8; KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        // если ключ успели создать параллельно, берется тот
+        match tree.compare_and_swap(name, None as Option<&[u8]>, Some(key.as_slice()))? {
+            Ok(()) => Ok(key),
+            Err(e) => Ok(e.current.map(|k| k.to_vec()).unwrap_or(key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_secret() {
+        let db = DataBase::temporary();
+        let key = db.secret("callback").unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        assert_eq!(db.secret("callback").unwrap(), key);
+        assert_ne!(db.secret("other").unwrap(), key);
+    }
+}
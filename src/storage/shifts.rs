@@ -0,0 +1,204 @@
+//! Смены сотрудников. Ключ: id чата и время открытия, big-endian,
+//! поэтому последняя запись чата - его текущая или прошлая смена.
+
+use super::{BinVals, DataBase, Error, Result, Revenue, Shift, Tree};
+use crate::dates;
+use crate::money::Money;
+use chrono_tz::Tz;
+use std::collections::{HashMap, HashSet};
+
+/// Итоги сотрудника за период
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct StaffTotal {
+    pub revenue: Money,
+    /// Дней, за которые внесена выручка
+    pub days: u32,
+    pub shifts: u32,
+    /// Отработано по закрытым сменам
+    pub seconds: u64,
+}
+
+impl Shift {
+    fn key(chat_id: i64, opened: u32) -> [u8; 12] {
+        let mut key = [0u8; 12];
+        key[..8].copy_from_slice(&chat_id.to_be_bytes());
+        key[8..].copy_from_slice(&opened.to_be_bytes());
+        key
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.closed.is_none()
+    }
+
+    /// День открытия в поясе `tz` в номерах `Revenue::date`
+    pub fn day(&self, tz: Tz) -> Result<u32> {
+        Revenue::day_of(dates::date_at(tz, self.opened as i64))
+    }
+}
+
+impl DataBase {
+    /// День открытия смены в поясе ее точки
+    pub fn shift_day(&self, shift: &Shift) -> Result<u32> {
+        shift.day(self.corner_tz(shift.corner_id)?)
+    }
+
+    /// Открывает смену, если у сотрудника нет открытой
+    pub fn open_shift(&self, chat_id: i64, corner_id: u32, cash: Money, now: u32) -> Result<Shift> {
+        if let Some(shift) = self.current_shift(chat_id)? {
+            return Err(Error::Duplicate(format!(
+                "shift opened at {}",
+                shift.opened
+            )));
+        }
+        let shift = Shift {
+            corner_id,
+            chat_id,
+            opened: now,
+            closed: None,
+            open_cash: cash,
+            close_cash: None,
+        };
+        self.tree(Tree::Shifts)?
+            .insert(Shift::key(chat_id, now), shift.to_val()?)?;
+        Ok(shift)
+    }
+
+    pub fn close_shift(&self, chat_id: i64, cash: Money, now: u32) -> Result<Shift> {
+        let mut shift = self.current_shift(chat_id)?.ok_or(Error::NotFound)?;
+        shift.closed = Some(now.max(shift.opened));
+        shift.close_cash = Some(cash);
+        self.tree(Tree::Shifts)?
+            .insert(Shift::key(chat_id, shift.opened), shift.to_val()?)?;
+        Ok(shift)
+    }
+
+    /// Открытая смена сотрудника
+    pub fn current_shift(&self, chat_id: i64) -> Result<Option<Shift>> {
+        match self
+            .tree(Tree::Shifts)?
+            .scan_prefix(chat_id.to_be_bytes())
+            .values()
+            .next_back()
+        {
+            Some(val) => Ok(Some(Shift::from_val(val?)?).filter(Shift::is_open)),
+            None => Ok(None),
+        }
+    }
+
+    /// Все смены сотрудника по порядку
+    pub fn shifts(&self, chat_id: i64) -> Result<Vec<Shift>> {
+        self.tree(Tree::Shifts)?
+            .scan_prefix(chat_id.to_be_bytes())
+            .values()
+            .map(|val| Shift::from_val(val?))
+            .collect()
+    }
+
+    /// Кто сейчас на смене на точке, раньше открывшие первыми
+    pub fn on_shift(&self, corner_id: u32) -> Result<Vec<Shift>> {
+        let mut res = Vec::new();
+        for (chat_id, _) in self.get_chats()? {
+            if let Some(shift) = self.current_shift(chat_id)? {
+                if shift.corner_id == corner_id {
+                    res.push(shift);
+                }
+            }
+        }
+        res.sort_by_key(|s| s.opened);
+        Ok(res)
+    }
+
+    /// Выручка и смены по сотрудникам за дни с `from` по `to` на точках `corners`.
+    /// Выручка без сотрудника собирается под `None`.
+    pub fn staff_totals(
+        &self,
+        corners: &HashSet<u32>,
+        from: u32,
+        to: u32,
+    ) -> Result<HashMap<Option<i64>, StaffTotal>> {
+        let mut totals: HashMap<Option<i64>, StaffTotal> = HashMap::new();
+        let range = Revenue::key(from, 0)..=Revenue::key(to, u32::MAX);
+        for val in self.tree(Tree::Revenues)?.range(range).values() {
+            let rev = Revenue::from_val(val?)?;
+            if corners.contains(&rev.corner_id) {
+                let total = totals.entry(rev.staff).or_default();
+                total.revenue += rev.amount;
+                total.days += 1;
+            }
+        }
+        for val in self.tree(Tree::Shifts)?.iter().values() {
+            let shift = Shift::from_val(val?)?;
+            let day = self.shift_day(&shift)?;
+            if corners.contains(&shift.corner_id) && from <= day && day <= to {
+                let total = totals.entry(Some(shift.chat_id)).or_default();
+                total.shifts += 1;
+                if let Some(closed) = shift.closed {
+                    total.seconds += closed.saturating_sub(shift.opened) as u64;
+                }
+            }
+        }
+        Ok(totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rev(corner_id: u32, date: u32, amount: i64, staff: Option<i64>) -> Revenue {
+        Revenue {
+            corner_id,
+            date,
+            amount: Money::rubles(amount),
+            post_datetime: 0,
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff,
+        }
+    }
+
+    #[test]
+    fn shifts_and_totals() {
+        let db = DataBase::temporary();
+        let now = 1_585_000_000;
+        db.open_shift(7, 1, Money::rubles(2_000), now).unwrap();
+        match db.open_shift(7, 1, Money::rubles(2_000), now + 60) {
+            Err(Error::Duplicate(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        assert_eq!(
+            db.current_shift(7).unwrap().unwrap().open_cash,
+            Money::rubles(2_000)
+        );
+        let shift = db
+            .close_shift(7, Money::rubles(5_500), now + 3_600)
+            .unwrap();
+        assert_eq!(shift.close_cash, Some(Money::rubles(5_500)));
+        assert_eq!(db.current_shift(7).unwrap(), None);
+        match db.close_shift(7, Money::ZERO, now + 7_200) {
+            Err(Error::NotFound) => {}
+            res => panic!("{:?}", res),
+        }
+        db.open_shift(7, 2, Money::ZERO, now + 86_400).unwrap();
+        assert_eq!(db.shifts(7).unwrap().len(), 2);
+        assert_eq!(db.on_shift(1).unwrap(), Vec::new());
+
+        let day = db.shift_day(&shift).unwrap();
+        db.put_revenue(&rev(1, day, 1_000, Some(7))).unwrap();
+        db.put_revenue(&rev(1, day + 1, 300, None)).unwrap();
+        db.put_revenue(&rev(2, day + 1, 500, Some(7))).unwrap();
+        let corners: HashSet<u32> = vec![1].into_iter().collect();
+        let totals = db.staff_totals(&corners, day, day + 1).unwrap();
+        assert_eq!(
+            totals[&Some(7)],
+            StaffTotal {
+                revenue: Money::rubles(1_000),
+                days: 1,
+                shifts: 1,
+                seconds: 3_600
+            }
+        );
+        assert_eq!(totals[&None].revenue, Money::rubles(300));
+    }
+}
@@ -0,0 +1,396 @@
+//! Месячные сводки в `Tree::Stats`. Выручка точки сразу добавляется
+//! в сводку самой точки и всех групп над ней, так что отчет по любому
+//! уровню иерархии читает одну запись.
+//!
+//! Ключ: вид узла (0 - точка, 1 - группа), id и номер месяца, все big-endian.
+
+use super::{
+    abort, BinVals, Corner, DataBase, Error, Expense, Group, Result, Revenue, Stats, Tree,
+};
+use crate::money::Money;
+use chrono::{Datelike, NaiveDate};
+use sled::transaction::{ConflictableTransactionResult, TransactionalTree};
+use sled::Transactional;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Node {
+    Corner(u32),
+    Group(u32),
+}
+
+impl Node {
+    pub(super) fn key(self, month: u32) -> [u8; 9] {
+        let (kind, id) = match self {
+            Node::Corner(id) => (0, id),
+            Node::Group(id) => (1, id),
+        };
+        let mut key = [kind; 9];
+        key[1..5].copy_from_slice(&id.to_be_bytes());
+        key[5..9].copy_from_slice(&month.to_be_bytes());
+        key
+    }
+}
+
+impl Stats {
+    /// Номер месяца, в который попадает день `Revenue::date`
+    pub fn month(day: u32) -> Result<u32> {
+        let date = Revenue::date_of(day)?;
+        Ok(date.year() as u32 * 12 + date.month0())
+    }
+
+    /// Первый день месяца по его номеру
+    pub fn month_start(month: u32) -> Result<NaiveDate> {
+        NaiveDate::from_ymd_opt((month / 12) as i32, month % 12 + 1, 1)
+            .filter(|date| date.year() > 0)
+            .ok_or_else(|| Error::out_of_range(format!("month {}", month)))
+    }
+
+    /// Номер первого дня месяца, как `Revenue::date`
+    pub fn first_day(month: u32) -> Result<u32> {
+        Revenue::day_of(Stats::month_start(month)?)
+    }
+
+    /// Прибавляет (`sign` = 1) или вычитает (-1) выручку вместе с разбивкой
+    fn add(&mut self, rev: &Revenue, sign: i64) -> Result<()> {
+        add_sum(&mut self.revenue, rev.amount, sign)?;
+        for p in rev.breakdown() {
+            let sum = self.by_method.entry(p.method).or_default();
+            add_sum(sum, p.amount, sign)?;
+            if *sum == Money::ZERO {
+                self.by_method.remove(&p.method);
+            }
+        }
+        Ok(())
+    }
+
+    /// Прибавляет (`sign` = 1) или вычитает (-1) сводку другого узла
+    pub(super) fn add_stats(&mut self, other: &Stats, sign: i64) -> Result<()> {
+        add_sum(&mut self.revenue, other.revenue, sign)?;
+        add_sum(&mut self.expenses, other.expenses, sign)?;
+        self.days = if sign < 0 {
+            self.days.checked_sub(other.days)
+        } else {
+            self.days.checked_add(other.days)
+        }
+        .ok_or_else(|| Error::Corrupt(format!("stats days {}", self.days)))?;
+        for (&method, &amount) in &other.by_method {
+            let sum = self.by_method.entry(method).or_default();
+            add_sum(sum, amount, sign)?;
+            if *sum == Money::ZERO {
+                self.by_method.remove(&method);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Прибавляет (`sign` = 1) или вычитает (-1) сумму в сводке. Переполнение -
+/// слишком большая сумма на входе, а уход в минус значит, что сводка
+/// разошлась с записями.
+pub(super) fn add_sum(sum: &mut Money, amount: Money, sign: i64) -> Result<()> {
+    let res = if sign < 0 {
+        sum.checked_sub(amount)
+    } else {
+        sum.checked_add(amount)
+    };
+    *sum = match res {
+        None => {
+            return Err(Error::Invalid(format!(
+                "sum overflow: {} + {}",
+                sum, amount
+            )))
+        }
+        Some(res) if res < Money::ZERO => {
+            return Err(Error::Corrupt(format!("negative stats sum {}", res)))
+        }
+        Some(res) => res,
+    };
+    Ok(())
+}
+
+impl DataBase {
+    /// Записывает выручку за день и обновляет сводки точки и ее групп.
+    /// Возвращает прежнюю запись за этот день, если она была.
+    pub fn put_revenue(&self, rev: &Revenue) -> Result<Option<Revenue>> {
+        rev.check()?;
+        let month = Stats::month(rev.date)?;
+        let val = rev.to_val()?;
+
+        let revenues = self.tree(Tree::Revenues)?;
+        let stats = self.tree(Tree::Stats)?;
+        let corners = self.tree(Tree::Corners)?;
+        let groups = self.tree(Tree::Groups)?;
+        let trees = (&revenues, &stats, &corners, &groups);
+        let old = trees.transaction(|(revenues, stats, corners, groups)| {
+            let nodes = corner_nodes(corners, groups, rev.corner_id)?;
+            let old = match revenues.insert(rev.to_key(), val.clone())? {
+                Some(old) => Some(Revenue::from_val(old).or_else(abort)?),
+                None => None,
+            };
+            update_stats(stats, &nodes, month, |s| {
+                match &old {
+                    Some(old) => s.add(old, -1)?,
+                    None => s.days += 1,
+                }
+                s.add(rev, 1)
+            })?;
+            Ok(old)
+        })?;
+        Ok(old)
+    }
+
+    /// Сводка узла за месяц, пустая если выручки не было
+    pub fn stats(&self, node: Node, month: u32) -> Result<Stats> {
+        Ok(self
+            .get(Tree::Stats, node.key(month).as_ref().into())?
+            .unwrap_or_default())
+    }
+
+    /// Сводки узла по месяцам с `from` по `to` включительно, без пустых месяцев
+    pub fn stats_range(&self, node: Node, from: u32, to: u32) -> Result<Vec<(u32, Stats)>> {
+        self.tree(Tree::Stats)?
+            .range(node.key(from)..=node.key(to))
+            .map(|kv| {
+                let (key, val) = kv?;
+                Ok((super::id_from_key(&key[5..])?, Stats::from_val(val)?))
+            })
+            .collect()
+    }
+
+    /// Месяцы, за которые у точки есть сводка
+    pub(super) fn stats_months(&self, corner_id: u32) -> Result<Vec<u32>> {
+        Ok(self
+            .stats_range(Node::Corner(corner_id), 0, u32::MAX)?
+            .into_iter()
+            .map(|(month, _)| month)
+            .collect())
+    }
+
+    /// Пересчитывает все сводки по выручке и расходам. Нужно после переноса точек
+    /// между группами и массовой загрузки.
+    pub fn rebuild_stats(&self) -> Result<usize> {
+        let mut paths = HashMap::new();
+        for corner in self.get_corners()? {
+            paths.insert(corner.id, self.group_path(corner.group_id)?);
+        }
+        let mut sums: HashMap<[u8; 9], Stats> = HashMap::new();
+        let keys = |corner_id: u32, day: u32| -> Result<Vec<[u8; 9]>> {
+            let groups = paths.get(&corner_id).map(Vec::as_slice).unwrap_or(&[]);
+            let month = Stats::month(day)?;
+            Ok(std::iter::once(Node::Corner(corner_id))
+                .chain(groups.iter().copied().map(Node::Group))
+                .map(|node| node.key(month))
+                .collect())
+        };
+        for val in self.tree(Tree::Revenues)?.iter().values() {
+            let rev = Revenue::from_val(val?)?;
+            for key in keys(rev.corner_id, rev.date)? {
+                let s = sums.entry(key).or_default();
+                s.add(&rev, 1)?;
+                s.days += 1;
+            }
+        }
+        for val in self.tree(Tree::Expenses)?.iter().values() {
+            let exp = Expense::from_val(val?)?;
+            for key in keys(exp.corner_id, exp.date)? {
+                add_sum(&mut sums.entry(key).or_default().expenses, exp.amount, 1)?;
+            }
+        }
+
+        let tree = self.tree(Tree::Stats)?;
+        let mut batch = sled::Batch::default();
+        for key in tree.iter().keys() {
+            batch.remove(key?);
+        }
+        for (key, s) in &sums {
+            batch.insert(&key[..], s.to_val()?);
+        }
+        tree.apply_batch(batch)?;
+        tree.flush()?;
+        Ok(sums.len())
+    }
+}
+
+/// Точка и все группы над ней. Читается в той же транзакции, что и
+/// сводки, чтобы перенос точки между группами не разошелся с ними.
+pub(super) fn corner_nodes(
+    corners: &TransactionalTree,
+    groups: &TransactionalTree,
+    corner_id: u32,
+) -> ConflictableTransactionResult<Vec<Node>, Error> {
+    let group_id = match corners.get(Corner::key(corner_id))? {
+        Some(val) => Corner::from_val(val).or_else(abort)?.group_id,
+        None => None,
+    };
+    group_nodes(groups, Node::Corner(corner_id), group_id)
+}
+
+/// `first` и группа `group_id` со всеми предками, как `DataBase::group_path`
+pub(super) fn group_nodes(
+    groups: &TransactionalTree,
+    first: Node,
+    group_id: Option<u32>,
+) -> ConflictableTransactionResult<Vec<Node>, Error> {
+    let mut nodes = vec![first];
+    let mut next = group_id;
+    while let Some(id) = next {
+        if nodes.contains(&Node::Group(id)) {
+            return abort(Error::Corrupt(format!("group {} is its own parent", id)));
+        }
+        nodes.push(Node::Group(id));
+        next = match groups.get(Group::key(id))? {
+            Some(val) => Group::from_val(val).or_else(abort)?.parent_id,
+            None => None,
+        };
+    }
+    Ok(nodes)
+}
+
+/// Меняет сводки узлов за месяц внутри транзакции, опустевшие удаляет
+pub(super) fn update_stats<F>(
+    stats: &TransactionalTree,
+    nodes: &[Node],
+    month: u32,
+    f: F,
+) -> ConflictableTransactionResult<(), Error>
+where
+    F: Fn(&mut Stats) -> Result<()>,
+{
+    for node in nodes {
+        let key = node.key(month);
+        let mut s = match stats.get(key)? {
+            Some(val) => Stats::from_val(val).or_else(abort)?,
+            None => Stats::default(),
+        };
+        f(&mut s).or_else(abort)?;
+        if s == Stats::default() {
+            stats.remove(&key)?;
+        } else {
+            stats.insert(&key, s.to_val().or_else(abort)?)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{CornerInfo, UNSPECIFIED};
+
+    fn rev(corner_id: u32, date: NaiveDate, amount: i64) -> Revenue {
+        Revenue {
+            corner_id,
+            date: Revenue::day(date).unwrap(),
+            amount: Money::rubles(amount),
+            post_datetime: 0,
+            comment: None,
+            payments: Vec::new(),
+            photo: None,
+            staff: None,
+        }
+    }
+
+    #[test]
+    fn rollups() {
+        let db = DataBase::temporary();
+        let region = db.add_group("Регион", None).unwrap();
+        let city = db.add_group("Город", Some(region.id)).unwrap();
+        let corner = |name: &str| {
+            db.add_corner(CornerInfo {
+                name: name.to_owned(),
+                ..Default::default()
+            })
+            .unwrap()
+            .id
+        };
+        let (a, b) = (corner("А"), corner("Б"));
+        db.set_corner_group(a, Some(city.id)).unwrap();
+
+        let march = NaiveDate::from_ymd(2020, 3, 1);
+        let month = Stats::month(Revenue::day(march).unwrap()).unwrap();
+        assert_eq!(Stats::month_start(month).unwrap(), march);
+        db.put_revenue(&rev(a, march, 100)).unwrap();
+        db.put_revenue(&rev(a, march.succ(), 50)).unwrap();
+        assert!(db.put_revenue(&rev(a, march, 70)).unwrap().is_some());
+        db.put_revenue(&rev(b, march, 1000)).unwrap();
+        db.put_revenue(&rev(a, NaiveDate::from_ymd(2020, 4, 1), 5))
+            .unwrap();
+
+        let s = |revenue, days| Stats {
+            revenue: Money::rubles(revenue),
+            days,
+            expenses: Money::ZERO,
+            by_method: vec![(UNSPECIFIED, Money::rubles(revenue))]
+                .into_iter()
+                .collect(),
+        };
+        assert_eq!(db.stats(Node::Corner(a), month).unwrap(), s(120, 2));
+        assert_eq!(db.stats(Node::Group(city.id), month).unwrap(), s(120, 2));
+        assert_eq!(db.stats(Node::Group(region.id), month).unwrap(), s(120, 2));
+        assert_eq!(
+            db.stats_range(Node::Group(region.id), month, month + 1)
+                .unwrap(),
+            vec![(month, s(120, 2)), (month + 1, s(5, 1))]
+        );
+
+        // перенос точки пересчитывает группы
+        db.set_corner_group(b, Some(city.id)).unwrap();
+        assert_eq!(db.stats(Node::Group(region.id), month).unwrap(), s(1120, 3));
+        db.set_corner_group(a, None).unwrap();
+        assert_eq!(db.stats(Node::Group(city.id), month).unwrap(), s(1000, 1));
+        assert_eq!(db.stats(Node::Corner(a), month).unwrap(), s(120, 2));
+
+        // перенос под другую ветку трогает только разошедшиеся группы
+        let other = db.add_group("Другой", Some(region.id)).unwrap();
+        db.set_corner_group(b, Some(other.id)).unwrap();
+        assert_eq!(
+            db.stats(Node::Group(city.id), month).unwrap(),
+            Stats::default()
+        );
+        assert_eq!(db.stats(Node::Group(other.id), month).unwrap(), s(1000, 1));
+        assert_eq!(db.stats(Node::Group(region.id), month).unwrap(), s(1000, 1));
+
+        // перенос группы уносит сводки ее точек
+        db.move_group(other.id, None).unwrap();
+        assert_eq!(
+            db.stats(Node::Group(region.id), month).unwrap(),
+            Stats::default()
+        );
+        assert_eq!(db.stats(Node::Group(other.id), month).unwrap(), s(1000, 1));
+        db.move_group(other.id, Some(city.id)).unwrap();
+        assert_eq!(db.stats(Node::Group(city.id), month).unwrap(), s(1000, 1));
+        assert_eq!(db.stats(Node::Group(region.id), month).unwrap(), s(1000, 1));
+        let before = db.stats_range(Node::Group(region.id), 0, u32::MAX).unwrap();
+        db.rebuild_stats().unwrap();
+        assert_eq!(
+            db.stats_range(Node::Group(region.id), 0, u32::MAX).unwrap(),
+            before
+        );
+    }
+
+    #[test]
+    fn overflow_rejected() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap()
+            .id;
+        let march = NaiveDate::from_ymd(2020, 3, 1);
+        let huge = 50_000_000_000_000_000;
+        db.put_revenue(&rev(corner, march, huge)).unwrap();
+        match db.put_revenue(&rev(corner, march.succ(), huge)) {
+            Err(Error::Invalid(_)) => {}
+            res => panic!("{:?}", res),
+        }
+        // транзакция откатилась целиком
+        let day = Revenue::day(march.succ()).unwrap();
+        assert_eq!(db.get_revenue(day, corner).unwrap(), None);
+        let month = Stats::month(day).unwrap();
+        let s = db.stats(Node::Corner(corner), month).unwrap();
+        assert_eq!((s.revenue, s.days), (Money::rubles(huge), 1));
+    }
+}
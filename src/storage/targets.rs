@@ -0,0 +1,205 @@
+//! Планы выручки. Каждое изменение плана - новая версия, действующая
+//! с указанного дня, поэтому план прошедших дней не меняется.
+//!
+//! Ключ: id точки и день начала действия, оба big-endian.
+
+use super::{BinVals, DataBase, Error, Result, Revenue, Stats, Target, TargetPeriod, Tree};
+use crate::money::Money;
+
+/// План и факт точки за месяц на указанный день
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlanFact {
+    pub day_actual: Money,
+    pub day_plan: Money,
+    pub month_plan: Money,
+    /// План с начала месяца по день включительно
+    pub plan_to_date: Money,
+    /// Факт с начала месяца по день включительно
+    pub actual: Money,
+    /// Выручка за месяц, если продавать так же, как с его начала
+    pub forecast: Money,
+}
+
+impl PlanFact {
+    /// Выполнение плана на дату, в процентах
+    pub fn percent(&self) -> u64 {
+        percent(self.actual, self.plan_to_date)
+    }
+
+    /// Прогноз относительно плана на месяц, в процентах
+    pub fn forecast_percent(&self) -> u64 {
+        percent(self.forecast, self.month_plan)
+    }
+}
+
+fn percent(part: Money, whole: Money) -> u64 {
+    let (part, whole) = (part.kopecks() as i128, whole.kopecks() as i128);
+    (part * 100 + whole / 2).checked_div(whole).unwrap_or(0) as u64
+}
+
+impl Target {
+    fn key(corner_id: u32, from: u32) -> [u8; 8] {
+        let mut key = [0u8; 8];
+        key[..4].copy_from_slice(&corner_id.to_be_bytes());
+        key[4..].copy_from_slice(&from.to_be_bytes());
+        key
+    }
+
+    /// План на день в копейках. Месячный план делится поровну
+    /// между `days` днями месяца.
+    fn for_day(&self, days: u32) -> f64 {
+        let kopecks = self.amount.kopecks() as f64;
+        match self.period {
+            TargetPeriod::Day => kopecks,
+            TargetPeriod::Month => kopecks / days as f64,
+        }
+    }
+}
+
+impl DataBase {
+    /// Добавляет версию плана, действующую с дня `from`.
+    /// Версия с тем же днем начала заменяется.
+    pub fn set_target(
+        &self,
+        corner_id: u32,
+        from: u32,
+        period: TargetPeriod,
+        amount: Money,
+    ) -> Result<Target> {
+        self.get_corner(corner_id)?.ok_or(Error::NotFound)?;
+        let target = Target {
+            corner_id,
+            from,
+            period,
+            amount,
+            set_at: chrono::Utc::now().timestamp() as u32,
+        };
+        self.tree(Tree::Targets)?
+            .insert(Target::key(corner_id, from), target.to_val()?)?;
+        Ok(target)
+    }
+
+    /// Все версии плана точки по порядку
+    pub fn target_history(&self, corner_id: u32) -> Result<Vec<Target>> {
+        self.tree(Tree::Targets)?
+            .scan_prefix(corner_id.to_be_bytes())
+            .values()
+            .map(|val| Target::from_val(val?))
+            .collect()
+    }
+
+    /// План и факт за месяц, в который входит `day`, по этот день.
+    /// `None`, если в этом месяце для точки не было плана.
+    pub fn plan_fact(&self, corner_id: u32, day: u32) -> Result<Option<PlanFact>> {
+        let month = Stats::month(day)?;
+        let start = Stats::first_day(month)?;
+        let days = Stats::first_day(month + 1)? - start;
+        let versions = self.target_history(corner_id)?;
+        let mut plan = vec![0f64; days as usize];
+        let mut any = false;
+        for (i, d) in (start..start + days).enumerate() {
+            if let Some(target) = versions.iter().rev().find(|t| t.from <= d) {
+                plan[i] = target.for_day(days);
+                any = true;
+            }
+        }
+        if !any {
+            return Ok(None);
+        }
+
+        let elapsed = (day - start + 1) as usize;
+        let mut day_actual = Money::ZERO;
+        let mut actual = Money::ZERO;
+        let revenues = self.tree(Tree::Revenues)?;
+        for val in revenues
+            .range(Revenue::key(start, 0)..=Revenue::key(day, u32::MAX))
+            .values()
+        {
+            let rev = Revenue::from_val(val?)?;
+            if rev.corner_id == corner_id {
+                actual += rev.amount;
+                if rev.date == day {
+                    day_actual = rev.amount;
+                }
+            }
+        }
+        let kopecks = |plan: f64| Money::from_kopecks(plan.round() as i64);
+        let forecast = actual.kopecks() as i128 * days as i128 / elapsed as i128;
+        Ok(Some(PlanFact {
+            day_actual,
+            day_plan: kopecks(plan[elapsed - 1]),
+            month_plan: kopecks(plan.iter().sum()),
+            plan_to_date: kopecks(plan[..elapsed].iter().sum()),
+            actual,
+            forecast: Money::from_kopecks(forecast as i64),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::CornerInfo;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn versioned_plan() {
+        let db = DataBase::temporary();
+        let corner = db
+            .add_corner(CornerInfo {
+                name: "Галерея".to_owned(),
+                ..Default::default()
+            })
+            .unwrap()
+            .id;
+        let day = |d| Revenue::day(NaiveDate::from_ymd(2020, 4, d)).unwrap();
+        assert_eq!(db.plan_fact(corner, day(10)).unwrap(), None);
+
+        db.set_target(corner, day(1), TargetPeriod::Month, Money::rubles(30_000))
+            .unwrap();
+        for d in 1..=10 {
+            db.put_revenue(&Revenue {
+                corner_id: corner,
+                date: day(d),
+                amount: Money::rubles(1_500),
+                post_datetime: 0,
+                comment: None,
+                payments: Vec::new(),
+                photo: None,
+                staff: None,
+            })
+            .unwrap();
+        }
+        let pf = db.plan_fact(corner, day(10)).unwrap().unwrap();
+        let rubles = |a, b, c| (Money::rubles(a), Money::rubles(b), Money::rubles(c));
+        assert_eq!(
+            (pf.month_plan, pf.plan_to_date, pf.day_plan),
+            rubles(30_000, 10_000, 1_000)
+        );
+        assert_eq!(
+            (pf.actual, pf.day_actual, pf.forecast),
+            rubles(15_000, 1_500, 45_000)
+        );
+        assert_eq!((pf.percent(), pf.forecast_percent()), (150, 150));
+
+        // новый план с 21 числа не трогает первые 20 дней
+        db.set_target(corner, day(21), TargetPeriod::Day, Money::rubles(2_000))
+            .unwrap();
+        let pf = db.plan_fact(corner, day(10)).unwrap().unwrap();
+        assert_eq!(
+            (pf.month_plan, pf.plan_to_date),
+            (Money::rubles(40_000), Money::rubles(10_000))
+        );
+        let history = db.target_history(corner).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|t| (t.from, t.amount))
+                .collect::<Vec<_>>(),
+            vec![
+                (day(1), Money::rubles(30_000)),
+                (day(21), Money::rubles(2_000))
+            ]
+        );
+    }
+}